@@ -0,0 +1,122 @@
+//! Throughput and CPU cost of the copy loop vs the splice fast path.
+//!
+//! Run with `cargo bench --bench forward`. Each case pushes
+//! `TCPAO_BENCH_BYTES` (default 1 GiB) through loopback TCP:
+//! writer -> proxy -> reader. CPU is process user+sys time from getrusage, so
+//! the writer and reader threads are included; they do the same work in every
+//! case, so the difference between rows is the proxy's cost.
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tcpao_proxy::forward::{forward, PumpOptions, Traffic};
+
+const DEFAULT_BYTES: u64 = 1 << 30;
+const WRITE_CHUNK: usize = 256 * 1024;
+
+fn main() {
+    let total = std::env::var("TCPAO_BENCH_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_BYTES);
+
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(2)
+        .enable_all()
+        .build()
+        .expect("tokio runtime");
+
+    println!(
+        "{:<10} {:>12} {:>12} {:>14}",
+        "mode", "MiB/s", "cpu_s", "cpu_s_per_GiB"
+    );
+    for (name, zero_copy) in [("copy", false), ("splice", true)] {
+        let (elapsed, cpu) = run_case(&rt, total, zero_copy);
+        let mib = total as f64 / (1024.0 * 1024.0);
+        let gib = mib / 1024.0;
+        println!(
+            "{:<10} {:>12.1} {:>12.3} {:>14.3}",
+            name,
+            mib / elapsed.as_secs_f64(),
+            cpu.as_secs_f64(),
+            cpu.as_secs_f64() / gib
+        );
+    }
+}
+
+fn run_case(rt: &tokio::runtime::Runtime, total: u64, zero_copy: bool) -> (Duration, Duration) {
+    let (client, proxy_in) = tcp_pair();
+    let (mut sink, proxy_out) = tcp_pair();
+
+    let proxy = rt.spawn(async move {
+        proxy_in.set_nonblocking(true).expect("nonblocking");
+        proxy_out.set_nonblocking(true).expect("nonblocking");
+        let source = tokio::net::TcpStream::from_std(proxy_in).expect("tokio source");
+        let destination = tokio::net::TcpStream::from_std(proxy_out).expect("tokio destination");
+        forward(
+            source,
+            destination,
+            PumpOptions {
+                zero_copy,
+                ..PumpOptions::default()
+            },
+            &Traffic::default(),
+        )
+        .await
+    });
+
+    let cpu_before = cpu_time();
+    let started = Instant::now();
+
+    let writer = thread::spawn(move || {
+        let mut client = client;
+        let chunk = vec![0x5A_u8; WRITE_CHUNK];
+        let mut left = total;
+        while left > 0 {
+            let n = left.min(WRITE_CHUNK as u64) as usize;
+            client.write_all(&chunk[..n]).expect("bench write");
+            left -= n as u64;
+        }
+        client
+            .shutdown(std::net::Shutdown::Write)
+            .expect("bench shutdown");
+    });
+
+    let mut buf = vec![0_u8; WRITE_CHUNK];
+    let mut received = 0_u64;
+    loop {
+        let n = sink.read(&mut buf).expect("bench read");
+        if n == 0 {
+            break;
+        }
+        received += n as u64;
+    }
+    sink.shutdown(std::net::Shutdown::Write)
+        .expect("bench shutdown");
+
+    writer.join().expect("writer thread");
+    let elapsed = started.elapsed();
+    let cpu = cpu_time().saturating_sub(cpu_before);
+
+    let stats = rt.block_on(proxy).expect("proxy task");
+    assert_eq!(received, total);
+    assert_eq!(stats.bytes_up, total);
+
+    (elapsed, cpu)
+}
+
+fn tcp_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bench listener");
+    let client = TcpStream::connect(listener.local_addr().expect("addr")).expect("connect");
+    let (accepted, _) = listener.accept().expect("accept");
+    (client, accepted)
+}
+
+fn cpu_time() -> Duration {
+    let mut usage: libc::rusage = unsafe { std::mem::zeroed() };
+    unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) };
+    let tv = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    tv(usage.ru_utime) + tv(usage.ru_stime)
+}
@@ -1,10 +1,22 @@
-use std::path::PathBuf;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
 
-use clap::{Parser, ValueEnum};
-use tcpao_proxy::config::{Config, LogFormat, Mode};
-use tcpao_proxy::error::Result;
-use tracing::{error, info};
-use tracing_subscriber::EnvFilter;
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use serde_json::Value;
+use tcpao_proxy::admin;
+use tcpao_proxy::config::{AoPolicyConfig, Config, KeySource, LogFormat, Mode, TelemetryConfig};
+use tcpao_proxy::error::{ProxyError, Result};
+use tcpao_proxy::keytool::{self, KeyEncoding, Vendor};
+use tcpao_proxy::plain::PlainEndpoint;
+use tcpao_proxy::tcpao::policy::PolicySet;
+use tcpao_proxy::telemetry::Telemetry;
+use tracing::{error, info, warn};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
 
 #[derive(Debug, Clone, Copy, ValueEnum)]
 enum ModeArg {
@@ -39,7 +51,156 @@ impl From<LogFormatArg> for LogFormat {
 #[derive(Debug, Parser)]
 #[command(name = "tcpao-proxy")]
 #[command(about = "BMP TCP-AO sidecar proxy (PoC scaffold)")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Without a subcommand the legacy flags behave like `run`.
+    #[command(flatten)]
+    legacy: LegacyArgs,
+}
+
+#[derive(Debug, Args)]
+struct LegacyArgs {
+    #[arg(long, value_enum)]
+    mode: Option<ModeArg>,
+
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[arg(long, value_enum)]
+    log_format: Option<LogFormatArg>,
+
+    #[arg(long)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the proxy in the foreground.
+    Run {
+        #[command(flatten)]
+        args: ServeArgs,
+
+        /// Load and validate everything, then exit.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Validate the config and key sources for a mode, then exit.
+    Check(ServeArgs),
+    /// Show mode, uptime and connection counters of a running proxy.
+    Status(AdminArgs),
+    /// List live sessions of a running proxy.
+    Sessions(AdminArgs),
+    /// Close one session of a running proxy.
+    Kill {
+        conn_id: u64,
+
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
+    /// Make a running proxy re-read its key sources.
+    Reload(AdminArgs),
+    /// Show the policies and key fingerprints a running proxy uses.
+    Keys(AdminArgs),
+    /// Exit 0 when a running proxy is ready, for container health checks.
+    Probe {
+        /// Only check that the proxy answers, not that it is ready.
+        #[arg(long)]
+        live: bool,
+
+        #[command(flatten)]
+        admin: AdminArgs,
+    },
+    /// Generate a random AO master key for a mac_alg.
+    Keygen {
+        #[arg(long, default_value = "hmac-sha256")]
+        mac_alg: String,
+
+        /// Random bytes instead of an alphanumeric key; needs --out and
+        /// cannot be entered on routers.
+        #[arg(long, requires = "out")]
+        raw: bool,
+
+        /// Write the key to a new file (mode 0600) instead of stdout.
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Print the fingerprint the proxy logs for a key.
+    Fingerprint {
+        /// Any key_source, e.g. `env:TCPAO_KEY` or `file:/etc/tcpao/key`;
+        /// `vault:` sources also need --config for its `[vault]` section.
+        #[arg(long, conflicts_with = "policy")]
+        key_source: Option<String>,
+
+        #[command(flatten)]
+        policies: PolicyArgs,
+    },
+    /// Print router config for a policy's key id, algorithm and key.
+    Render {
+        #[arg(long, value_enum)]
+        vendor: VendorArg,
+
+        #[command(flatten)]
+        policies: PolicyArgs,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum VendorArg {
+    Junos,
+    IosXr,
+    Eos,
+    Frr,
+}
+
+impl From<VendorArg> for Vendor {
+    fn from(value: VendorArg) -> Self {
+        match value {
+            VendorArg::Junos => Vendor::Junos,
+            VendorArg::IosXr => Vendor::IosXr,
+            VendorArg::Eos => Vendor::Eos,
+            VendorArg::Frr => Vendor::Frr,
+        }
+    }
+}
+
+/// Policies taken from a config file; all of them unless one is named.
+#[derive(Debug, Args)]
+struct PolicyArgs {
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[arg(long, requires = "config")]
+    policy: Option<String>,
+}
+
+impl PolicyArgs {
+    fn load(&self) -> Result<Vec<AoPolicyConfig>> {
+        let Some(path) = &self.config else {
+            return Err(ProxyError::Config("pass --config".to_string()));
+        };
+        let config = Config::load(path)?;
+        install_vault(&config)?;
+
+        let policies: Vec<_> = config
+            .ao_policy
+            .into_iter()
+            .filter(|p| self.policy.as_ref().is_none_or(|name| &p.name == name))
+            .collect();
+        if policies.is_empty() {
+            return Err(ProxyError::Config(match &self.policy {
+                Some(name) => format!("no ao_policy named '{name}' in {}", path.display()),
+                None => format!("{} has no ao_policy entries", path.display()),
+            }));
+        }
+        Ok(policies)
+    }
+}
+
+#[derive(Debug, Args)]
+struct ServeArgs {
     #[arg(long, value_enum)]
     mode: ModeArg,
 
@@ -48,14 +209,61 @@ struct Cli {
 
     #[arg(long, value_enum)]
     log_format: Option<LogFormatArg>,
+}
+
+/// How the client subcommands find the admin API.
+#[derive(Debug, Args)]
+struct AdminArgs {
+    /// Admin endpoint, `unix:/path` or a loopback `host:port`.
+    #[arg(long, env = "TCPAO_PROXY_ADMIN", conflicts_with = "config")]
+    admin: Option<String>,
 
+    /// Read the endpoint from `[admin] listen` in this config instead.
     #[arg(long)]
-    dry_run: bool,
+    config: Option<PathBuf>,
+
+    /// Print the JSON response instead of a table.
+    #[arg(long)]
+    json: bool,
 }
 
-#[tokio::main]
-async fn main() {
-    if let Err(err) = run().await {
+impl AdminArgs {
+    fn endpoint(&self) -> Result<PlainEndpoint> {
+        if let Some(admin) = &self.admin {
+            return PlainEndpoint::parse(admin);
+        }
+        let Some(path) = &self.config else {
+            return Err(ProxyError::Config(
+                "pass --admin or --config to locate the admin api".to_string(),
+            ));
+        };
+        Config::load(path)?
+            .admin
+            .ok_or_else(|| {
+                ProxyError::Config(format!("{} has no [admin] section", path.display()))
+            })?
+            .listen_endpoint()
+    }
+}
+
+fn main() {
+    // Before the runtime starts its threads, so removing the variables does
+    // not race with anything reading the environment.
+    tcpao_proxy::systemd::take_environment();
+    let runtime = match tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(err) => {
+            eprintln!("error: failed to start the async runtime: {err}");
+            std::process::exit(1);
+        }
+    };
+    let result = runtime.block_on(run());
+    // Writes out audit records still queued for the sink.
+    let _ = tcpao_proxy::audit::install(None);
+    if let Err(err) = result {
         error!(error = %err, "proxy exited with error");
         eprintln!("error: {err}");
         std::process::exit(1);
@@ -64,40 +272,372 @@ async fn main() {
 
 async fn run() -> Result<()> {
     let cli = Cli::parse();
-    let mode: Mode = cli.mode.into();
+    let command = match cli.command {
+        Some(command) => command,
+        None => legacy_command(cli.legacy),
+    };
+
+    match command {
+        Command::Run { args, dry_run } => serve(args, dry_run).await,
+        Command::Check(args) => serve(args, true).await,
+        Command::Status(admin) => {
+            let body = admin::call(&admin.endpoint()?, "GET", "/status").await?;
+            print_json_or(&body, admin.json, print_status);
+            Ok(())
+        }
+        Command::Sessions(admin) => {
+            let body = admin::call(&admin.endpoint()?, "GET", "/sessions").await?;
+            print_json_or(&body, admin.json, print_sessions);
+            Ok(())
+        }
+        Command::Kill { conn_id, admin } => {
+            let path = format!("/sessions/{conn_id}/kill");
+            let body = admin::call(&admin.endpoint()?, "POST", &path).await?;
+            print_json_or(&body, admin.json, |_| println!("killed session {conn_id}"));
+            Ok(())
+        }
+        Command::Reload(admin) => {
+            let body = admin::call(&admin.endpoint()?, "POST", "/reload").await?;
+            print_json_or(&body, admin.json, |_| println!("reload requested"));
+            Ok(())
+        }
+        Command::Keys(admin) => {
+            let body = admin::call(&admin.endpoint()?, "GET", "/policies").await?;
+            print_json_or(&body, admin.json, print_keys);
+            Ok(())
+        }
+        Command::Probe { live, admin } => {
+            let path = if live { "/healthz" } else { "/readyz" };
+            let body = admin::call(&admin.endpoint()?, "GET", path).await?;
+            print_json_or(&body, admin.json, |body| {
+                if live {
+                    println!("ok");
+                } else {
+                    print_checks(body);
+                }
+            });
+            Ok(())
+        }
+        Command::Keygen { mac_alg, raw, out } => keygen(&mac_alg, raw, out.as_deref()),
+        Command::Fingerprint {
+            key_source: Some(source),
+            policies,
+        } => {
+            if let Some(path) = &policies.config {
+                install_vault(&Config::load(path)?)?;
+            }
+            println!("{}", KeySource(source).load_key()?.fingerprint());
+            Ok(())
+        }
+        Command::Fingerprint { policies, .. } => {
+            for policy in policies.load()? {
+                let key = policy.key_source.load_key()?;
+                println!("{}  {}", policy.name, key.fingerprint());
+            }
+            Ok(())
+        }
+        Command::Render { vendor, policies } => {
+            for policy in policies.load()? {
+                let key = policy.key_source.load_key()?;
+                print!("{}", keytool::render(vendor.into(), &policy, &key)?);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Sets up the `[vault]` client the way `run` does, so `vault:` sources
+/// resolve outside the daemon.
+fn install_vault(config: &Config) -> Result<()> {
+    if let Some(vault) = &config.vault {
+        vault.validate()?;
+    }
+    tcpao_proxy::vault::install(config.vault.as_ref())
+}
 
-    let config = Config::load(&cli.config)?;
-    init_tracing(
-        cli.log_format
+fn keygen(mac_alg: &str, raw: bool, out: Option<&Path>) -> Result<()> {
+    let encoding = if raw {
+        KeyEncoding::Raw
+    } else {
+        KeyEncoding::Text
+    };
+    let key = keytool::generate(mac_alg, encoding)?;
+
+    match out {
+        Some(path) => {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)?;
+            file.write_all(key.as_bytes())?;
+            eprintln!("key_source = \"file:{}\"", path.display());
+        }
+        None => println!("{}", String::from_utf8_lossy(key.as_bytes())),
+    }
+    eprintln!("fingerprint {}", key.fingerprint());
+    Ok(())
+}
+
+fn legacy_command(legacy: LegacyArgs) -> Command {
+    let (Some(mode), Some(config)) = (legacy.mode, legacy.config) else {
+        Cli::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "a subcommand or both --mode and --config are required",
+            )
+            .exit()
+    };
+    Command::Run {
+        args: ServeArgs {
+            mode,
+            config,
+            log_format: legacy.log_format,
+        },
+        dry_run: legacy.dry_run,
+    }
+}
+
+async fn serve(args: ServeArgs, dry_run: bool) -> Result<()> {
+    let mode: Mode = args.mode.into();
+
+    // Before any key material is loaded.
+    let dumpable_err = tcpao_proxy::secret::disable_core_dumps().err();
+
+    let config = Config::load(&args.config)?;
+    // Exporting spans only makes sense for a proxy that keeps running.
+    let telemetry = init_tracing(
+        args.log_format
             .map(Into::into)
             .unwrap_or(config.global.log_format),
-    );
+        config.telemetry.as_ref().filter(|_| !dry_run),
+        mode,
+    )?;
+    if let Some(err) = dumpable_err {
+        warn!(error = %err, "failed to disable core dumps; key material may be dumped");
+    }
     config.validate(mode)?;
 
-    info!(mode = ?mode, config = %cli.config.display(), summary = %config.redacted_summary(), "config loaded");
+    info!(mode = ?mode, config = %args.config.display(), summary = %config.redacted_summary(), "config loaded");
+    for policy in config.relaxed_policies() {
+        warn!(
+            policy = %policy.name,
+            enforcement = policy.enforcement.as_str(),
+            "tcp-ao enforcement relaxed for policy; sessions without AO will be admitted and audited"
+        );
+    }
 
-    if cli.dry_run {
-        info!("dry-run successful");
+    if dry_run {
+        // Load every key the way `run` does, so a missing key or an
+        // unreachable Vault fails here rather than at startup.
+        tcpao_proxy::vault::install(config.vault.as_ref())?;
+        let policies = PolicySet::compile_in_background(&config.ao_policy).await?;
+        info!(keys = policies.len(), "dry-run successful");
         return Ok(());
     }
 
-    match mode {
-        Mode::Initiator => tcpao_proxy::mode_initiator::run(config).await,
-        Mode::Terminator => tcpao_proxy::mode_terminator::run(config).await,
+    let serve = async {
+        match mode {
+            Mode::Initiator => tcpao_proxy::mode_initiator::run(config).await,
+            Mode::Terminator => tcpao_proxy::mode_terminator::run(config).await,
+        }
+    };
+
+    // Dropping the mode future closes its listeners, which also removes any
+    // unix socket files they created.
+    let result = tokio::select! {
+        result = serve => result,
+        signal = shutdown_signal() => {
+            info!(signal, "shutting down");
+            tcpao_proxy::systemd::stopping();
+            Ok(())
+        }
+    };
+    if let Some(telemetry) = telemetry {
+        telemetry.shutdown().await;
     }
+    result
 }
 
-fn init_tracing(log_format: LogFormat) {
-    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+fn print_json_or(body: &Value, json: bool, table: impl FnOnce(&Value)) {
+    if json {
+        println!("{body:#}");
+    } else {
+        table(body);
+    }
+}
 
-    let builder = tracing_subscriber::fmt().with_env_filter(env_filter);
+fn print_status(body: &Value) {
+    let connections = &body["connections"];
+    println!("mode:         {}", text(&body["mode"]));
+    println!("uptime:       {}s", text(&body["uptime_secs"]));
+    println!("sessions:     {}", text(&body["sessions"]));
+    for counter in ["open", "closed", "rejected", "evicted", "unprotected"] {
+        println!(
+            "{:<13} {}",
+            format!("{counter}:"),
+            text(&connections[counter])
+        );
+    }
+}
 
-    match log_format {
-        LogFormat::Text => {
-            builder.compact().init();
+fn print_checks(body: &Value) {
+    let rows = rows(&body["checks"], |c| {
+        vec![
+            text(&c["name"]),
+            if c["ok"] == true { "ok" } else { "failed" }.to_string(),
+            text(&c["error"]),
+        ]
+    });
+    print_table(&["CHECK", "STATUS", "ERROR"], &rows);
+}
+
+fn print_sessions(body: &Value) {
+    let rows = rows(body, |s| {
+        let ao = s["ao_keys"]
+            .as_array()
+            .and_then(|keys| keys.iter().find(|key| key["current"] == true))
+            .map(|key| format!("{}/{}", text(&key["pkt_good"]), text(&key["pkt_bad"])))
+            .unwrap_or_else(|| "-".to_string());
+        vec![
+            text(&s["conn_id"]),
+            text(&s["peer"]),
+            text(&s["policy"]),
+            text(&s["keyid"]),
+            text(&s["rnextkeyid"]),
+            text(&s["protected"]),
+            text(&s["bytes_up"]),
+            text(&s["bytes_down"]),
+            format!("{}s", text(&s["age_secs"])),
+            ao,
+        ]
+    });
+    print_table(
+        &[
+            "CONN_ID",
+            "PEER",
+            "POLICY",
+            "KEYID",
+            "RNEXT",
+            "AO",
+            "BYTES_UP",
+            "BYTES_DOWN",
+            "AGE",
+            "PKT_GOOD/BAD",
+        ],
+        &rows,
+    );
+}
+
+fn print_keys(body: &Value) {
+    let rows = rows(body, |p| {
+        vec![
+            text(&p["name"]),
+            text(&p["peer_ip"]),
+            text(&p["keyid"]),
+            text(&p["rnextkeyid"]),
+            text(&p["mac_alg"]),
+            text(&p["enforcement"]),
+            text(&p["key_source"]),
+            text(&p["key_fingerprint"]),
+        ]
+    });
+    print_table(
+        &[
+            "POLICY",
+            "PEER",
+            "KEYID",
+            "RNEXT",
+            "MAC_ALG",
+            "ENFORCEMENT",
+            "SOURCE",
+            "FINGERPRINT",
+        ],
+        &rows,
+    );
+}
+
+fn rows(body: &Value, row: impl Fn(&Value) -> Vec<String>) -> Vec<Vec<String>> {
+    body.as_array()
+        .map(|items| items.iter().map(row).collect())
+        .unwrap_or_default()
+}
+
+fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let mut widths: Vec<usize> = header.iter().map(|h| h.len()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
         }
-        LogFormat::Json => {
-            builder.json().init();
+    }
+
+    let line = |cells: Vec<&str>| {
+        let padded: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", padded.join("  ").trim_end());
+    };
+    line(header.to_vec());
+    for row in rows {
+        line(row.iter().map(String::as_str).collect());
+    }
+}
+
+/// Strings without quotes, `-` for null.
+fn text(value: &Value) -> String {
+    match value {
+        Value::Null => "-".to_string(),
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut term = match signal(SignalKind::terminate()) {
+        Ok(term) => term,
+        Err(err) => {
+            warn!(error = %err, "failed to install SIGTERM handler");
+            let _ = tokio::signal::ctrl_c().await;
+            return "SIGINT";
         }
+    };
+
+    tokio::select! {
+        _ = term.recv() => "SIGTERM",
+        _ = tokio::signal::ctrl_c() => "SIGINT",
+    }
+}
+
+fn init_tracing(
+    log_format: LogFormat,
+    telemetry: Option<&TelemetryConfig>,
+    mode: Mode,
+) -> Result<Option<Telemetry>> {
+    let env_filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let fmt = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().compact().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(env_filter).with(fmt);
+
+    #[cfg(feature = "otel")]
+    if let Some(cfg) = telemetry {
+        let (telemetry, layer) = tcpao_proxy::telemetry::init(cfg, mode.as_str())?;
+        registry.with(layer).init();
+        return Ok(Some(telemetry));
+    }
+
+    registry.init();
+    if telemetry.is_some() {
+        warn!(
+            mode = mode.as_str(),
+            "built without the otel feature; [telemetry] is ignored"
+        );
     }
+    Ok(None)
 }
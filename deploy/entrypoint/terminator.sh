@@ -68,6 +68,8 @@ required_env TCPAO_KEY
 required_env PEER_IP
 
 mkdir -p "$(dirname "$CONFIG_PATH")"
+# Admin socket used by the image health check (tcpao-proxy probe).
+mkdir -p /run/tcpao-proxy
 if [[ ! -f "$TEMPLATE_PATH" ]]; then
   echo "[entrypoint] template not found: $TEMPLATE_PATH" >&2
   exit 1
@@ -0,0 +1,187 @@
+//! Client access control for the initiator's plain listener.
+//!
+//! TCP clients are matched against `allowed_clients` CIDRs. Unix socket
+//! clients are matched on the uid/gid the kernel reports through
+//! `SO_PEERCRED`. An empty list allows every client for that check.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::error::{ProxyError, Result};
+use crate::session::Peer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => mask_eq(&net.octets(), &ip.octets(), self.prefix),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = ProxyError;
+
+    fn from_str(raw: &str) -> Result<Self> {
+        let invalid = || ProxyError::Config(format!("invalid CIDR '{raw}'"));
+        let (addr, prefix) = match raw.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (raw, None),
+        };
+
+        let network: IpAddr = addr.trim().parse().map_err(|_| invalid())?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.trim().parse::<u8>().map_err(|_| invalid())?,
+            None => max,
+        };
+        if prefix > max {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            network: network.to_canonical(),
+            prefix,
+        })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+fn mask_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let full = usize::from(prefix / 8);
+    if a[..full] != b[..full] {
+        return false;
+    }
+
+    let rem = prefix % 8;
+    if rem == 0 {
+        return true;
+    }
+    let mask = 0xff_u8 << (8 - rem);
+    a[full] & mask == b[full] & mask
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientAcl {
+    networks: Vec<Cidr>,
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl ClientAcl {
+    pub fn new(networks: Vec<Cidr>, uids: Vec<u32>, gids: Vec<u32>) -> Self {
+        Self {
+            networks,
+            uids,
+            gids,
+        }
+    }
+
+    pub fn restricts_addrs(&self) -> bool {
+        !self.networks.is_empty()
+    }
+
+    pub fn restricts_credentials(&self) -> bool {
+        !self.uids.is_empty() || !self.gids.is_empty()
+    }
+
+    pub fn allows_addr(&self, ip: IpAddr) -> bool {
+        self.networks.is_empty() || self.networks.iter().any(|net| net.contains(ip))
+    }
+
+    /// A client passes when its uid or gid is listed; with only one list
+    /// configured, that list alone decides.
+    pub fn allows_credentials(&self, uid: u32, gid: u32) -> bool {
+        if !self.restricts_credentials() {
+            return true;
+        }
+
+        self.uids.contains(&uid) || self.gids.contains(&gid)
+    }
+
+    /// Returns the audit reason when `peer` is not allowed.
+    pub fn check(&self, peer: &Peer) -> std::result::Result<(), &'static str> {
+        match peer {
+            Peer::Tcp(addr) if !self.allows_addr(addr.ip()) => {
+                Err("source address not in allowed_clients")
+            }
+            Peer::Unix { uid, gid, .. } if !self.allows_credentials(*uid, *gid) => {
+                Err("peer credentials not in allowed_uids/allowed_gids")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cidr(raw: &str) -> Cidr {
+        raw.parse().expect("valid cidr")
+    }
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().expect("valid ip")
+    }
+
+    #[test]
+    fn cidr_matches_prefix() {
+        let net = cidr("10.1.0.0/20");
+        assert!(net.contains(ip("10.1.15.254")));
+        assert!(!net.contains(ip("10.1.16.1")));
+        assert!(!net.contains(ip("2001:db8::1")));
+
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(cidr("0.0.0.0/0").contains(ip("192.0.2.1")));
+        assert!(cidr("127.0.0.1").contains(ip("::ffff:127.0.0.1")));
+    }
+
+    #[test]
+    fn cidr_rejects_invalid_prefix() {
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/x".parse::<Cidr>().is_err());
+        assert!("not-an-ip/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn empty_acl_allows_everyone() {
+        let acl = ClientAcl::default();
+        assert!(acl.allows_addr(ip("203.0.113.7")));
+        assert!(acl.allows_credentials(1000, 1000));
+    }
+
+    #[test]
+    fn acl_checks_networks_and_credentials() {
+        let acl = ClientAcl::new(vec![cidr("127.0.0.0/8")], vec![0], vec![995]);
+        assert!(acl.allows_addr(ip("127.0.0.1")));
+        assert!(!acl.allows_addr(ip("10.0.0.1")));
+        assert!(acl.allows_credentials(0, 0));
+        assert!(acl.allows_credentials(1000, 995));
+        assert!(!acl.allows_credentials(1000, 1000));
+
+        assert!(acl
+            .check(&Peer::Tcp("127.0.0.1:4000".parse().expect("valid addr")))
+            .is_ok());
+        assert!(acl
+            .check(&Peer::Unix {
+                pid: Some(1),
+                uid: 1000,
+                gid: 1000
+            })
+            .is_err());
+    }
+}
@@ -0,0 +1,534 @@
+//! Opt-in local admin API.
+//!
+//! A minimal HTTP/1.1 server on a loopback address or unix socket that answers
+//! one JSON request per connection:
+//!
+//! - `GET /status`: mode, uptime and connection counters.
+//! - `GET /sessions`: live sessions with byte counts and AO key counters.
+//! - `GET /policies`: configured policies with key fingerprints; key
+//!   locations are reduced to their source type.
+//! - `GET /listeners`: what this process listens on and forwards to.
+//! - `POST /sessions/{conn_id}/kill`: closes one session.
+//! - `POST /reload`: re-reads every key source, as a key change would.
+//! - `GET /healthz`: answers while the runtime is serving requests.
+//! - `GET /readyz`: reports the [`health`](crate::health) checks; 503 with
+//!   the failed ones when any fails.
+//!
+//! [`call`] is the matching client used by the CLI subcommands.
+
+use std::io;
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::audit;
+use crate::config::AdminConfig;
+use crate::error::{ProxyError, Result};
+use crate::health::Readiness;
+use crate::http;
+use crate::metrics::Metrics;
+use crate::plain::{PlainEndpoint, PlainListener};
+use crate::session::{Session, SessionRegistry};
+use crate::tcpao::linux;
+use crate::tcpao::policy::{CompiledPolicy, PolicyStore};
+
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One socket the mode listens on, as shown by `GET /listeners`.
+#[derive(Debug, Clone)]
+pub struct Listener {
+    /// `plain` or `ao`.
+    pub role: &'static str,
+    pub address: String,
+    /// Where accepted sessions are sent.
+    pub target: String,
+}
+
+/// Everything the admin API reads from or acts on in a running mode.
+#[derive(Debug)]
+pub struct AdminState {
+    pub mode: &'static str,
+    pub started: Instant,
+    pub sessions: Arc<SessionRegistry>,
+    pub policies: Arc<PolicyStore>,
+    pub metrics: Arc<Metrics>,
+    pub listeners: Vec<Listener>,
+    /// Signalled by `POST /reload`; the mode reloads its keys when it fires.
+    pub reload: Arc<Notify>,
+    pub readiness: Readiness,
+}
+
+/// The running admin server. Dropping it stops the server and removes its
+/// unix socket.
+#[derive(Debug)]
+pub struct AdminServer {
+    task: JoinHandle<()>,
+}
+
+impl Drop for AdminServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Binds and starts the admin API when `[admin]` is configured.
+pub async fn spawn(cfg: Option<&AdminConfig>, state: AdminState) -> Result<Option<AdminServer>> {
+    let Some(cfg) = cfg else {
+        return Ok(None);
+    };
+
+    let endpoint = cfg.listen_endpoint()?;
+    let listener = PlainListener::bind(&endpoint).await?;
+    info!(listen = %endpoint, "admin api listening");
+
+    let state = Arc::new(state);
+    let task = tokio::spawn(serve(listener, endpoint, state));
+    Ok(Some(AdminServer { task }))
+}
+
+async fn serve(listener: PlainListener, endpoint: PlainEndpoint, state: Arc<AdminState>) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(listen = %endpoint, error = %err, "admin accept failed");
+                continue;
+            }
+        };
+
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(err) = handle(stream, &state).await {
+                debug!(peer = %peer, error = %err, "admin request failed");
+            }
+        });
+    }
+}
+
+async fn handle<S>(mut stream: S, state: &AdminState) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let head = tokio::time::timeout(REQUEST_TIMEOUT, read_head(&mut stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "admin request timed out"))??;
+
+    let (status, body) = match head.split_whitespace().collect::<Vec<_>>()[..] {
+        [method, target, _version, ..] => route(method, target, state).await,
+        _ => (400, json!({ "error": "malformed request line" })),
+    };
+
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        reason_phrase(status),
+        body.len(),
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads up to the end of the request headers and returns the request line.
+/// Request bodies are never needed and are ignored.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<String> {
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0_u8; 512];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_HEAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "admin request head too large",
+            ));
+        }
+        let n = stream.read(&mut buf).await?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+
+    let line = head.split(|b| *b == b'\n').next().unwrap_or_default();
+    Ok(String::from_utf8_lossy(line).trim_end().to_string())
+}
+
+async fn route(method: &str, target: &str, state: &AdminState) -> (u16, Value) {
+    let path = target.split('?').next().unwrap_or_default();
+    let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+
+    match (method, segments.as_slice()) {
+        ("GET", ["status"]) => (200, status(state)),
+        ("GET", ["healthz"]) => (
+            200,
+            json!({ "status": "ok", "mode": state.mode, "uptime_secs": state.started.elapsed().as_secs() }),
+        ),
+        ("GET", ["readyz"]) => readiness(state),
+        ("GET", ["sessions"]) => (
+            200,
+            Value::Array(
+                state
+                    .sessions
+                    .snapshot()
+                    .iter()
+                    .map(|s| session(s))
+                    .collect(),
+            ),
+        ),
+        ("GET", ["policies"]) => (
+            200,
+            Value::Array(state.policies.current().iter().map(policy).collect()),
+        ),
+        ("GET", ["listeners"]) => (
+            200,
+            Value::Array(
+                state
+                    .listeners
+                    .iter()
+                    .map(|l| json!({ "role": l.role, "address": l.address, "target": l.target }))
+                    .collect(),
+            ),
+        ),
+        ("POST", ["sessions", id, "kill"]) => match id.parse::<u64>() {
+            Ok(conn_id) if state.sessions.kill(conn_id) => {
+                info!(
+                    mode = state.mode,
+                    conn_id, "session kill requested via admin api"
+                );
+                (200, json!({ "killed": conn_id }))
+            }
+            Ok(_) => (404, json!({ "error": "no such session" })),
+            Err(_) => (400, json!({ "error": "invalid conn_id" })),
+        },
+        ("POST", ["reload"]) => {
+            info!(mode = state.mode, "key reload requested via admin api");
+            state.reload.notify_one();
+            (202, json!({ "reload": "requested" }))
+        }
+        (
+            _,
+            ["status" | "sessions" | "policies" | "listeners" | "reload" | "healthz" | "readyz"],
+        )
+        | (_, ["sessions", _, "kill"]) => (405, json!({ "error": "method not allowed" })),
+        _ => (404, json!({ "error": "not found" })),
+    }
+}
+
+fn readiness(state: &AdminState) -> (u16, Value) {
+    let checks = state.readiness.check(&state.policies.current());
+    let failed: Vec<String> = checks
+        .iter()
+        .filter_map(|check| Some(format!("{}: {}", check.name, check.error.as_ref()?)))
+        .collect();
+    let body = checks
+        .iter()
+        .map(|check| json!({ "name": check.name, "ok": check.ok(), "error": check.error }))
+        .collect::<Vec<_>>();
+
+    if failed.is_empty() {
+        (200, json!({ "ready": true, "checks": body }))
+    } else {
+        let error = format!("not ready: {}", failed.join("; "));
+        (
+            503,
+            json!({ "ready": false, "checks": body, "error": error }),
+        )
+    }
+}
+
+fn status(state: &AdminState) -> Value {
+    let metrics = &state.metrics;
+    json!({
+        "mode": state.mode,
+        "uptime_secs": state.started.elapsed().as_secs(),
+        "sessions": state.sessions.len(),
+        "connections": {
+            "open": metrics.open_connections(),
+            "closed": metrics.closed_connections(),
+            "rejected": metrics.rejected_connections(),
+            "evicted": metrics.evicted_connections(),
+            "unprotected": metrics.unprotected_sessions(),
+        },
+        "audit_dropped": audit::dropped_events(),
+    })
+}
+
+fn session(session: &Session) -> Value {
+    let details = session.details();
+    // Counters come straight from the kernel; null when the AO socket is not
+    // up yet or carries no keys.
+    let ao_keys = session
+        .wire_fd()
+        .and_then(|fd| linux::session_keys(fd.as_raw_fd()).ok())
+        .map(|keys| {
+            keys.iter()
+                .map(|key| {
+                    json!({
+                        "sndid": key.sndid,
+                        "rcvid": key.rcvid,
+                        "current": key.is_current,
+                        "rnext": key.is_rnext,
+                        "pkt_good": key.pkt_good,
+                        "pkt_bad": key.pkt_bad,
+                    })
+                })
+                .collect::<Vec<_>>()
+        });
+
+    json!({
+        "conn_id": session.conn_id(),
+        "peer": session.peer().to_string(),
+        "age_secs": session.age().as_secs(),
+        "policy": details.policy,
+        "keyid": details.keyid,
+        "rnextkeyid": details.rnextkeyid,
+        "wire_peer": details.wire_peer.map(|addr| addr.to_string()),
+        "protected": details.protected,
+        "client": details.client.map(|addr| addr.to_string()),
+        "upstream_policy": details.upstream_policy,
+        "upstream_keyid": details.upstream_keyid,
+        "bytes_up": session.traffic().bytes_up(),
+        "bytes_down": session.traffic().bytes_down(),
+        "ao_keys": ao_keys,
+    })
+}
+
+fn policy(policy: &CompiledPolicy) -> Value {
+    let config = &policy.config;
+    json!({
+        "name": config.name,
+        "peer_ip": config.peer_ip.to_string(),
+        "peer_port": config.peer_port,
+        "keyid": config.keyid,
+        "rnextkeyid": config.rnextkeyid,
+        "mac_alg": policy.alg_name,
+        "enforcement": config.enforcement.as_str(),
+        "key_source": config.key_source.scheme(),
+        "key_fingerprint": policy.key.as_ref().map(|key| key.fingerprint()),
+    })
+}
+
+/// Sends one request to a running proxy's admin API and returns the JSON
+/// body. Non-2xx answers become [`ProxyError::Admin`].
+pub async fn call(endpoint: &PlainEndpoint, method: &str, path: &str) -> Result<Value> {
+    let exchange = async {
+        let mut stream = endpoint.connect().await?;
+        let head =
+            format!("{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n");
+        stream.write_all(head.as_bytes()).await?;
+        let mut raw = Vec::new();
+        stream.read_to_end(&mut raw).await?;
+        Ok::<_, io::Error>(raw)
+    };
+    let raw = tokio::time::timeout(REQUEST_TIMEOUT, exchange)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "admin request timed out"))??;
+
+    let response = http::read_response(raw.as_slice())?;
+    let body: Value = serde_json::from_slice(&response.body)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    if !(200..300).contains(&response.status) {
+        let message = body["error"]
+            .as_str()
+            .unwrap_or("request failed")
+            .to_string();
+        return Err(ProxyError::Admin(response.status, message));
+    }
+    Ok(body)
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+
+    use super::*;
+    use crate::config::{test_key_source, AoPolicyConfig, GlobalConfig};
+    use crate::tcpao::policy::PolicySet;
+
+    fn state() -> AdminState {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let key_source = test_key_source(dir.path(), "admin-test-key");
+        let policies = PolicySet::compile(&[AoPolicyConfig {
+            peer_port: Some(1790),
+            rnextkeyid: Some(2),
+            ..AoPolicyConfig::for_test("bmp-peer-1", "10.0.0.2", &key_source)
+        }])
+        .expect("compiled policies");
+
+        AdminState {
+            mode: "terminator",
+            started: Instant::now(),
+            sessions: Arc::new(SessionRegistry::new(&GlobalConfig::default())),
+            policies: Arc::new(PolicyStore::new(policies)),
+            metrics: Arc::new(Metrics::default()),
+            listeners: vec![Listener {
+                role: "ao",
+                address: "0.0.0.0:1790".to_string(),
+                target: "127.0.0.1:11019".to_string(),
+            }],
+            reload: Arc::new(Notify::new()),
+            readiness: {
+                let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+                Readiness::new(std::os::fd::AsFd::as_fd(&listener), false, None).expect("readiness")
+            },
+        }
+    }
+
+    async fn request(state: &AdminState, raw: &str) -> (u16, Value) {
+        let (mut client, server) = duplex(64 * 1024);
+        client
+            .write_all(raw.as_bytes())
+            .await
+            .expect("write request");
+        handle(server, state).await.expect("handled");
+
+        let mut response = String::new();
+        client
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        let (head, body) = response.split_once("\r\n\r\n").expect("response head");
+        let status = head
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("status code");
+        (status, serde_json::from_str(body).expect("json body"))
+    }
+
+    #[tokio::test]
+    async fn lists_sessions_and_kills_one() {
+        let state = state();
+        let session = state
+            .sessions
+            .admit(
+                7,
+                "10.0.0.2:40000"
+                    .parse::<std::net::SocketAddr>()
+                    .expect("addr"),
+            )
+            .expect("admit");
+        session.describe(|d| {
+            d.policy = Some("bmp-peer-1".to_string());
+            d.keyid = Some(1);
+            d.client = Some("192.0.2.7:5000".parse().expect("addr"));
+            d.upstream_keyid = Some(3);
+        });
+
+        let (status, body) = request(&state, "GET /sessions HTTP/1.1\r\nHost: x\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["conn_id"], 7);
+        assert_eq!(body[0]["peer"], "10.0.0.2:40000");
+        assert_eq!(body[0]["policy"], "bmp-peer-1");
+        assert_eq!(body[0]["bytes_up"], 0);
+        assert_eq!(body[0]["client"], "192.0.2.7:5000");
+        assert_eq!(body[0]["upstream_keyid"], 3);
+        assert!(body[0]["upstream_policy"].is_null());
+        assert!(body[0]["ao_keys"].is_null());
+
+        let (status, _) = request(&state, "POST /sessions/7/kill HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(
+            tokio::time::timeout(Duration::from_secs(1), session.killed())
+                .await
+                .expect("session told to close"),
+            crate::session::KillReason::Admin
+        );
+        let (status, _) = request(&state, "POST /sessions/7/kill HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 404);
+    }
+
+    #[tokio::test]
+    async fn policies_show_fingerprints_but_not_key_locations() {
+        let state = state();
+        let (status, body) = request(&state, "GET /policies HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["name"], "bmp-peer-1");
+        assert_eq!(body[0]["key_source"], "file");
+        assert_eq!(
+            body[0]["key_fingerprint"],
+            crate::secret::fingerprint(b"admin-test-key")
+        );
+        assert!(!body.to_string().contains("/key"));
+    }
+
+    #[tokio::test]
+    async fn reload_signals_the_mode_and_unknown_routes_fail() {
+        let state = state();
+        let (status, _) = request(&state, "POST /reload HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 202);
+        tokio::time::timeout(Duration::from_secs(1), state.reload.notified())
+            .await
+            .expect("reload signalled");
+
+        assert_eq!(request(&state, "GET /reload HTTP/1.1\r\n\r\n").await.0, 405);
+        assert_eq!(request(&state, "GET /nope HTTP/1.1\r\n\r\n").await.0, 404);
+        let (status, body) = request(&state, "GET /listeners HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body[0]["role"], "ao");
+    }
+
+    #[tokio::test]
+    async fn health_and_readiness_report_their_checks() {
+        let state = state();
+        let (status, body) = request(&state, "GET /healthz HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "ok");
+
+        // Whether tcp-ao is usable depends on the host, so only the checks
+        // that do not are asserted.
+        let (status, body) = request(&state, "GET /readyz HTTP/1.1\r\n\r\n").await;
+        assert_eq!(status == 200, body["ready"] == true, "{body}");
+        let checks = body["checks"].as_array().expect("checks");
+        for name in ["listener", "key_sources"] {
+            assert!(
+                checks.iter().any(|c| c["name"] == name && c["ok"] == true),
+                "{body}"
+            );
+        }
+        assert_eq!(
+            request(&state, "POST /readyz HTTP/1.1\r\n\r\n").await.0,
+            405
+        );
+    }
+
+    #[tokio::test]
+    async fn client_talks_to_server_over_unix_socket() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let listen = format!("unix:{}", dir.path().join("admin.sock").display());
+        let cfg = AdminConfig {
+            listen: listen.clone(),
+        };
+        let _server = spawn(Some(&cfg), state())
+            .await
+            .expect("admin server")
+            .expect("configured");
+        let endpoint = PlainEndpoint::parse(&listen).expect("endpoint");
+
+        let status = call(&endpoint, "GET", "/status").await.expect("status");
+        assert_eq!(status["mode"], "terminator");
+        assert_eq!(status["sessions"], 0);
+
+        let err = call(&endpoint, "POST", "/sessions/9/kill")
+            .await
+            .expect_err("unknown session");
+        assert!(matches!(err, ProxyError::Admin(404, ref msg) if msg == "no such session"));
+    }
+}
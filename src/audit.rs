@@ -0,0 +1,621 @@
+//! Security-relevant events, emitted under the `tcpao_proxy::audit` tracing
+//! target so they can be filtered apart from operational logs.
+//!
+//! With an `[audit]` section every event is also written to a dedicated sink
+//! as one JSON object with a fixed set of fields, so a SIEM can ingest it
+//! without parsing log messages: a JSON-lines file rotated by size, or RFC
+//! 5424 syslog over a unix datagram socket. Records are written by a
+//! dedicated thread behind a bounded queue, so emitting never blocks on the
+//! sink; when the queue is full the record is dropped and counted.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::config::{AuditConfig, AuditSink, Enforcement};
+use crate::error::Result;
+
+pub const TARGET: &str = "tcpao_proxy::audit";
+
+/// Bumped whenever a field changes meaning or goes away.
+const SCHEMA_VERSION: u32 = 1;
+const APP_NAME: &str = "tcpao-proxy";
+/// `authpriv`, the facility syslog daemons keep for security messages.
+const SYSLOG_FACILITY: u8 = 10;
+
+/// Records waiting for the writer thread before new ones are dropped.
+const QUEUE_DEPTH: usize = 1024;
+
+static WRITER: Mutex<Option<Writer>> = Mutex::new(None);
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Opens the process-wide audit sink. Passing `None` closes any previous one
+/// after the records already queued for it are written.
+pub fn install(cfg: Option<&AuditConfig>) -> Result<()> {
+    let writer = cfg
+        .map(Sink::open)
+        .transpose()?
+        .map(Writer::spawn)
+        .transpose()?;
+    let previous = std::mem::replace(
+        &mut *WRITER.lock().unwrap_or_else(|e| e.into_inner()),
+        writer,
+    );
+    if let Some(previous) = previous {
+        previous.close();
+    }
+    Ok(())
+}
+
+/// Audit records dropped because the sink fell behind.
+pub fn dropped_events() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+    /// Let through, but with weaker protection than configured.
+    Allowed,
+    Denied,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Outcome::Success => "success",
+            Outcome::Failure => "failure",
+            Outcome::Allowed => "allowed",
+            Outcome::Denied => "denied",
+        }
+    }
+}
+
+/// One audit record. Every field is always present, `null` when it does not
+/// apply to the event.
+#[derive(Debug, Serialize)]
+pub struct AuditEvent<'a> {
+    pub version: u32,
+    pub ts: String,
+    pub event: &'static str,
+    pub outcome: Outcome,
+    pub mode: Option<&'static str>,
+    pub conn_id: Option<u64>,
+    pub peer: Option<String>,
+    pub policy: Option<&'a str>,
+    pub keyid: Option<u8>,
+    pub enforcement: Option<&'static str>,
+    pub fingerprint: Option<String>,
+    pub reason: Option<&'a str>,
+}
+
+impl<'a> AuditEvent<'a> {
+    fn new(event: &'static str, outcome: Outcome) -> Self {
+        Self {
+            version: SCHEMA_VERSION,
+            ts: rfc3339(SystemTime::now()),
+            event,
+            outcome,
+            mode: None,
+            conn_id: None,
+            peer: None,
+            policy: None,
+            keyid: None,
+            enforcement: None,
+            fingerprint: None,
+            reason: None,
+        }
+    }
+
+    fn connection(mut self, mode: &'static str, conn_id: u64, peer: &dyn fmt::Display) -> Self {
+        self.mode = Some(mode);
+        self.conn_id = Some(conn_id);
+        self.peer = Some(peer.to_string());
+        self
+    }
+}
+
+/// A session admitted without AO because its policy is not `required`.
+pub fn unprotected_session(
+    mode: &'static str,
+    conn_id: u64,
+    peer: SocketAddr,
+    policy: &str,
+    enforcement: Enforcement,
+    reason: &str,
+) {
+    emit(
+        AuditEvent {
+            policy: Some(policy),
+            enforcement: Some(enforcement.as_str()),
+            reason: Some(reason),
+            ..AuditEvent::new("ao_not_enforced", Outcome::Allowed).connection(mode, conn_id, &peer)
+        },
+        "session admitted without tcp-ao",
+    );
+}
+
+/// A client refused by the plain listener's access list.
+pub fn client_rejected(mode: &'static str, conn_id: u64, peer: &dyn fmt::Display, reason: &str) {
+    emit(
+        AuditEvent {
+            reason: Some(reason),
+            ..AuditEvent::new("client_rejected", Outcome::Denied).connection(mode, conn_id, peer)
+        },
+        "unauthorized client closed",
+    );
+}
+
+/// A connection for which no `[[ao_policy]]` matches the AO peer.
+pub fn no_policy(mode: &'static str, conn_id: u64, peer: SocketAddr) {
+    emit(
+        AuditEvent::new("no_policy_for_peer", Outcome::Denied).connection(mode, conn_id, &peer),
+        "no ao policy for peer",
+    );
+}
+
+/// AO keys installed on an outbound socket, or an inbound session verified
+/// against its policy. `keyid` is the key the session runs on when known.
+pub fn policy_applied(
+    mode: &'static str,
+    conn_id: u64,
+    peer: SocketAddr,
+    policy: &str,
+    keyid: Option<u8>,
+) {
+    emit(
+        AuditEvent {
+            policy: Some(policy),
+            keyid,
+            ..AuditEvent::new("policy_applied", Outcome::Success).connection(mode, conn_id, &peer)
+        },
+        "tcp-ao policy applied",
+    );
+}
+
+/// AO could not be set up or verified for a session, which is then closed.
+pub fn policy_failed(
+    mode: &'static str,
+    conn_id: u64,
+    peer: SocketAddr,
+    policy: &str,
+    reason: &str,
+) {
+    emit(
+        AuditEvent {
+            policy: Some(policy),
+            reason: Some(reason),
+            ..AuditEvent::new("policy_failed", Outcome::Denied).connection(mode, conn_id, &peer)
+        },
+        "tcp-ao policy failed; session closed",
+    );
+}
+
+/// A policy's master key was read from its key source.
+pub fn key_loaded(policy: &str, keyid: u8, fingerprint: String) {
+    emit(
+        AuditEvent {
+            policy: Some(policy),
+            keyid: Some(keyid),
+            fingerprint: Some(fingerprint),
+            ..AuditEvent::new("key_loaded", Outcome::Success)
+        },
+        "ao key loaded",
+    );
+}
+
+/// A policy's key source could not be read.
+pub fn key_load_failed(policy: &str, keyid: u8, reason: &str) {
+    emit(
+        AuditEvent {
+            policy: Some(policy),
+            keyid: Some(keyid),
+            reason: Some(reason),
+            ..AuditEvent::new("key_load_failed", Outcome::Failure)
+        },
+        "ao key could not be loaded",
+    );
+}
+
+fn emit(event: AuditEvent<'_>, message: &str) {
+    let peer = event.peer.as_deref();
+    let fingerprint = event.fingerprint.as_deref();
+    if event.outcome == Outcome::Success {
+        info!(
+            target: TARGET,
+            event = event.event,
+            outcome = event.outcome.as_str(),
+            mode = event.mode,
+            conn_id = event.conn_id,
+            peer,
+            policy = event.policy,
+            keyid = event.keyid,
+            fingerprint,
+            "{message}"
+        );
+    } else {
+        warn!(
+            target: TARGET,
+            event = event.event,
+            outcome = event.outcome.as_str(),
+            mode = event.mode,
+            conn_id = event.conn_id,
+            peer,
+            policy = event.policy,
+            keyid = event.keyid,
+            enforcement = event.enforcement,
+            reason = event.reason,
+            "{message}"
+        );
+    }
+
+    let writer = WRITER.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(writer) = writer.as_ref() {
+        let record = match Record::new(&event) {
+            Ok(record) => record,
+            Err(err) => {
+                warn!(error = %err, event = event.event, "failed to encode audit event");
+                return;
+            }
+        };
+        match writer.queue.try_send(record) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                DROPPED.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// The sink's thread and the queue feeding it.
+struct Writer {
+    queue: SyncSender<Record>,
+    thread: JoinHandle<()>,
+}
+
+impl Writer {
+    fn spawn(mut sink: Sink) -> io::Result<Self> {
+        let (queue, records) = mpsc::sync_channel::<Record>(QUEUE_DEPTH);
+        let thread = thread::Builder::new()
+            .name("audit-writer".to_string())
+            .spawn(move || {
+                for record in records {
+                    if let Err(err) = sink.write(&record) {
+                        warn!(error = %err, event = record.event, "failed to write audit event");
+                    }
+                }
+            })?;
+        Ok(Self { queue, thread })
+    }
+
+    /// Closes the queue and waits for the records still in it.
+    fn close(self) {
+        drop(self.queue);
+        let _ = self.thread.join();
+    }
+}
+
+/// An event encoded on the emitting thread, ready for the sink.
+struct Record {
+    event: &'static str,
+    outcome: Outcome,
+    ts: String,
+    json: String,
+}
+
+impl Record {
+    fn new(event: &AuditEvent<'_>) -> serde_json::Result<Self> {
+        Ok(Self {
+            event: event.event,
+            outcome: event.outcome,
+            ts: event.ts.clone(),
+            json: serde_json::to_string(event)?,
+        })
+    }
+}
+
+enum Sink {
+    File(RotatingFile),
+    Syslog(Syslog),
+}
+
+impl Sink {
+    fn open(cfg: &AuditConfig) -> Result<Self> {
+        Ok(match cfg.sink {
+            AuditSink::File => Sink::File(RotatingFile::open(
+                cfg.sink_path().to_path_buf(),
+                cfg.max_bytes,
+                cfg.max_files,
+            )?),
+            AuditSink::Syslog => Sink::Syslog(Syslog::new(cfg.sink_path().to_path_buf())),
+        })
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()> {
+        match self {
+            Sink::File(file) => file.append(&record.json),
+            Sink::Syslog(syslog) => syslog.send(record),
+        }
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    max_files: u32,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, max_files: u32) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            max_bytes,
+            max_files,
+        })
+    }
+
+    fn append(&mut self, json: &str) -> io::Result<()> {
+        let len = json.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(format!("{json}\n").as_bytes())?;
+        self.written += len;
+        Ok(())
+    }
+
+    /// `audit.jsonl` becomes `audit.jsonl.1`, `.1` becomes `.2`, and so on;
+    /// whatever was at `.max_files` is overwritten.
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..self.max_files).rev() {
+            match fs::rename(self.rotated(n), self.rotated(n + 1)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                _ => {}
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+
+    fn rotated(&self, n: u32) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o640)
+        .open(path)
+}
+
+struct Syslog {
+    path: PathBuf,
+    socket: Option<UnixDatagram>,
+    hostname: String,
+    pid: u32,
+}
+
+impl Syslog {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            socket: None,
+            hostname: hostname(),
+            pid: std::process::id(),
+        }
+    }
+
+    /// Reconnects once per event, since the syslog daemon may have restarted
+    /// and recreated its socket.
+    fn send(&mut self, record: &Record) -> io::Result<()> {
+        let severity = match record.outcome {
+            Outcome::Success => 5,
+            _ => 4,
+        };
+        let message = format!(
+            "<{}>1 {} {} {APP_NAME} {} {} - {}",
+            SYSLOG_FACILITY * 8 + severity,
+            record.ts,
+            self.hostname,
+            self.pid,
+            record.event,
+            record.json,
+        );
+
+        if let Some(socket) = &self.socket {
+            if socket.send(message.as_bytes()).is_ok() {
+                return Ok(());
+            }
+        }
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&self.path)?;
+        socket.send(message.as_bytes())?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn hostname() -> String {
+    let mut buf = [0_u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    match rc {
+        0 if len > 0 => String::from_utf8_lossy(&buf[..len]).into_owned(),
+        _ => "-".to_string(),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn hostname() -> String {
+    "-".to_string()
+}
+
+/// UTC timestamp with millisecond precision, e.g. `2024-05-01T12:00:00.123Z`.
+fn rfc3339(time: SystemTime) -> String {
+    let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since.as_secs();
+    let (days, rem) = (secs / 86_400, secs % 86_400);
+
+    // Howard Hinnant's civil-from-days.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60,
+        since.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn event() -> AuditEvent<'static> {
+        AuditEvent {
+            policy: Some("bmp-peer-1"),
+            reason: Some("bad mac"),
+            ..AuditEvent::new("policy_failed", Outcome::Denied).connection(
+                "terminator",
+                7,
+                &"10.0.0.2:179",
+            )
+        }
+    }
+
+    fn record() -> Record {
+        Record::new(&event()).expect("record")
+    }
+
+    #[test]
+    fn timestamps_are_rfc3339_utc() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        let leap_day = UNIX_EPOCH + Duration::from_millis(1_709_210_096_789);
+        assert_eq!(rfc3339(leap_day), "2024-02-29T12:34:56.789Z");
+    }
+
+    #[test]
+    fn events_keep_every_field() {
+        let json: serde_json::Value =
+            serde_json::to_value(AuditEvent::new("key_loaded", Outcome::Success)).expect("json");
+        let mut keys: Vec<_> = json.as_object().expect("object").keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "conn_id",
+                "enforcement",
+                "event",
+                "fingerprint",
+                "keyid",
+                "mode",
+                "outcome",
+                "peer",
+                "policy",
+                "reason",
+                "ts",
+                "version"
+            ]
+        );
+        assert_eq!(json["outcome"], "success");
+        assert!(json["peer"].is_null());
+    }
+
+    #[test]
+    fn file_sink_rotates_by_size() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("audit.jsonl");
+        let mut sink = Sink::File(RotatingFile::open(path.clone(), 600, 2).expect("open"));
+
+        for _ in 0..8 {
+            sink.write(&record()).expect("write");
+        }
+
+        let live = fs::read_to_string(&path).expect("live file");
+        let line: serde_json::Value =
+            serde_json::from_str(live.lines().next().expect("a line")).expect("json line");
+        assert_eq!(line["event"], "policy_failed");
+        assert_eq!(line["conn_id"], 7);
+        assert!(path.with_extension("jsonl.1").exists());
+        assert!(path.with_extension("jsonl.2").exists());
+        assert!(!path.with_extension("jsonl.3").exists());
+        for file in ["audit.jsonl", "audit.jsonl.1", "audit.jsonl.2"] {
+            let len = fs::metadata(dir.path().join(file)).expect("metadata").len();
+            assert!(len <= 600, "{file} is {len} bytes");
+        }
+    }
+
+    #[test]
+    fn syslog_sink_sends_rfc5424() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("log.sock");
+        let receiver = UnixDatagram::bind(&path).expect("bind");
+        let mut sink = Sink::Syslog(Syslog::new(path));
+
+        sink.write(&record()).expect("send");
+        let mut buf = [0_u8; 2048];
+        let n = receiver.recv(&mut buf).expect("recv");
+        let message = std::str::from_utf8(&buf[..n]).expect("utf8");
+
+        assert!(message.starts_with("<84>1 "), "{message}");
+        let (head, json) = message.split_once(" - ").expect("structured data");
+        assert!(head.ends_with(&format!("{APP_NAME} {} policy_failed", std::process::id())));
+        let body: serde_json::Value = serde_json::from_str(json).expect("json body");
+        assert_eq!(body["policy"], "bmp-peer-1");
+    }
+
+    #[test]
+    fn writer_drains_its_queue_on_close() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("audit.jsonl");
+        let writer = Writer::spawn(Sink::File(
+            RotatingFile::open(path.clone(), 1 << 20, 1).expect("open"),
+        ))
+        .expect("writer");
+
+        for _ in 0..3 {
+            writer.queue.try_send(record()).expect("queued");
+        }
+        writer.close();
+
+        assert_eq!(fs::read_to_string(&path).expect("file").lines().count(), 3);
+    }
+}
@@ -1,13 +1,25 @@
 use std::collections::HashSet;
 use std::env;
+use std::fmt;
 use std::fs;
 use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::Deserialize;
 
+use crate::acl::{Cidr, ClientAcl};
 use crate::error::{ProxyError, Result};
+use crate::forward::{PumpOptions, DEFAULT_BUFFER_SIZE, DEFAULT_MAX_BUFFERED_BYTES};
+use crate::plain::PlainEndpoint;
+use crate::secret::KeyMaterial;
+
+const CREDENTIALS_DIRECTORY_ENV: &str = "CREDENTIALS_DIRECTORY";
+const KEY_EXEC_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_SYSLOG_SOCKET: &str = "/dev/log";
+const DEFAULT_SERVICE_NAME: &str = "tcpao-proxy";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
@@ -15,6 +27,15 @@ pub enum Mode {
     Terminator,
 }
 
+impl Mode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Mode::Initiator => "initiator",
+            Mode::Terminator => "terminator",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum LogFormat {
@@ -31,6 +52,10 @@ pub struct Config {
     pub terminator: Option<TerminatorConfig>,
     #[serde(default)]
     pub ao_policy: Vec<AoPolicyConfig>,
+    pub vault: Option<VaultConfig>,
+    pub admin: Option<AdminConfig>,
+    pub audit: Option<AuditConfig>,
+    pub telemetry: Option<TelemetryConfig>,
 }
 
 impl Config {
@@ -43,17 +68,38 @@ impl Config {
     pub fn validate(&self, mode: Mode) -> Result<()> {
         match mode {
             Mode::Initiator => {
-                if self.initiator.is_none() {
+                let Some(initiator) = &self.initiator else {
                     return Err(ProxyError::MissingModeConfig("initiator"));
-                }
+                };
+                initiator.client_acl()?;
+                initiator.buffers.validate("initiator")?;
             }
             Mode::Terminator => {
-                if self.terminator.is_none() {
+                let Some(terminator) = &self.terminator else {
                     return Err(ProxyError::MissingModeConfig("terminator"));
+                };
+                terminator.forward_plain_endpoint()?;
+                self.validate_terminator_fallback(terminator)?;
+                terminator.buffers.validate("terminator")?;
+                terminator.health_check.validate()?;
+                if let Some(spool) = &terminator.spool {
+                    spool.validate()?;
                 }
             }
         }
 
+        if let Some(admin) = &self.admin {
+            admin.listen_endpoint()?;
+        }
+
+        if let Some(audit) = &self.audit {
+            audit.validate()?;
+        }
+
+        if let Some(telemetry) = &self.telemetry {
+            telemetry.validate()?;
+        }
+
         if self.ao_policy.is_empty() {
             return Err(ProxyError::Config(
                 "at least one [[ao_policy]] entry is required".to_string(),
@@ -61,9 +107,21 @@ impl Config {
         }
 
         for policy in &self.ao_policy {
-            let _ = policy.key_source.kind()?;
+            let kind = policy.key_source.kind()?;
+            if matches!(kind, KeySourceKind::Vault { .. }) && self.vault.is_none() {
+                return Err(ProxyError::Config(format!(
+                    "ao_policy '{}' uses a vault key_source but no [vault] section is configured",
+                    policy.name
+                )));
+            }
+        }
+
+        if let Some(vault) = &self.vault {
+            vault.validate()?;
         }
 
+        self.global.validate()?;
+
         let mut names = HashSet::new();
         let mut peer_tuples = HashSet::new();
         for policy in &self.ao_policy {
@@ -90,28 +148,95 @@ impl Config {
         Ok(())
     }
 
+    /// A `preferred` peer that matches a key on `listen_ao` can only connect
+    /// with AO, so without `fallback_listen_ao` the terminator would enforce
+    /// it like `required`; such a config is refused.
+    fn validate_terminator_fallback(&self, terminator: &TerminatorConfig) -> Result<()> {
+        if terminator.fallback_listen_ao_addr()?.is_some() {
+            return Ok(());
+        }
+        match self
+            .ao_policy
+            .iter()
+            .find(|p| p.enforcement == Enforcement::Preferred)
+        {
+            Some(policy) => Err(ProxyError::Config(format!(
+                "ao_policy '{}' is preferred, which the terminator only honours with \
+terminator.fallback_listen_ao; use required or monitor, or configure the fallback listener",
+                policy.name
+            ))),
+            None => Ok(()),
+        }
+    }
+
     pub fn redacted_summary(&self) -> String {
+        let count = |level: Enforcement| {
+            self.ao_policy
+                .iter()
+                .filter(|p| p.enforcement == level)
+                .count()
+        };
+
         format!(
-            "log_format={:?}, idle_timeout_secs={}, tcp_keepalive={}, policies={}",
+            "log_format={:?}, idle_timeout_secs={}, tcp_keepalive={}, policies={}, \
+enforcement=required:{},preferred:{},monitor:{}",
             self.global.log_format,
             self.global.idle_timeout_secs,
             self.global.tcp_keepalive,
-            self.ao_policy.len()
+            self.ao_policy.len(),
+            count(Enforcement::Required),
+            count(Enforcement::Preferred),
+            count(Enforcement::Monitor)
         )
     }
+
+    /// `[telemetry] service_name`, also used without a `[telemetry]` section
+    /// to name the proxy in connection spans.
+    pub fn service_name(&self) -> &str {
+        self.telemetry
+            .as_ref()
+            .map_or(DEFAULT_SERVICE_NAME, |t| t.service_name.as_str())
+    }
+
+    /// Policies whose enforcement is weaker than `required`.
+    pub fn relaxed_policies(&self) -> impl Iterator<Item = &AoPolicyConfig> {
+        self.ao_policy
+            .iter()
+            .filter(|p| !p.enforcement.is_required())
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct GlobalConfig {
     #[serde(default)]
     pub log_format: LogFormat,
+    /// No bytes in either direction; 0 disables.
     #[serde(default = "default_idle_timeout_secs")]
     pub idle_timeout_secs: u64,
+    /// No bytes router -> collector.
+    pub idle_timeout_up_secs: Option<u64>,
+    /// No bytes collector -> router.
+    pub idle_timeout_down_secs: Option<u64>,
+    pub max_session_lifetime_secs: Option<u64>,
     #[serde(default)]
     pub tcp_keepalive: bool,
     pub keepalive_time_secs: Option<u64>,
     pub keepalive_intvl_secs: Option<u64>,
     pub keepalive_probes: Option<u32>,
+    #[serde(default = "default_key_watch_interval_secs")]
+    pub key_watch_interval_secs: u64,
+    pub max_connections: Option<usize>,
+    pub max_connections_per_peer: Option<usize>,
+    /// Sustained accepts per second; bursts up to `accept_burst` (defaults to
+    /// the rate) are allowed.
+    pub accept_rate_per_sec: Option<u32>,
+    pub accept_burst: Option<u32>,
+    #[serde(default)]
+    pub on_connection_limit: LimitAction,
+    /// Forward with `splice(2)` instead of copying through userspace. Off
+    /// unless set.
+    #[serde(default)]
+    pub zero_copy: bool,
 }
 
 impl Default for GlobalConfig {
@@ -119,33 +244,251 @@ impl Default for GlobalConfig {
         Self {
             log_format: LogFormat::Text,
             idle_timeout_secs: default_idle_timeout_secs(),
+            idle_timeout_up_secs: None,
+            idle_timeout_down_secs: None,
+            max_session_lifetime_secs: None,
             tcp_keepalive: false,
             keepalive_time_secs: None,
             keepalive_intvl_secs: None,
             keepalive_probes: None,
+            key_watch_interval_secs: default_key_watch_interval_secs(),
+            max_connections: None,
+            max_connections_per_peer: None,
+            accept_rate_per_sec: None,
+            accept_burst: None,
+            on_connection_limit: LimitAction::Reject,
+            zero_copy: false,
         }
     }
 }
 
 impl GlobalConfig {
-    pub fn idle_timeout(&self) -> Option<Duration> {
-        if self.idle_timeout_secs == 0 {
+    pub fn validate(&self) -> Result<()> {
+        let limits = [
+            ("max_connections", self.max_connections.map(|v| v as u64)),
+            (
+                "max_connections_per_peer",
+                self.max_connections_per_peer.map(|v| v as u64),
+            ),
+            (
+                "accept_rate_per_sec",
+                self.accept_rate_per_sec.map(u64::from),
+            ),
+            ("accept_burst", self.accept_burst.map(u64::from)),
+        ];
+        for (name, value) in limits {
+            if value == Some(0) {
+                return Err(ProxyError::Config(format!(
+                    "global.{name} must be greater than 0; omit it to disable the limit"
+                )));
+            }
+        }
+
+        if self.accept_burst.is_some() && self.accept_rate_per_sec.is_none() {
+            return Err(ProxyError::Config(
+                "global.accept_burst requires accept_rate_per_sec".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Forwarding options for a mode: the mode section's `timeouts` override
+    /// `[global]` one by one, and `buffers` only exist per mode.
+    pub fn pump_options(&self, service: &SessionTimeouts, buffers: &BufferLimits) -> PumpOptions {
+        PumpOptions {
+            idle_timeout: secs(service.idle_timeout_secs.or(Some(self.idle_timeout_secs))),
+            idle_timeout_up: secs(service.idle_timeout_up_secs.or(self.idle_timeout_up_secs)),
+            idle_timeout_down: secs(
+                service
+                    .idle_timeout_down_secs
+                    .or(self.idle_timeout_down_secs),
+            ),
+            max_lifetime: secs(
+                service
+                    .max_session_lifetime_secs
+                    .or(self.max_session_lifetime_secs),
+            ),
+            buffer_size: buffers.buffer_size,
+            max_buffered_bytes: buffers.max_buffered_bytes,
+            zero_copy: self.zero_copy,
+        }
+    }
+
+    pub fn key_watch_interval(&self) -> Option<Duration> {
+        if self.key_watch_interval_secs == 0 {
             None
         } else {
-            Some(Duration::from_secs(self.idle_timeout_secs))
+            Some(Duration::from_secs(self.key_watch_interval_secs))
+        }
+    }
+}
+
+/// Per-mode overrides for the `[global]` session timers. Set a value to 0 to
+/// turn a global timer off for this mode.
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct SessionTimeouts {
+    pub idle_timeout_secs: Option<u64>,
+    pub idle_timeout_up_secs: Option<u64>,
+    pub idle_timeout_down_secs: Option<u64>,
+    pub max_session_lifetime_secs: Option<u64>,
+}
+
+/// Copy-loop buffering for one mode. The splice path buffers in a kernel pipe
+/// instead and ignores these.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct BufferLimits {
+    /// Bytes per read.
+    #[serde(default = "default_buffer_size")]
+    pub buffer_size: usize,
+    /// Read-but-unwritten bytes allowed per direction before reads pause.
+    #[serde(default = "default_max_buffered_bytes")]
+    pub max_buffered_bytes: usize,
+}
+
+impl Default for BufferLimits {
+    fn default() -> Self {
+        Self {
+            buffer_size: default_buffer_size(),
+            max_buffered_bytes: default_max_buffered_bytes(),
         }
     }
 }
 
+impl BufferLimits {
+    pub fn validate(&self, section: &str) -> Result<()> {
+        if self.buffer_size == 0 || self.buffer_size > MAX_BUFFER_SIZE {
+            return Err(ProxyError::Config(format!(
+                "{section}.buffer_size must be between 1 and {MAX_BUFFER_SIZE}"
+            )));
+        }
+        if self.max_buffered_bytes < self.buffer_size {
+            return Err(ProxyError::Config(format!(
+                "{section}.max_buffered_bytes must be at least buffer_size ({})",
+                self.buffer_size
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Active checks of `forward_plain`. While the target is down the terminator
+/// stops accepting AO sessions, so routers back off instead of completing a
+/// handshake that is closed straight away.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Seconds between connect checks; unset turns checking off.
+    pub health_check_interval_secs: Option<u64>,
+    #[serde(default = "default_health_check_timeout_secs")]
+    pub health_check_timeout_secs: u64,
+    /// Failed checks in a row before accepting pauses. One success resumes.
+    #[serde(default = "default_health_check_failures")]
+    pub health_check_failures: u32,
+}
+
+impl Default for HealthCheck {
+    fn default() -> Self {
+        Self {
+            health_check_interval_secs: None,
+            health_check_timeout_secs: default_health_check_timeout_secs(),
+            health_check_failures: default_health_check_failures(),
+        }
+    }
+}
+
+impl HealthCheck {
+    pub fn validate(&self) -> Result<()> {
+        if self.health_check_interval_secs == Some(0) {
+            return Err(ProxyError::Config(
+                "terminator.health_check_interval_secs must be at least 1; leave it unset to turn checking off"
+                    .to_string(),
+            ));
+        }
+        if self.health_check_timeout_secs == 0 {
+            return Err(ProxyError::Config(
+                "terminator.health_check_timeout_secs must be at least 1".to_string(),
+            ));
+        }
+        if self.health_check_failures == 0 {
+            return Err(ProxyError::Config(
+                "terminator.health_check_failures must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn interval(&self) -> Option<Duration> {
+        self.health_check_interval_secs.map(Duration::from_secs)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.health_check_timeout_secs)
+    }
+}
+
+/// Upper bound on `buffer_size`; a single read larger than this buys nothing.
+const MAX_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+fn secs(value: Option<u64>) -> Option<Duration> {
+    value.filter(|v| *v > 0).map(Duration::from_secs)
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct InitiatorConfig {
     pub listen_plain: String,
     pub remote_ao: String,
+    /// CIDRs that may connect to `listen_plain`; empty allows any client.
+    #[serde(default)]
+    pub allowed_clients: Vec<String>,
+    /// Peer uids/gids accepted on a unix `listen_plain`; empty allows any.
+    #[serde(default)]
+    pub allowed_uids: Vec<u32>,
+    #[serde(default)]
+    pub allowed_gids: Vec<u32>,
+    /// Expect a PROXY protocol v2 header from every plain client.
+    #[serde(default)]
+    pub accept_proxy_protocol: bool,
+    #[serde(flatten)]
+    pub timeouts: SessionTimeouts,
+    #[serde(flatten)]
+    pub buffers: BufferLimits,
 }
 
 impl InitiatorConfig {
-    pub fn listen_plain_addr(&self) -> Result<SocketAddr> {
-        Ok(self.listen_plain.parse()?)
+    pub fn client_acl(&self) -> Result<ClientAcl> {
+        let networks = self
+            .allowed_clients
+            .iter()
+            .map(|raw| raw.parse::<Cidr>())
+            .collect::<Result<Vec<_>>>()?;
+        let acl = ClientAcl::new(
+            networks,
+            self.allowed_uids.clone(),
+            self.allowed_gids.clone(),
+        );
+
+        let unix = self.listen_plain_endpoint()?.is_unix();
+        if acl.restricts_credentials() && !unix {
+            return Err(ProxyError::Config(
+                "initiator.allowed_uids/allowed_gids need a unix socket listen_plain; \
+peer credentials are not available for tcp clients"
+                    .to_string(),
+            ));
+        }
+        if acl.restricts_addrs() && unix {
+            return Err(ProxyError::Config(
+                "initiator.allowed_clients applies to a tcp listen_plain; \
+use allowed_uids/allowed_gids for a unix socket"
+                    .to_string(),
+            ));
+        }
+
+        Ok(acl)
+    }
+
+    /// `listen_plain` as a TCP address or `unix:PATH`.
+    pub fn listen_plain_endpoint(&self) -> Result<PlainEndpoint> {
+        PlainEndpoint::parse(&self.listen_plain)
     }
 
     pub fn remote_ao_addr(&self) -> Result<SocketAddr> {
@@ -156,7 +499,21 @@ impl InitiatorConfig {
 #[derive(Debug, Clone, Deserialize)]
 pub struct TerminatorConfig {
     pub listen_ao: String,
+    /// Second AO listener that holds keys for `required` peers only, where
+    /// `preferred` peers that cannot sign are admitted without AO.
+    pub fallback_listen_ao: Option<String>,
     pub forward_plain: String,
+    /// Prefix `forward_plain` connections with a PROXY protocol v2 header.
+    #[serde(default)]
+    pub send_proxy_protocol: bool,
+    #[serde(flatten)]
+    pub timeouts: SessionTimeouts,
+    #[serde(flatten)]
+    pub buffers: BufferLimits,
+    #[serde(flatten)]
+    pub health_check: HealthCheck,
+    /// Keep sessions open across `forward_plain` restarts; off when absent.
+    pub spool: Option<SpoolConfig>,
 }
 
 impl TerminatorConfig {
@@ -164,8 +521,17 @@ impl TerminatorConfig {
         Ok(self.listen_ao.parse()?)
     }
 
-    pub fn forward_plain_addr(&self) -> Result<SocketAddr> {
-        Ok(self.forward_plain.parse()?)
+    pub fn fallback_listen_ao_addr(&self) -> Result<Option<SocketAddr>> {
+        Ok(self
+            .fallback_listen_ao
+            .as_deref()
+            .map(str::parse::<SocketAddr>)
+            .transpose()?)
+    }
+
+    /// `forward_plain` as a TCP address or `unix:PATH`.
+    pub fn forward_plain_endpoint(&self) -> Result<PlainEndpoint> {
+        PlainEndpoint::parse(&self.forward_plain)
     }
 }
 
@@ -178,19 +544,386 @@ pub struct AoPolicyConfig {
     pub rnextkeyid: Option<u8>,
     pub mac_alg: String,
     pub key_source: KeySource,
+    #[serde(default)]
+    pub enforcement: Enforcement,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// A `required` hmac-sha256 policy with key id 1 for any port of `peer_ip`;
+/// tests override the rest with struct update syntax.
+#[cfg(test)]
+impl AoPolicyConfig {
+    pub(crate) fn for_test(name: &str, peer_ip: &str, key_source: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            peer_ip: peer_ip.parse().expect("valid ip"),
+            peer_port: None,
+            keyid: 1,
+            rnextkeyid: None,
+            mac_alg: "hmac-sha256".to_string(),
+            key_source: KeySource(key_source.to_string()),
+            enforcement: Enforcement::Required,
+        }
+    }
+}
+
+/// Writes `key` to a file in `dir` and returns its `file:` source, so tests
+/// that load keys never change the process environment.
+#[cfg(test)]
+pub(crate) fn test_key_source(dir: &Path, key: &str) -> String {
+    let path = dir.join("key");
+    fs::write(&path, key).expect("write key");
+    format!("file:{}", path.display())
+}
+
+/// How strictly a policy's AO protection is enforced. Anything other than
+/// `required` must be set explicitly and is meant for migrations only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Enforcement {
+    /// Sessions without AO are rejected.
+    #[default]
+    Required,
+    /// AO is configured and used when the peer speaks it. The initiator falls
+    /// back to an unprotected connect. The kernel never falls back on its own:
+    /// it drops unsigned SYNs from a peer that matches a listener key, so the
+    /// terminator admits unsigned sessions only on `fallback_listen_ao`, which
+    /// holds no key for the peer. Every fallback is audited.
+    Preferred,
+    /// AO is never enforced: local AO setup errors are tolerated and the
+    /// terminator does not install the peer's key, so its sessions are
+    /// accepted unprotected. Every unprotected session is audited.
+    Monitor,
+}
+
+impl Enforcement {
+    pub fn is_required(self) -> bool {
+        self == Enforcement::Required
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Enforcement::Required => "required",
+            Enforcement::Preferred => "preferred",
+            Enforcement::Monitor => "monitor",
+        }
+    }
+}
+
+#[derive(Clone, Deserialize)]
 #[serde(transparent)]
 pub struct KeySource(pub String);
 
+impl fmt::Debug for KeySource {
+    // Only the locator is printed; inline command arguments may carry secrets
+    // and are never shown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind() {
+            Ok(KeySourceKind::Exec { program, args }) => write!(
+                f,
+                "KeySource(\"exec:{} <{} args redacted>\")",
+                program.display(),
+                args.len()
+            ),
+            Ok(_) => f.debug_tuple("KeySource").field(&self.0).finish(),
+            Err(_) => f.write_str("KeySource(<invalid>)"),
+        }
+    }
+}
+
+/// What happens to a new connection that would exceed `max_connections` or
+/// `max_connections_per_peer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimitAction {
+    /// Close the new connection.
+    #[default]
+    Reject,
+    /// Close the oldest session in the exhausted scope and admit the new one.
+    EvictOldest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeyFailureMode {
+    #[default]
+    FailClosed,
+    LastKnownGood,
+}
+
+/// The local admin API. It is unauthenticated, so it only listens on a unix
+/// socket or a loopback address.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AdminConfig {
+    pub listen: String,
+}
+
+impl AdminConfig {
+    pub fn listen_endpoint(&self) -> Result<PlainEndpoint> {
+        let endpoint = PlainEndpoint::parse(&self.listen)?;
+        if let PlainEndpoint::Tcp(addr) = &endpoint {
+            if !addr.ip().is_loopback() {
+                return Err(ProxyError::Config(format!(
+                    "admin.listen {addr} must be a loopback address or a unix socket"
+                )));
+            }
+        }
+        Ok(endpoint)
+    }
+}
+
+/// `[terminator.spool]`: while `forward_plain` is unreachable, BMP messages
+/// from the router are queued in a file per session under `dir` and
+/// replayed once a reconnect succeeds.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpoolConfig {
+    pub dir: PathBuf,
+    /// Bytes a single session may have spooled at once.
+    #[serde(default = "default_spool_max_bytes")]
+    pub max_bytes: u64,
+    #[serde(default)]
+    pub overflow: SpoolOverflow,
+    /// First reconnect delay; doubled after each failure up to
+    /// `reconnect_max_secs`.
+    #[serde(default = "default_spool_reconnect_initial_ms")]
+    pub reconnect_initial_ms: u64,
+    #[serde(default = "default_spool_reconnect_max_secs")]
+    pub reconnect_max_secs: u64,
+}
+
+/// What a session does when its spool is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpoolOverflow {
+    /// Close the session; the router reconnects and re-sends its tables.
+    #[default]
+    Close,
+    /// Stop reading from the router until the spool drains, so TCP flow
+    /// control pushes back on it.
+    Block,
+}
+
+impl SpoolConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.dir.as_os_str().is_empty() {
+            return Err(ProxyError::Config(
+                "terminator.spool.dir must not be empty".to_string(),
+            ));
+        }
+        if self.max_bytes < crate::spool::MAX_MESSAGE_LEN as u64 {
+            return Err(ProxyError::Config(format!(
+                "terminator.spool.max_bytes must be at least {} so any bmp message fits",
+                crate::spool::MAX_MESSAGE_LEN
+            )));
+        }
+        if self.reconnect_initial_ms == 0 || self.reconnect_max() < self.reconnect_initial() {
+            return Err(ProxyError::Config(
+                "terminator.spool.reconnect_initial_ms must be at least 1 and no more than reconnect_max_secs"
+                    .to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn reconnect_initial(&self) -> Duration {
+        Duration::from_millis(self.reconnect_initial_ms)
+    }
+
+    pub fn reconnect_max(&self) -> Duration {
+        Duration::from_secs(self.reconnect_max_secs)
+    }
+}
+
+/// Where audit events go besides the `tcpao_proxy::audit` log target.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuditConfig {
+    pub sink: AuditSink,
+    /// The JSON-lines file, or the syslog socket (default `/dev/log`).
+    pub path: Option<PathBuf>,
+    /// Size at which the file is rotated.
+    #[serde(default = "default_audit_max_bytes")]
+    pub max_bytes: u64,
+    /// Rotated files kept next to the live one.
+    #[serde(default = "default_audit_max_files")]
+    pub max_files: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditSink {
+    /// JSON lines appended to `path`, rotated by size.
+    File,
+    /// RFC 5424 messages with a JSON body over a unix datagram socket.
+    Syslog,
+}
+
+impl AuditConfig {
+    pub fn validate(&self) -> Result<()> {
+        if self.sink == AuditSink::File && self.path.is_none() {
+            return Err(ProxyError::Config(
+                "audit.path is required for the file sink".to_string(),
+            ));
+        }
+        if self.max_bytes == 0 || self.max_files == 0 {
+            return Err(ProxyError::Config(
+                "audit.max_bytes and audit.max_files must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn sink_path(&self) -> &Path {
+        self.path
+            .as_deref()
+            .unwrap_or_else(|| Path::new(DEFAULT_SYSLOG_SOCKET))
+    }
+}
+
+/// OTLP export of spans and counters. Only acted on when built with the
+/// `otel` feature.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TelemetryConfig {
+    /// Collector base URL, e.g. `http://127.0.0.1:4317` for gRPC or
+    /// `http://127.0.0.1:4318` for HTTP; `/v1/traces` and `/v1/metrics` are
+    /// appended for HTTP.
+    pub endpoint: String,
+    #[serde(default)]
+    pub protocol: OtlpProtocol,
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+    #[serde(default = "default_telemetry_metrics_interval_secs")]
+    pub metrics_interval_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OtlpProtocol {
+    #[default]
+    Grpc,
+    /// Protobuf over HTTP.
+    Http,
+}
+
+impl TelemetryConfig {
+    pub fn validate(&self) -> Result<()> {
+        if !self.endpoint.starts_with("http://") && !self.endpoint.starts_with("https://") {
+            return Err(ProxyError::Config(format!(
+                "telemetry.endpoint '{}' must be an http:// or https:// url",
+                self.endpoint
+            )));
+        }
+        if self.metrics_interval_secs == 0 {
+            return Err(ProxyError::Config(
+                "telemetry.metrics_interval_secs must be at least 1".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VaultConfig {
+    pub address: String,
+    /// PEM bundle trusted for an `https://` address instead of the webpki roots.
+    pub ca_cert: Option<PathBuf>,
+    pub token_source: Option<KeySource>,
+    pub role_id: Option<String>,
+    pub secret_id_source: Option<KeySource>,
+    #[serde(default = "default_vault_approle_mount")]
+    pub approle_mount: String,
+    pub namespace: Option<String>,
+    #[serde(default = "default_vault_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    #[serde(default = "default_vault_timeout_secs")]
+    pub timeout_secs: u64,
+    #[serde(default)]
+    pub on_failure: KeyFailureMode,
+}
+
+impl VaultConfig {
+    pub fn validate(&self) -> Result<()> {
+        let address = crate::http::Url::parse(&self.address)
+            .map_err(|e| ProxyError::Config(format!("invalid vault address: {e}")))?;
+        if address.tls && cfg!(not(feature = "tls")) {
+            return Err(ProxyError::Config(
+                "https vault address requires building tcpao-proxy with the 'tls' feature"
+                    .to_string(),
+            ));
+        }
+        if self.ca_cert.is_some() && !address.tls {
+            return Err(ProxyError::Config(
+                "vault ca_cert requires an https:// address".to_string(),
+            ));
+        }
+
+        match (&self.token_source, &self.role_id) {
+            (Some(_), Some(_)) => {
+                return Err(ProxyError::Config(
+                    "vault token_source and role_id are mutually exclusive".to_string(),
+                ))
+            }
+            (None, None) => {
+                return Err(ProxyError::Config(
+                    "vault requires token_source or role_id".to_string(),
+                ))
+            }
+            _ => {}
+        }
+
+        for source in [&self.token_source, &self.secret_id_source]
+            .into_iter()
+            .flatten()
+        {
+            if matches!(source.kind()?, KeySourceKind::Vault { .. }) {
+                return Err(ProxyError::Config(
+                    "vault credentials cannot themselves come from vault".to_string(),
+                ));
+            }
+        }
+
+        if self.timeout_secs == 0 {
+            return Err(ProxyError::Config(
+                "vault timeout_secs must be greater than zero".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum KeySourceKind {
     File(PathBuf),
     Env(String),
+    /// Helper command whose stdout is the key; one trailing newline is stripped.
+    Exec {
+        program: PathBuf,
+        args: Vec<String>,
+    },
+    /// systemd `LoadCredential=` entry read from `$CREDENTIALS_DIRECTORY`.
+    Credential(String),
+    /// Entry in a mounted secret directory (e.g. a Kubernetes secret volume).
+    /// Unlike `file:`, these sources are watched and re-keyed on change.
+    Dir(PathBuf),
+    /// KV secret `path` read from the `[vault]` server; `field` defaults to `key`.
+    Vault {
+        path: String,
+        field: String,
+    },
+}
+
+impl KeySourceKind {
+    pub fn is_watched(&self) -> bool {
+        matches!(self, KeySourceKind::Dir(_) | KeySourceKind::Vault { .. })
+    }
 }
 
 impl KeySource {
+    /// The source type (`env`, `file`, `vault`, ...) without its locator,
+    /// for places that must not reveal where a key lives.
+    pub fn scheme(&self) -> &str {
+        self.0.split_once(':').map_or("", |(scheme, _)| scheme)
+    }
+
     pub fn kind(&self) -> Result<KeySourceKind> {
         if let Some(v) = self.0.strip_prefix("file:") {
             let path = PathBuf::from(v);
@@ -211,20 +944,74 @@ impl KeySource {
             return Ok(KeySourceKind::Env(v.to_string()));
         }
 
+        if let Some(v) = self.0.strip_prefix("exec:") {
+            let mut parts = split_command(v)?.into_iter();
+            let program = parts.next().ok_or_else(|| {
+                ProxyError::Config("key_source exec command must not be empty".to_string())
+            })?;
+            let program = PathBuf::from(program);
+            if !program.is_absolute() {
+                return Err(ProxyError::Config(format!(
+                    "key_source exec command '{}' must be an absolute path",
+                    program.display()
+                )));
+            }
+            return Ok(KeySourceKind::Exec {
+                program,
+                args: parts.collect(),
+            });
+        }
+
+        if let Some(v) = self.0.strip_prefix("credential:") {
+            if v.is_empty() || v.contains('/') || v == "." || v == ".." {
+                return Err(ProxyError::Config(format!(
+                    "key_source credential name '{v}' must be a plain file name"
+                )));
+            }
+            return Ok(KeySourceKind::Credential(v.to_string()));
+        }
+
+        if let Some(v) = self.0.strip_prefix("dir:") {
+            let path = PathBuf::from(v);
+            if path.as_os_str().is_empty() || path.file_name().is_none() {
+                return Err(ProxyError::Config(
+                    "key_source dir entry must name a file inside the secret directory".to_string(),
+                ));
+            }
+            return Ok(KeySourceKind::Dir(path));
+        }
+
+        if let Some(v) = self.0.strip_prefix("vault:") {
+            let (path, field) = match v.split_once('#') {
+                Some((path, field)) => (path, field),
+                None => (v, "key"),
+            };
+            if path.trim_matches('/').is_empty() || field.is_empty() {
+                return Err(ProxyError::Config(
+                    "key_source vault path and field must not be empty".to_string(),
+                ));
+            }
+            return Ok(KeySourceKind::Vault {
+                path: path.trim_matches('/').to_string(),
+                field: field.to_string(),
+            });
+        }
+
         Err(ProxyError::Config(format!(
-            "unsupported key_source '{}'; expected file:PATH or env:VAR",
+            "unsupported key_source '{}'; expected file:PATH, env:VAR, exec:COMMAND, \
+credential:NAME, dir:PATH or vault:PATH[#FIELD]",
             self.0
         )))
     }
 
-    pub fn load_key_bytes(&self) -> Result<Vec<u8>> {
-        match self.kind()? {
+    pub fn load_key(&self) -> Result<KeyMaterial> {
+        let raw = match self.kind()? {
             KeySourceKind::File(path) => {
                 let raw = fs::read(path)?;
                 if raw.is_empty() {
                     return Err(ProxyError::Config("key file is empty".to_string()));
                 }
-                Ok(raw)
+                raw
             }
             KeySourceKind::Env(name) => {
                 let raw = env::var(&name).map_err(|_| {
@@ -233,16 +1020,226 @@ impl KeySource {
                 if raw.is_empty() {
                     return Err(ProxyError::Config("env key value is empty".to_string()));
                 }
-                Ok(raw.into_bytes())
+                raw.into_bytes()
+            }
+            KeySourceKind::Exec { program, args } => {
+                run_key_command(&program, &args, KEY_EXEC_TIMEOUT)?
+            }
+            KeySourceKind::Credential(name) => {
+                let dir = env::var_os(CREDENTIALS_DIRECTORY_ENV).ok_or_else(|| {
+                    ProxyError::Config(format!(
+                        "credential key '{name}' requested but {CREDENTIALS_DIRECTORY_ENV} is not set"
+                    ))
+                })?;
+                let raw = fs::read(Path::new(&dir).join(&name))?;
+                if raw.is_empty() {
+                    return Err(ProxyError::Config(format!(
+                        "credential key '{name}' is empty"
+                    )));
+                }
+                raw
+            }
+            KeySourceKind::Dir(path) => {
+                let raw = fs::read(path)?;
+                if raw.is_empty() {
+                    return Err(ProxyError::Config(
+                        "secret directory key is empty".to_string(),
+                    ));
+                }
+                raw
+            }
+            KeySourceKind::Vault { path, field } => return crate::vault::read_key(&path, &field),
+        };
+
+        Ok(KeyMaterial::new(raw))
+    }
+
+    /// Whether an unreadable key must be removed from sockets instead of
+    /// keeping the last installed one.
+    pub fn fails_closed(&self) -> bool {
+        matches!(self.kind(), Ok(KeySourceKind::Vault { .. })) && crate::vault::fails_closed()
+    }
+}
+
+/// Splits an `exec:` command line on whitespace. Single quotes keep their
+/// contents verbatim; inside double quotes and unquoted, a backslash escapes
+/// the next character. No other shell syntax is interpreted.
+fn split_command(line: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated_quote()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.push(chars.next().ok_or_else(unterminated_quote)?),
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated_quote()),
+                    }
+                }
             }
+            '\\' => {
+                let escaped = chars.next().ok_or_else(|| {
+                    ProxyError::Config("key_source exec command ends with '\\'".to_string())
+                })?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            c => word.get_or_insert_with(String::new).push(c),
         }
     }
+    words.extend(word);
+    Ok(words)
+}
+
+fn unterminated_quote() -> ProxyError {
+    ProxyError::Config("key_source exec command has an unterminated quote".to_string())
+}
+
+/// Blocks for up to `timeout`; async callers reach it through
+/// `spawn_blocking`.
+fn run_key_command(program: &Path, args: &[String], timeout: Duration) -> Result<Vec<u8>> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            ProxyError::Config(format!(
+                "failed to start key command '{}': {e}",
+                program.display()
+            ))
+        })?;
+
+    // Drain stdout on a separate thread so a chatty helper cannot block on a
+    // full pipe while we wait for it to exit.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let reader = thread::spawn(move || {
+        let mut raw = Vec::new();
+        std::io::Read::read_to_end(&mut stdout, &mut raw).map(|_| raw)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(ProxyError::Config(format!(
+                "key command '{}' timed out after {}s",
+                program.display(),
+                timeout.as_secs()
+            )));
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    if !status.success() {
+        return Err(ProxyError::Config(format!(
+            "key command '{}' exited with {status}",
+            program.display()
+        )));
+    }
+
+    let mut raw = reader
+        .join()
+        .map_err(|_| ProxyError::Config("key command reader panicked".to_string()))??;
+    if raw.last() == Some(&b'\n') {
+        raw.pop();
+        if raw.last() == Some(&b'\r') {
+            raw.pop();
+        }
+    }
+    if raw.is_empty() {
+        return Err(ProxyError::Config(format!(
+            "key command '{}' produced no output",
+            program.display()
+        )));
+    }
+
+    Ok(raw)
 }
 
 fn default_idle_timeout_secs() -> u64 {
     120
 }
 
+fn default_key_watch_interval_secs() -> u64 {
+    5
+}
+
+fn default_buffer_size() -> usize {
+    DEFAULT_BUFFER_SIZE
+}
+
+fn default_max_buffered_bytes() -> usize {
+    DEFAULT_MAX_BUFFERED_BYTES
+}
+
+fn default_health_check_timeout_secs() -> u64 {
+    2
+}
+
+fn default_health_check_failures() -> u32 {
+    2
+}
+
+fn default_spool_max_bytes() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_spool_reconnect_initial_ms() -> u64 {
+    250
+}
+
+fn default_spool_reconnect_max_secs() -> u64 {
+    30
+}
+
+fn default_audit_max_bytes() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_audit_max_files() -> u32 {
+    5
+}
+
+fn default_telemetry_service_name() -> String {
+    DEFAULT_SERVICE_NAME.to_string()
+}
+
+fn default_telemetry_metrics_interval_secs() -> u64 {
+    30
+}
+
+fn default_vault_approle_mount() -> String {
+    "approle".to_string()
+}
+
+fn default_vault_cache_ttl_secs() -> u64 {
+    300
+}
+
+fn default_vault_timeout_secs() -> u64 {
+    5
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
@@ -258,6 +1255,7 @@ mod tests {
             rnextkeyid: None,
             mac_alg: "hmac-sha256".to_string(),
             key_source: KeySource("env:TCPAO_KEY".to_string()),
+            enforcement: Enforcement::Required,
         }
     }
 
@@ -267,12 +1265,28 @@ mod tests {
             initiator: Some(InitiatorConfig {
                 listen_plain: "127.0.0.1:5000".to_string(),
                 remote_ao: "127.0.0.1:1790".to_string(),
+                allowed_clients: Vec::new(),
+                allowed_uids: Vec::new(),
+                allowed_gids: Vec::new(),
+                accept_proxy_protocol: false,
+                timeouts: SessionTimeouts::default(),
+                buffers: BufferLimits::default(),
             }),
             terminator: Some(TerminatorConfig {
                 listen_ao: "0.0.0.0:1790".to_string(),
+                fallback_listen_ao: None,
                 forward_plain: "127.0.0.1:11019".to_string(),
+                send_proxy_protocol: false,
+                timeouts: SessionTimeouts::default(),
+                buffers: BufferLimits::default(),
+                health_check: HealthCheck::default(),
+                spool: None,
             }),
             ao_policy,
+            vault: None,
+            admin: None,
+            audit: None,
+            telemetry: None,
         }
     }
 
@@ -285,12 +1299,366 @@ mod tests {
         }
     }
 
+    #[test]
+    fn key_source_exec_is_parsed_with_args() {
+        let source = KeySource("exec:/usr/local/bin/key-helper --peer bmp-1".to_string());
+        match source.kind().expect("valid key source") {
+            KeySourceKind::Exec { program, args } => {
+                assert_eq!(program, PathBuf::from("/usr/local/bin/key-helper"));
+                assert_eq!(args, vec!["--peer".to_string(), "bmp-1".to_string()]);
+            }
+            _ => panic!("unexpected key source kind"),
+        }
+    }
+
+    #[test]
+    fn key_source_exec_args_can_be_quoted() {
+        let source = KeySource(
+            r#"exec:/usr/local/bin/key-helper --peer 'bmp 1' "a \"b\"" c\ d ''"#.to_string(),
+        );
+        match source.kind().expect("valid key source") {
+            KeySourceKind::Exec { args, .. } => {
+                assert_eq!(args, vec!["--peer", "bmp 1", "a \"b\"", "c d", ""]);
+            }
+            _ => panic!("unexpected key source kind"),
+        }
+
+        assert!(KeySource("exec:/bin/echo 'open".to_string())
+            .kind()
+            .is_err());
+        assert!(KeySource("exec:/bin/echo trailing\\".to_string())
+            .kind()
+            .is_err());
+    }
+
+    #[test]
+    fn key_source_exec_requires_absolute_path() {
+        let source = KeySource("exec:key-helper".to_string());
+        assert!(source.kind().is_err());
+    }
+
+    #[test]
+    fn key_source_exec_reads_stdout_without_trailing_newline() {
+        let source = KeySource("exec:/bin/echo exec-key".to_string());
+        let key = source.load_key().expect("helper output");
+        assert_eq!(key.as_bytes(), b"exec-key");
+    }
+
+    #[test]
+    fn key_source_exec_fails_on_nonzero_exit() {
+        let source = KeySource("exec:/bin/false".to_string());
+        assert!(source.load_key().is_err());
+    }
+
+    #[test]
+    fn key_source_debug_redacts_exec_arguments() {
+        let source = KeySource("exec:/usr/local/bin/key-helper --key hunter2".to_string());
+        let rendered = format!("{source:?}");
+        assert!(!rendered.contains("hunter2"));
+        assert!(rendered.contains("2 args redacted"));
+
+        let mut p = policy("peer-a", "10.0.0.2", None);
+        p.key_source = source;
+        assert!(!format!("{p:?}").contains("hunter2"));
+    }
+
+    #[test]
+    fn key_source_credential_rejects_paths() {
+        assert!(KeySource("credential:../etc/passwd".to_string())
+            .kind()
+            .is_err());
+        assert!(KeySource("credential:".to_string()).kind().is_err());
+        match KeySource("credential:bmp-key".to_string())
+            .kind()
+            .expect("valid key source")
+        {
+            KeySourceKind::Credential(name) => assert_eq!(name, "bmp-key"),
+            _ => panic!("unexpected key source kind"),
+        }
+    }
+
+    #[test]
+    fn key_source_dir_is_watched_and_loaded() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let entry = dir.path().join("bmp-peer-1");
+        fs::write(&entry, b"dir-key").expect("write key");
+
+        let source = KeySource(format!("dir:{}", entry.display()));
+        let kind = source.kind().expect("valid key source");
+        assert!(kind.is_watched());
+        assert_eq!(source.load_key().expect("key bytes").as_bytes(), b"dir-key");
+    }
+
     #[test]
     fn key_source_rejects_invalid_prefix() {
-        let source = KeySource("vault:secret/path".to_string());
+        let source = KeySource("aws-sm:secret/path".to_string());
         assert!(source.kind().is_err());
     }
 
+    #[test]
+    fn key_source_vault_defaults_field_to_key() {
+        match KeySource("vault:/secret/data/bmp-peer-1".to_string())
+            .kind()
+            .expect("valid key source")
+        {
+            KeySourceKind::Vault { path, field } => {
+                assert_eq!(path, "secret/data/bmp-peer-1");
+                assert_eq!(field, "key");
+            }
+            _ => panic!("unexpected key source kind"),
+        }
+
+        match KeySource("vault:secret/data/bmp#ao".to_string())
+            .kind()
+            .expect("valid key source")
+        {
+            KeySourceKind::Vault { field, .. } => assert_eq!(field, "ao"),
+            _ => panic!("unexpected key source kind"),
+        }
+    }
+
+    #[test]
+    fn validate_requires_vault_section_for_vault_sources() {
+        let mut p = policy("peer-a", "10.0.0.2", None);
+        p.key_source = KeySource("vault:secret/data/bmp".to_string());
+        let cfg = base_config(vec![p]);
+
+        let err = cfg
+            .validate(Mode::Initiator)
+            .expect_err("missing vault section must fail");
+        assert!(err.to_string().contains("no [vault] section"));
+    }
+
+    #[test]
+    fn vault_config_requires_exactly_one_auth_method() {
+        let raw = "address = \"http://127.0.0.1:8200\"\n";
+        let cfg: VaultConfig = toml::from_str(raw).expect("valid vault config");
+        assert!(cfg.validate().is_err());
+
+        let raw = "address = \"http://127.0.0.1:8200\"\ntoken_source = \"env:VAULT_TOKEN\"\non_failure = \"last-known-good\"\n";
+        let cfg: VaultConfig = toml::from_str(raw).expect("valid vault config");
+        assert!(cfg.validate().is_ok());
+        assert_eq!(cfg.on_failure, KeyFailureMode::LastKnownGood);
+    }
+
+    #[test]
+    fn vault_https_needs_the_tls_feature_and_ca_cert_needs_https() {
+        let raw = "address = \"https://vault.example:8200\"\ntoken_source = \"env:VAULT_TOKEN\"\n";
+        let cfg: VaultConfig = toml::from_str(raw).expect("valid vault config");
+        assert_eq!(cfg.validate().is_ok(), cfg!(feature = "tls"));
+
+        let raw = "address = \"http://127.0.0.1:8200\"\nca_cert = \"/etc/vault/ca.pem\"\ntoken_source = \"env:VAULT_TOKEN\"\n";
+        let cfg: VaultConfig = toml::from_str(raw).expect("valid vault config");
+        let err = cfg.validate().expect_err("ca_cert without https must fail");
+        assert!(err.to_string().contains("https://"));
+    }
+
+    #[test]
+    fn initiator_client_acl_is_validated() {
+        let mut cfg = base_config(vec![policy("peer-a", "192.0.2.10", None)]);
+        let initiator = cfg.initiator.as_mut().expect("initiator config");
+        initiator.allowed_clients = vec!["127.0.0.0/8".to_string(), "::1".to_string()];
+        let acl = initiator.client_acl().expect("valid acl");
+        assert!(acl.allows_addr("127.0.0.2".parse().expect("valid ip")));
+        assert!(!acl.allows_addr("10.0.0.1".parse().expect("valid ip")));
+        assert!(cfg.validate(Mode::Initiator).is_ok());
+
+        let initiator = cfg.initiator.as_mut().expect("initiator config");
+        initiator.allowed_clients = vec!["127.0.0.0/40".to_string()];
+        assert!(cfg.validate(Mode::Initiator).is_err());
+
+        let initiator = cfg.initiator.as_mut().expect("initiator config");
+        initiator.allowed_clients.clear();
+        initiator.allowed_uids = vec![0];
+        assert!(cfg.validate(Mode::Initiator).is_err());
+
+        let initiator = cfg.initiator.as_mut().expect("initiator config");
+        initiator.listen_plain = "unix:/run/tcpao/bmp.sock".to_string();
+        assert!(cfg.validate(Mode::Initiator).is_ok());
+
+        let initiator = cfg.initiator.as_mut().expect("initiator config");
+        initiator.allowed_clients = vec!["127.0.0.0/8".to_string()];
+        assert!(cfg.validate(Mode::Initiator).is_err());
+    }
+
+    #[test]
+    fn global_admission_limits_parse_and_validate() {
+        let cfg: GlobalConfig = toml::from_str(
+            r#"
+max_connections = 512
+max_connections_per_peer = 4
+accept_rate_per_sec = 20
+on_connection_limit = "evict-oldest"
+"#,
+        )
+        .expect("valid global config");
+        assert_eq!(cfg.max_connections, Some(512));
+        assert_eq!(cfg.on_connection_limit, LimitAction::EvictOldest);
+        assert!(cfg.validate().is_ok());
+
+        let zero = GlobalConfig {
+            max_connections_per_peer: Some(0),
+            ..GlobalConfig::default()
+        };
+        assert!(zero.validate().is_err());
+
+        let burst_only = GlobalConfig {
+            accept_burst: Some(10),
+            ..GlobalConfig::default()
+        };
+        assert!(burst_only.validate().is_err());
+    }
+
+    #[test]
+    fn service_timeouts_override_global_ones() {
+        let cfg: Config = toml::from_str(
+            r#"
+[global]
+idle_timeout_secs = 120
+idle_timeout_down_secs = 600
+max_session_lifetime_secs = 86400
+
+[terminator]
+listen_ao = "0.0.0.0:1790"
+forward_plain = "127.0.0.1:11019"
+idle_timeout_secs = 0
+idle_timeout_up_secs = 30
+"#,
+        )
+        .expect("valid config");
+        let terminator = cfg.terminator.as_ref().expect("terminator section");
+        assert_eq!(terminator.timeouts.idle_timeout_up_secs, Some(30));
+
+        let opts = cfg
+            .global
+            .pump_options(&terminator.timeouts, &terminator.buffers);
+        assert_eq!(opts.idle_timeout, None);
+        assert_eq!(opts.idle_timeout_up, Some(Duration::from_secs(30)));
+        assert_eq!(opts.idle_timeout_down, Some(Duration::from_secs(600)));
+        assert_eq!(opts.max_lifetime, Some(Duration::from_secs(86400)));
+
+        let defaults = cfg
+            .global
+            .pump_options(&SessionTimeouts::default(), &BufferLimits::default());
+        assert_eq!(defaults.idle_timeout, Some(Duration::from_secs(120)));
+        assert_eq!(defaults.idle_timeout_up, None);
+    }
+
+    #[test]
+    fn buffer_limits_parse_per_mode_and_validate() {
+        let cfg: Config = toml::from_str(
+            r#"
+[initiator]
+listen_plain = "127.0.0.1:5000"
+remote_ao = "10.0.0.2:1790"
+buffer_size = 65536
+max_buffered_bytes = 4194304
+"#,
+        )
+        .expect("valid config");
+        let initiator = cfg.initiator.as_ref().expect("initiator section");
+        assert_eq!(initiator.buffers.buffer_size, 65536);
+        assert!(initiator.buffers.validate("initiator").is_ok());
+        assert!(!cfg.global.zero_copy, "splice is opt-in");
+        assert_eq!(
+            cfg.global.zero_copy,
+            PumpOptions::default().zero_copy,
+            "config and pump defaults agree"
+        );
+
+        let too_small = BufferLimits {
+            buffer_size: 65536,
+            max_buffered_bytes: 1024,
+        };
+        assert!(too_small.validate("initiator").is_err());
+        let zero = BufferLimits {
+            buffer_size: 0,
+            ..BufferLimits::default()
+        };
+        assert!(zero.validate("terminator").is_err());
+    }
+
+    #[test]
+    fn admin_listener_must_be_local() {
+        let admin = |listen: &str| AdminConfig {
+            listen: listen.to_string(),
+        };
+        assert!(admin("127.0.0.1:9901").listen_endpoint().is_ok());
+        assert!(admin("[::1]:9901").listen_endpoint().is_ok());
+        assert!(admin("unix:/run/tcpao/admin.sock")
+            .listen_endpoint()
+            .is_ok());
+        assert!(admin("0.0.0.0:9901").listen_endpoint().is_err());
+
+        let mut cfg = base_config(vec![policy("peer-a", "10.0.0.2", None)]);
+        cfg.admin = Some(admin("192.0.2.1:9901"));
+        assert!(cfg.validate(Mode::Terminator).is_err());
+    }
+
+    #[test]
+    fn audit_file_sink_needs_a_path() {
+        let audit: AuditConfig = toml::from_str("sink = \"file\"").expect("audit config");
+        assert_eq!(audit.max_files, 5);
+        assert!(audit.validate().is_err());
+
+        let audit: AuditConfig = toml::from_str("sink = \"syslog\"").expect("audit config");
+        assert!(audit.validate().is_ok());
+        assert_eq!(audit.sink_path(), Path::new("/dev/log"));
+    }
+
+    #[test]
+    fn health_check_is_off_by_default_and_needs_a_positive_interval() {
+        let check: HealthCheck = toml::from_str("").expect("health check");
+        assert_eq!(check.interval(), None);
+        assert!(check.validate().is_ok());
+
+        let check: HealthCheck =
+            toml::from_str("health_check_interval_secs = 0").expect("health check");
+        assert!(check.validate().is_err());
+
+        let check: HealthCheck =
+            toml::from_str("health_check_interval_secs = 5").expect("health check");
+        assert_eq!(check.interval(), Some(Duration::from_secs(5)));
+        assert_eq!(check.health_check_failures, 2);
+    }
+
+    #[test]
+    fn spool_section_parses_with_defaults_and_validates() {
+        let cfg: Config = toml::from_str(
+            r#"
+[terminator]
+listen_ao = "0.0.0.0:1790"
+forward_plain = "127.0.0.1:11019"
+
+[terminator.spool]
+dir = "/var/spool/tcpao-proxy"
+overflow = "block"
+"#,
+        )
+        .expect("valid config");
+        let spool = cfg
+            .terminator
+            .as_ref()
+            .and_then(|t| t.spool.clone())
+            .expect("spool section");
+        assert_eq!(spool.overflow, SpoolOverflow::Block);
+        assert_eq!(spool.max_bytes, 64 * 1024 * 1024);
+        assert_eq!(spool.reconnect_initial(), Duration::from_millis(250));
+        assert!(spool.validate().is_ok());
+
+        let tiny = SpoolConfig {
+            max_bytes: 4096,
+            ..spool.clone()
+        };
+        assert!(tiny.validate().is_err());
+        let backwards = SpoolConfig {
+            reconnect_initial_ms: 60_000,
+            reconnect_max_secs: 1,
+            ..spool
+        };
+        assert!(backwards.validate().is_err());
+    }
+
     #[test]
     fn validate_rejects_duplicate_policy_names() {
         let cfg = base_config(vec![
@@ -330,6 +1698,46 @@ mod tests {
         assert!(err.to_string().contains("duplicate ao_policy peer tuple"));
     }
 
+    #[test]
+    fn enforcement_defaults_to_required_and_is_summarized() {
+        let raw = "[[ao_policy]]\nname = \"a\"\npeer_ip = \"10.0.0.2\"\nkeyid = 1\nmac_alg = \"hmac-sha256\"\nkey_source = \"env:K\"\n\n[[ao_policy]]\nname = \"b\"\npeer_ip = \"10.0.0.3\"\nkeyid = 1\nmac_alg = \"hmac-sha256\"\nkey_source = \"env:K\"\nenforcement = \"monitor\"\n";
+        let cfg: Config = toml::from_str(raw).expect("valid config");
+
+        assert_eq!(cfg.ao_policy[0].enforcement, Enforcement::Required);
+        assert_eq!(cfg.ao_policy[1].enforcement, Enforcement::Monitor);
+        assert_eq!(cfg.relaxed_policies().count(), 1);
+        assert!(cfg
+            .redacted_summary()
+            .contains("enforcement=required:1,preferred:0,monitor:1"));
+    }
+
+    #[test]
+    fn enforcement_rejects_unknown_level() {
+        let raw = "[[ao_policy]]\nname = \"a\"\npeer_ip = \"10.0.0.2\"\nkeyid = 1\nmac_alg = \"hmac-sha256\"\nkey_source = \"env:K\"\nenforcement = \"off\"\n";
+        assert!(toml::from_str::<Config>(raw).is_err());
+    }
+
+    #[test]
+    fn preferred_terminator_policies_need_the_fallback_listener() {
+        let mut preferred = policy("peer-a", "10.0.0.2", None);
+        preferred.enforcement = Enforcement::Preferred;
+        let mut cfg = base_config(vec![preferred]);
+
+        assert!(cfg.validate(Mode::Initiator).is_ok());
+        let err = cfg
+            .validate(Mode::Terminator)
+            .expect_err("preferred without fallback_listen_ao");
+        assert!(err.to_string().contains("fallback_listen_ao"), "{err}");
+
+        let terminator = cfg.terminator.as_mut().expect("terminator");
+        terminator.fallback_listen_ao = Some("0.0.0.0:1791".to_string());
+        assert!(cfg.validate(Mode::Terminator).is_ok());
+
+        let terminator = cfg.terminator.as_mut().expect("terminator");
+        terminator.fallback_listen_ao = Some("not-an-address".to_string());
+        assert!(cfg.validate(Mode::Terminator).is_err());
+    }
+
     #[test]
     fn validate_accepts_unique_names_and_peer_tuples() {
         let cfg = base_config(vec![
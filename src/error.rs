@@ -24,6 +24,9 @@ pub enum ProxyError {
     #[error("no AO policy matched peer {0}")]
     NoPolicyForPeer(String),
 
+    #[error("admin api returned {0}: {1}")]
+    Admin(u16, String),
+
     #[error("tcp-ao unsupported or not configured: {0}")]
     TcpAo(String),
 }
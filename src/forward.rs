@@ -1,114 +1,591 @@
+use std::future::Future;
 use std::io;
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::debug;
 
+use crate::splice::{self, SpliceIo};
+
+pub const DEFAULT_BUFFER_SIZE: usize = 16 * 1024;
+pub const DEFAULT_MAX_BUFFERED_BYTES: usize = 256 * 1024;
+
+/// Session timers and buffering. Every timer is off when `None`.
+///
+/// "Up" is source -> destination, which both modes orient as router ->
+/// collector, so for BMP `idle_timeout_down` usually stays unset.
 #[derive(Debug, Clone, Copy)]
 pub struct PumpOptions {
+    /// No bytes in either direction.
     pub idle_timeout: Option<Duration>,
+    /// No bytes source -> destination while that direction is still open.
+    pub idle_timeout_up: Option<Duration>,
+    /// No bytes destination -> source while that direction is still open.
+    pub idle_timeout_down: Option<Duration>,
+    /// Hard cap on session duration, regardless of traffic.
+    pub max_lifetime: Option<Duration>,
+    /// Bytes per read in the copy loop.
+    pub buffer_size: usize,
+    /// Bytes the copy loop may hold per direction, read but not yet written,
+    /// before it stops reading. At least `buffer_size`.
+    pub max_buffered_bytes: usize,
+    /// Use the `splice(2)` fast path when the platform supports it.
+    pub zero_copy: bool,
 }
 
-#[derive(Debug, Clone, Copy)]
+impl Default for PumpOptions {
+    fn default() -> Self {
+        Self {
+            idle_timeout: None,
+            idle_timeout_up: None,
+            idle_timeout_down: None,
+            max_lifetime: None,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            max_buffered_bytes: DEFAULT_MAX_BUFFERED_BYTES,
+            zero_copy: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CloseReason {
-    SourceEof,
-    DestinationEof,
+    /// Both directions reached EOF.
+    Completed,
     IdleTimeout,
+    IdleTimeoutUp,
+    IdleTimeoutDown,
+    MaxLifetime,
+    /// One direction failed and the other was torn down with it.
+    Error,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// One end of a forwarded session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Source,
+    Destination,
+}
+
+#[derive(Debug)]
 pub struct PumpStats {
     pub bytes_up: u64,
     pub bytes_down: u64,
     pub reason: CloseReason,
+    /// The side whose outgoing direction finished first, by EOF or error.
+    /// `None` when the session timed out with both directions still open.
+    pub first_closed: Option<Side>,
+    /// Last error seen on the source -> destination direction.
+    pub up_error: Option<io::Error>,
+    /// Last error seen on the destination -> source direction.
+    pub down_error: Option<io::Error>,
+    /// Most bytes held between read and write at once, per direction. For
+    /// the splice path this is what sat in the pipe.
+    pub high_water_up: u64,
+    pub high_water_down: u64,
+    /// Time spent waiting for the destination (up) or source (down) to take
+    /// bytes. A large value points at that side as the bottleneck.
+    pub write_blocked_up: Duration,
+    pub write_blocked_down: Duration,
     pub duration: Duration,
 }
 
-pub async fn pump(
-    mut source: TcpStream,
-    mut destination: TcpStream,
+/// Forwards between two sockets, using the zero-copy splice path when
+/// `opts.zero_copy` is set and pipes can be created, and the copy loop in
+/// [`pump`] otherwise.
+pub async fn forward<S, D>(
+    source: S,
+    destination: D,
     opts: PumpOptions,
-) -> io::Result<PumpStats> {
-    let mut source_to_destination = 0_u64;
-    let mut destination_to_source = 0_u64;
-    let started = Instant::now();
-
-    let mut source_buf = vec![0_u8; 16 * 1024];
-    let mut destination_buf = vec![0_u8; 16 * 1024];
-
-    loop {
-        if let Some(timeout) = opts.idle_timeout {
-            tokio::select! {
-                source_read = source.read(&mut source_buf) => {
-                    let count = source_read?;
-                    if count == 0 {
-                        let _ = destination.shutdown().await;
-                        return Ok(PumpStats {
-                            bytes_up: source_to_destination,
-                            bytes_down: destination_to_source,
-                            reason: CloseReason::SourceEof,
-                            duration: started.elapsed(),
-                        });
-                    }
-                    destination.write_all(&source_buf[..count]).await?;
-                    source_to_destination += count as u64;
-                }
-                destination_read = destination.read(&mut destination_buf) => {
-                    let count = destination_read?;
-                    if count == 0 {
-                        let _ = source.shutdown().await;
-                        return Ok(PumpStats {
-                            bytes_up: source_to_destination,
-                            bytes_down: destination_to_source,
-                            reason: CloseReason::DestinationEof,
-                            duration: started.elapsed(),
-                        });
-                    }
-                    source.write_all(&destination_buf[..count]).await?;
-                    destination_to_source += count as u64;
-                }
-                _ = tokio::time::sleep(timeout) => {
-                    let _ = source.shutdown().await;
-                    let _ = destination.shutdown().await;
-                    return Ok(PumpStats {
-                        bytes_up: source_to_destination,
-                        bytes_down: destination_to_source,
-                        reason: CloseReason::IdleTimeout,
-                        duration: started.elapsed(),
-                    });
-                }
+    traffic: &Traffic,
+) -> PumpStats
+where
+    S: AsyncRead + AsyncWrite + SpliceIo + Unpin,
+    D: AsyncRead + AsyncWrite + SpliceIo + Unpin,
+{
+    if opts.zero_copy {
+        match splice::pipes(&opts) {
+            Ok(pipes) => return splice::pump(&source, &destination, pipes, opts, traffic).await,
+            Err(err) => debug!(error = %err, "splice unavailable; using copy loop"),
+        }
+    }
+
+    pump(source, destination, opts, traffic).await
+}
+
+/// Userspace copy loop. Kept for platforms without `splice` and for callers
+/// that need to see the bytes.
+///
+/// Each direction runs on its own: EOF from one side half-closes the other
+/// side's write half and leaves the opposite direction running. Within a
+/// direction, reads and writes are decoupled by a queue of at most
+/// `opts.max_buffered_bytes`, so a slow writer only stops reads once the
+/// queue is full. The session
+/// ends once both directions have finished, on the first error, or when one
+/// of the timers in `opts` expires.
+pub async fn pump<S, D>(
+    source: S,
+    destination: D,
+    opts: PumpOptions,
+    traffic: &Traffic,
+) -> PumpStats
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    D: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut source_read, mut source_write) = tokio::io::split(source);
+    let (mut destination_read, mut destination_write) = tokio::io::split(destination);
+    let activity = Activity::new();
+
+    let stats = {
+        let upstream = copy_one_way(
+            &mut source_read,
+            &mut destination_write,
+            &traffic.up,
+            &activity,
+            Direction::Up,
+            opts,
+        );
+        let downstream = copy_one_way(
+            &mut destination_read,
+            &mut source_write,
+            &traffic.down,
+            &activity,
+            Direction::Down,
+            opts,
+        );
+        drive(upstream, downstream, traffic, &activity, opts).await
+    };
+
+    if stats.reason != CloseReason::Completed {
+        let _ = source_write.shutdown().await;
+        let _ = destination_write.shutdown().await;
+    }
+    stats
+}
+
+async fn copy_one_way<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    flow: &Flow,
+    activity: &Activity,
+    direction: Direction,
+    opts: PumpOptions,
+) -> io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let buffer_size = opts.buffer_size.max(1);
+    let budget = Semaphore::new(opts.max_buffered_bytes.max(buffer_size));
+    let (queue, mut pending) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    let read_side = async {
+        // Dropping the sender at EOF is what tells the writer to finish.
+        let queue = queue;
+        loop {
+            // Reserve a full buffer before reading so the queue never holds
+            // more than the budget, then hand back what the read did not use.
+            budget
+                .acquire_many(buffer_size as u32)
+                .await
+                .expect("budget semaphore is never closed")
+                .forget();
+            let mut buf = vec![0_u8; buffer_size];
+            let count = reader.read(&mut buf).await?;
+            budget.add_permits(buffer_size - count);
+            if count == 0 {
+                return Ok(());
+            }
+            buf.truncate(count);
+            flow.queued(count);
+            activity.touch(direction);
+            if queue.send(buf).is_err() {
+                return Ok(());
+            }
+        }
+    };
+
+    let write_side = async {
+        while let Some(chunk) = pending.recv().await {
+            let started = Instant::now();
+            writer.write_all(&chunk).await?;
+            flow.written(chunk.len(), started.elapsed());
+            budget.add_permits(chunk.len());
+            activity.touch(direction);
+        }
+        // Best effort, as the peer may already be gone entirely.
+        let _ = writer.shutdown().await;
+        Ok(())
+    };
+
+    tokio::try_join!(read_side, write_side).map(|_| ())
+}
+
+/// Counters for one direction of a session.
+#[derive(Debug, Default)]
+pub(crate) struct Flow {
+    bytes: AtomicU64,
+    buffered: AtomicU64,
+    high_water: AtomicU64,
+    write_blocked_us: AtomicU64,
+}
+
+impl Flow {
+    /// `len` bytes were read and are waiting to be written.
+    pub(crate) fn queued(&self, len: usize) {
+        let buffered = self.buffered.fetch_add(len as u64, Ordering::Relaxed) + len as u64;
+        self.high_water.fetch_max(buffered, Ordering::Relaxed);
+    }
+
+    /// `len` queued bytes were written after `blocked` spent waiting on the
+    /// writer.
+    pub(crate) fn written(&self, len: usize, blocked: Duration) {
+        self.buffered.fetch_sub(len as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(len as u64, Ordering::Relaxed);
+        self.write_blocked_us
+            .fetch_add(blocked.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    fn high_water(&self) -> u64 {
+        self.high_water.load(Ordering::Relaxed)
+    }
+
+    fn write_blocked(&self) -> Duration {
+        Duration::from_micros(self.write_blocked_us.load(Ordering::Relaxed))
+    }
+}
+
+/// Live byte counters for a session, readable while it is being forwarded.
+#[derive(Debug, Default)]
+pub struct Traffic {
+    pub(crate) up: Flow,
+    pub(crate) down: Flow,
+}
+
+impl Traffic {
+    pub fn bytes_up(&self) -> u64 {
+        self.up.bytes()
+    }
+
+    pub fn bytes_down(&self) -> u64 {
+        self.down.bytes()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Direction {
+    Up,
+    Down,
+}
+
+/// Per-direction time of the last byte moved, in milliseconds since the
+/// session started, plus whether the direction has finished.
+pub(crate) struct Activity {
+    started: Instant,
+    last_ms: [AtomicU64; 2],
+    finished: [AtomicBool; 2],
+}
+
+impl Activity {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            last_ms: [AtomicU64::new(0), AtomicU64::new(0)],
+            finished: [AtomicBool::new(false), AtomicBool::new(false)],
+        }
+    }
+
+    pub(crate) fn touch(&self, direction: Direction) {
+        self.last_ms[direction as usize]
+            .store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish(&self, direction: Direction) {
+        self.finished[direction as usize].store(true, Ordering::Relaxed);
+    }
+
+    /// Earliest armed deadline, as an offset from `started`. A direction's
+    /// own idle timer is disarmed once it has finished.
+    fn next_deadline(&self, opts: &PumpOptions) -> Option<(Duration, CloseReason)> {
+        let last = |direction: Direction| {
+            Duration::from_millis(self.last_ms[direction as usize].load(Ordering::Relaxed))
+        };
+        let open =
+            |direction: Direction| !self.finished[direction as usize].load(Ordering::Relaxed);
+        let up = last(Direction::Up);
+        let down = last(Direction::Down);
+
+        [
+            (opts.max_lifetime, Duration::ZERO, CloseReason::MaxLifetime),
+            (opts.idle_timeout, up.max(down), CloseReason::IdleTimeout),
+            (
+                opts.idle_timeout_up.filter(|_| open(Direction::Up)),
+                up,
+                CloseReason::IdleTimeoutUp,
+            ),
+            (
+                opts.idle_timeout_down.filter(|_| open(Direction::Down)),
+                down,
+                CloseReason::IdleTimeoutDown,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(timeout, since, reason)| Some((since + timeout?, reason)))
+        .min_by_key(|(deadline, _)| *deadline)
+    }
+
+    /// Resolves with the reason of the first timer to expire; never when no
+    /// timer is armed.
+    pub(crate) async fn expired(&self, opts: &PumpOptions) -> CloseReason {
+        loop {
+            let Some((deadline, reason)) = self.next_deadline(opts) else {
+                return std::future::pending().await;
+            };
+            if self.started.elapsed() >= deadline {
+                return reason;
             }
-        } else {
-            tokio::select! {
-                source_read = source.read(&mut source_buf) => {
-                    let count = source_read?;
-                    if count == 0 {
-                        let _ = destination.shutdown().await;
-                        return Ok(PumpStats {
-                            bytes_up: source_to_destination,
-                            bytes_down: destination_to_source,
-                            reason: CloseReason::SourceEof,
-                            duration: started.elapsed(),
-                        });
-                    }
-                    destination.write_all(&source_buf[..count]).await?;
-                    source_to_destination += count as u64;
+            // Activity may push the deadline out while we sleep; it is
+            // recomputed on wake-up.
+            tokio::time::sleep_until((self.started + deadline).into()).await;
+        }
+    }
+}
+
+/// Runs both directions until they have each finished, one fails, or the
+/// a timer expires. Directions are expected to half-close their writer on
+/// EOF themselves; tearing down after an error or timeout is left to the
+/// caller.
+pub(crate) async fn drive<U, D>(
+    upstream: U,
+    downstream: D,
+    traffic: &Traffic,
+    activity: &Activity,
+    opts: PumpOptions,
+) -> PumpStats
+where
+    U: Future<Output = io::Result<()>>,
+    D: Future<Output = io::Result<()>>,
+{
+    let mut upstream = pin!(upstream);
+    let mut downstream = pin!(downstream);
+    let mut timers = pin!(activity.expired(&opts));
+
+    let mut up_done = false;
+    let mut down_done = false;
+    let mut first_closed = None;
+    let mut up_error = None;
+    let mut down_error = None;
+
+    let reason = loop {
+        tokio::select! {
+            result = &mut upstream, if !up_done => {
+                up_done = true;
+                activity.finish(Direction::Up);
+                first_closed.get_or_insert(Side::Source);
+                if let Err(err) = result {
+                    up_error = Some(err);
+                    break CloseReason::Error;
                 }
-                destination_read = destination.read(&mut destination_buf) => {
-                    let count = destination_read?;
-                    if count == 0 {
-                        let _ = source.shutdown().await;
-                        return Ok(PumpStats {
-                            bytes_up: source_to_destination,
-                            bytes_down: destination_to_source,
-                            reason: CloseReason::DestinationEof,
-                            duration: started.elapsed(),
-                        });
-                    }
-                    source.write_all(&destination_buf[..count]).await?;
-                    destination_to_source += count as u64;
+            }
+            result = &mut downstream, if !down_done => {
+                down_done = true;
+                activity.finish(Direction::Down);
+                first_closed.get_or_insert(Side::Destination);
+                if let Err(err) = result {
+                    down_error = Some(err);
+                    break CloseReason::Error;
                 }
             }
+            reason = &mut timers => break reason,
+        }
+        if up_done && down_done {
+            break CloseReason::Completed;
+        }
+    };
+
+    PumpStats::collect(
+        traffic,
+        activity,
+        reason,
+        first_closed,
+        up_error,
+        down_error,
+    )
+}
+
+impl PumpStats {
+    /// Final stats of a session whose counters are in `traffic`.
+    pub(crate) fn collect(
+        traffic: &Traffic,
+        activity: &Activity,
+        reason: CloseReason,
+        first_closed: Option<Side>,
+        up_error: Option<io::Error>,
+        down_error: Option<io::Error>,
+    ) -> Self {
+        PumpStats {
+            bytes_up: traffic.up.bytes(),
+            bytes_down: traffic.down.bytes(),
+            reason,
+            first_closed,
+            up_error,
+            down_error,
+            high_water_up: traffic.up.high_water(),
+            high_water_down: traffic.down.high_water(),
+            write_blocked_up: traffic.up.write_blocked(),
+            write_blocked_down: traffic.down.write_blocked(),
+            duration: activity.started.elapsed(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{duplex, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn reply_after_half_close_is_delivered() {
+        let (mut router, proxy_in) = duplex(64 * 1024);
+        let (proxy_out, mut collector) = duplex(64 * 1024);
+        let task = tokio::spawn(async move {
+            pump(
+                proxy_in,
+                proxy_out,
+                PumpOptions::default(),
+                &Traffic::default(),
+            )
+            .await
+        });
+
+        router.write_all(b"bmp").await.expect("write");
+        router.shutdown().await.expect("half-close");
+
+        let mut got = Vec::new();
+        collector.read_to_end(&mut got).await.expect("read to eof");
+        assert_eq!(got, b"bmp");
+        collector.write_all(b"late reply").await.expect("reply");
+        collector.shutdown().await.expect("collector close");
+
+        let mut reply = Vec::new();
+        router.read_to_end(&mut reply).await.expect("read reply");
+        assert_eq!(reply, b"late reply");
+
+        let stats = task.await.expect("pump task");
+        assert_eq!(stats.reason, CloseReason::Completed);
+        assert_eq!(stats.first_closed, Some(Side::Source));
+        assert_eq!((stats.bytes_up, stats.bytes_down), (3, 10));
+        assert!(stats.up_error.is_none() && stats.down_error.is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_collector_does_not_stall_the_reply_direction() {
+        let (mut router, proxy_in) = duplex(64 * 1024);
+        let (proxy_out, mut collector) = duplex(1024);
+        let opts = PumpOptions {
+            buffer_size: 1024,
+            max_buffered_bytes: 8 * 1024,
+            ..PumpOptions::default()
+        };
+        let task =
+            tokio::spawn(async move { pump(proxy_in, proxy_out, opts, &Traffic::default()).await });
+
+        // Far more than the collector pipe plus the queue can hold.
+        router.write_all(&[7_u8; 48 * 1024]).await.expect("write");
+        collector.write_all(b"reply").await.expect("reply");
+        let mut reply = [0_u8; 5];
+        router
+            .read_exact(&mut reply)
+            .await
+            .expect("reply while up is stuck");
+        assert_eq!(&reply, b"reply");
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        router.shutdown().await.expect("router close");
+        let mut got = Vec::new();
+        collector.read_to_end(&mut got).await.expect("drain");
+        assert_eq!(got.len(), 48 * 1024);
+        collector.shutdown().await.expect("collector close");
+
+        let stats = task.await.expect("pump task");
+        assert_eq!(stats.reason, CloseReason::Completed);
+        assert!(stats.high_water_up > 0 && stats.high_water_up <= 8 * 1024);
+        assert!(stats.write_blocked_up >= Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn idle_timeout_ends_half_open_session() {
+        let (_router, proxy_in) = duplex(1024);
+        let (proxy_out, mut collector) = duplex(1024);
+        collector.shutdown().await.expect("collector close");
+
+        let opts = PumpOptions {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..PumpOptions::default()
+        };
+        let stats = pump(proxy_in, proxy_out, opts, &Traffic::default()).await;
+        assert_eq!(stats.reason, CloseReason::IdleTimeout);
+        assert_eq!(stats.first_closed, Some(Side::Destination));
+    }
+
+    #[tokio::test]
+    async fn quiet_return_direction_trips_only_its_own_timer() {
+        let (mut router, proxy_in) = duplex(1024);
+        let (proxy_out, mut collector) = duplex(1024);
+        let opts = PumpOptions {
+            idle_timeout: Some(Duration::from_millis(300)),
+            idle_timeout_down: Some(Duration::from_millis(100)),
+            ..PumpOptions::default()
+        };
+        let task =
+            tokio::spawn(async move { pump(proxy_in, proxy_out, opts, &Traffic::default()).await });
+
+        // Router -> collector keeps flowing, so only the down timer fires.
+        let traffic = tokio::spawn(async move {
+            let mut buf = [0_u8; 1];
+            loop {
+                if router.write_all(b"x").await.is_err()
+                    || collector.read_exact(&mut buf).await.is_err()
+                {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(40)).await;
+            }
+        });
+
+        let stats = task.await.expect("pump task");
+        assert_eq!(stats.reason, CloseReason::IdleTimeoutDown);
+        assert!(stats.bytes_up >= 2);
+        traffic.await.expect("traffic task");
+    }
+
+    #[tokio::test]
+    async fn finished_direction_disarms_its_idle_timer() {
+        let (mut router, proxy_in) = duplex(1024);
+        let (proxy_out, mut collector) = duplex(1024);
+        let opts = PumpOptions {
+            idle_timeout_down: Some(Duration::from_millis(50)),
+            max_lifetime: Some(Duration::from_millis(250)),
+            ..PumpOptions::default()
+        };
+        collector.shutdown().await.expect("collector half-close");
+        let task =
+            tokio::spawn(async move { pump(proxy_in, proxy_out, opts, &Traffic::default()).await });
+
+        router.write_all(b"bmp").await.expect("write");
+        let mut buf = [0_u8; 3];
+        collector.read_exact(&mut buf).await.expect("read");
+
+        let stats = task.await.expect("pump task");
+        assert_eq!(stats.reason, CloseReason::MaxLifetime);
+        assert!(stats.duration >= Duration::from_millis(250));
+    }
+}
@@ -0,0 +1,200 @@
+//! Readiness checks behind the admin API's `GET /readyz` and the `probe`
+//! subcommand.
+//!
+//! A proxy is ready when its listener is still listening, TCP-AO is usable
+//! (and, in terminator mode, the listener keys are in place), every policy
+//! has its key and, in terminator mode with health checks on,
+//! `forward_plain` passed its last check. Key and upstream state come from
+//! the key watcher's last reload and the health checker, so a probe never
+//! reads a key source or connects anywhere itself.
+
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+
+use socket2::SockRef;
+
+use crate::tcpao::linux;
+use crate::tcpao::policy::PolicySet;
+use crate::upstream::UpstreamStatus;
+
+/// What a mode hands the admin API so it can tell whether it is ready.
+#[derive(Debug)]
+pub struct Readiness {
+    /// A duplicate of the mode's listener, so the check never outlives it.
+    listener: OwnedFd,
+    /// Whether the listener carries AO keys; the initiator installs its keys
+    /// on each outbound socket instead.
+    ao_listener: bool,
+    forward_plain: Option<UpstreamStatus>,
+}
+
+/// The result of one readiness check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn from_result(name: &'static str, result: Result<(), String>) -> Self {
+        Check {
+            name,
+            error: result.err(),
+        }
+    }
+
+    pub fn ok(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl Readiness {
+    pub fn new(
+        listener: BorrowedFd<'_>,
+        ao_listener: bool,
+        forward_plain: Option<UpstreamStatus>,
+    ) -> io::Result<Self> {
+        Ok(Readiness {
+            listener: listener.try_clone_to_owned()?,
+            ao_listener,
+            forward_plain,
+        })
+    }
+
+    pub fn check(&self, policies: &PolicySet) -> Vec<Check> {
+        let mut checks = vec![
+            Check::from_result("listener", self.check_listener()),
+            Check::from_result("tcp_ao", self.check_tcp_ao(policies)),
+            Check::from_result("key_sources", check_key_sources(policies)),
+        ];
+        if let Some(upstream) = &self.forward_plain {
+            let result = if upstream.is_up() {
+                Ok(())
+            } else {
+                Err(format!("{}: failing health checks", upstream.endpoint()))
+            };
+            checks.push(Check::from_result("forward_plain", result));
+        }
+        checks
+    }
+
+    fn check_listener(&self) -> Result<(), String> {
+        let listening = linux::is_listening(self.listener.as_fd()).map_err(|e| e.to_string())?;
+        if !listening {
+            return Err("listener socket is not listening".to_string());
+        }
+        Ok(())
+    }
+
+    fn check_tcp_ao(&self, policies: &PolicySet) -> Result<(), String> {
+        linux::probe_tcpao_support().map_err(|e| format!("tcp-ao unavailable: {e}"))?;
+        if !self.ao_listener {
+            return Ok(());
+        }
+
+        let addr = SockRef::from(&self.listener)
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_socket())
+            .ok_or_else(|| "ao listener has no inet address".to_string())?;
+        linux::check_listener(
+            self.listener.as_raw_fd(),
+            addr,
+            policies,
+            linux::ListenerRole::Primary,
+        )
+        .map_err(|e| format!("listener ao keys missing: {e}"))
+    }
+}
+
+/// Failures recorded by the last reload; reading a source again here would
+/// put exec helpers and Vault on the probe path.
+fn check_key_sources(policies: &PolicySet) -> Result<(), String> {
+    let failed: Vec<String> = policies
+        .iter()
+        .filter_map(|policy| match (&policy.key_error, &policy.key) {
+            (Some(err), _) => Some(format!("policy '{}': {err}", policy.name())),
+            (None, None) => Some(format!("policy '{}': no key loaded", policy.name())),
+            (None, Some(_)) => None,
+        })
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(failed.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio::sync::watch;
+
+    use super::*;
+    use crate::config::{test_key_source, AoPolicyConfig, KeySource};
+    use crate::plain::PlainEndpoint;
+
+    fn policies(key_source: &str) -> Arc<PolicySet> {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let compiled_with = test_key_source(dir.path(), "health-test-key");
+        let set = PolicySet::compile(&[AoPolicyConfig::for_test(
+            "bmp-peer-1",
+            "10.0.0.2",
+            &compiled_with,
+        )])
+        .expect("compiled policies");
+        // Swap the source afterwards so compile does not fail on it.
+        let mut configs: Vec<AoPolicyConfig> = set.iter().map(|p| p.config.clone()).collect();
+        configs[0].key_source = KeySource(key_source.to_string());
+        Arc::new(set.reload(&configs).expect("reloaded"))
+    }
+
+    fn find<'a>(checks: &'a [Check], name: &str) -> &'a Check {
+        checks
+            .iter()
+            .find(|check| check.name == name)
+            .unwrap_or_else(|| panic!("no {name} check in {checks:?}"))
+    }
+
+    #[tokio::test]
+    async fn reports_listener_keys_and_forward_target() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let endpoint = PlainEndpoint::Tcp("127.0.0.1:11019".parse().expect("addr"));
+        let (upstream, state) = watch::channel(true);
+        let readiness = Readiness::new(
+            listener.as_fd(),
+            false,
+            Some(UpstreamStatus::new(endpoint, state)),
+        )
+        .expect("readiness");
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let key_source = test_key_source(dir.path(), "health-test-key");
+        let checks = readiness.check(&policies(&key_source));
+        for name in ["listener", "key_sources", "forward_plain"] {
+            assert!(find(&checks, name).ok(), "{checks:?}");
+        }
+
+        upstream.send_replace(false);
+        let checks = readiness.check(&policies("env:TCPAO_HEALTH_TEST_MISSING_KEY"));
+        let keys = find(&checks, "key_sources");
+        assert!(
+            keys.error
+                .as_deref()
+                .is_some_and(|e| e.contains("bmp-peer-1")),
+            "{keys:?}"
+        );
+        assert!(!find(&checks, "forward_plain").ok(), "{checks:?}");
+    }
+
+    #[test]
+    fn a_bound_socket_that_is_not_listening_is_not_ready() {
+        let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None)
+            .expect("socket");
+        let fd = OwnedFd::from(socket);
+        let readiness = Readiness::new(fd.as_fd(), false, None).expect("readiness");
+        assert!(readiness.check_listener().is_err());
+    }
+}
@@ -0,0 +1,415 @@
+//! Minimal blocking HTTP/1.1 client used for key fetches.
+//!
+//! Only what the proxy needs is implemented: one request per connection,
+//! `Content-Length` or chunked response bodies of at most [`MAX_BODY`] bytes,
+//! and optional TLS when built with the `tls` feature.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::time::Duration;
+
+use zeroize::Zeroizing;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(raw: &str) -> io::Result<Self> {
+        let (tls, rest) = if let Some(rest) = raw.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = raw.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(invalid(format!(
+                "unsupported url '{raw}'; expected http:// or https://"
+            )));
+        };
+
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, "/"),
+        };
+
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            let end = v6
+                .find(']')
+                .ok_or_else(|| invalid(format!("invalid ipv6 authority in '{raw}'")))?;
+            let port = match &v6[end + 1..] {
+                "" => default_port,
+                p => parse_port(p.trim_start_matches(':'), raw)?,
+            };
+            (v6[..end].to_string(), port)
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host.to_string(), parse_port(port, raw)?),
+                None => (authority.to_string(), default_port),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(invalid(format!("missing host in url '{raw}'")));
+        }
+
+        Ok(Self {
+            tls,
+            host,
+            port,
+            path: path.to_string(),
+        })
+    }
+
+    pub fn join(&self, path: &str) -> Self {
+        let mut joined = self.clone();
+        joined.path = format!(
+            "{}/{}",
+            self.path.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        );
+        joined
+    }
+}
+
+/// Largest response body accepted; key fetches and logins are far smaller.
+pub const MAX_BODY: usize = 1024 * 1024;
+
+/// Certificates trusted for `https://` requests: the bundled webpki roots,
+/// or only those in a PEM `ca_cert` bundle.
+#[derive(Clone)]
+pub struct Trust(tls::Roots);
+
+impl Trust {
+    pub fn new(ca_cert: Option<&Path>) -> io::Result<Self> {
+        tls::roots(ca_cert).map(Self)
+    }
+}
+
+/// A response; the body may hold a key or token and is wiped on drop.
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub body: Zeroizing<Vec<u8>>,
+}
+
+pub fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+    timeout: Duration,
+    trust: &Trust,
+) -> io::Result<Response> {
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| invalid(format!("could not resolve host '{}'", url.host)))?;
+
+    let stream = TcpStream::connect_timeout(&addr, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+
+    // Headers may carry a vault token; the buffer is sized up front so it
+    // never reallocates and leaves an unwiped copy behind.
+    let capacity = 256
+        + url.path.len()
+        + url.host.len()
+        + headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len() + 4)
+            .sum::<usize>();
+    let mut head = Zeroizing::new(String::with_capacity(capacity));
+    let _ = write!(
+        head,
+        "{method} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nUser-Agent: tcpao-proxy\r\n",
+        url.path, url.host
+    );
+    for (name, value) in headers {
+        let _ = write!(head, "{name}: {value}\r\n");
+    }
+    if let Some(body) = body {
+        let _ = write!(head, "Content-Length: {}\r\n", body.len());
+    }
+    head.push_str("\r\n");
+
+    if url.tls {
+        exchange(tls::connect(stream, &url.host, &trust.0)?, &head, body)
+    } else {
+        exchange(stream, &head, body)
+    }
+}
+
+fn exchange<S: Read + Write>(
+    mut stream: S,
+    head: &str,
+    body: Option<&[u8]>,
+) -> io::Result<Response> {
+    stream.write_all(head.as_bytes())?;
+    if let Some(body) = body {
+        stream.write_all(body)?;
+    }
+    stream.flush()?;
+
+    read_response(BufReader::new(stream))
+}
+
+pub fn read_response<R: BufRead>(mut reader: R) -> io::Result<Response> {
+    let mut status_line = String::new();
+    reader.read_line(&mut status_line)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| {
+            invalid(format!(
+                "malformed http status line '{}'",
+                status_line.trim()
+            ))
+        })?;
+
+    let mut content_length = None;
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().ok();
+            } else if name.eq_ignore_ascii_case("transfer-encoding")
+                && value.eq_ignore_ascii_case("chunked")
+            {
+                chunked = true;
+            }
+        }
+    }
+
+    // Bodies are read into buffers sized up front, so a growing Vec never
+    // leaves an unwiped copy of a secret behind.
+    let body = if chunked {
+        read_chunked(&mut reader)?
+    } else if let Some(len) = content_length {
+        if len > MAX_BODY {
+            return Err(too_large());
+        }
+        let mut body = Zeroizing::new(vec![0_u8; len]);
+        reader.read_exact(&mut body)?;
+        body
+    } else {
+        let mut body = Zeroizing::new(Vec::with_capacity(MAX_BODY + 1));
+        reader.take(MAX_BODY as u64 + 1).read_to_end(&mut body)?;
+        if body.len() > MAX_BODY {
+            return Err(too_large());
+        }
+        body
+    };
+
+    Ok(Response { status, body })
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> io::Result<Zeroizing<Vec<u8>>> {
+    let mut body = Zeroizing::new(Vec::with_capacity(MAX_BODY));
+    loop {
+        let mut size_line = String::new();
+        reader.read_line(&mut size_line)?;
+        let size_hex = size_line.trim().split(';').next().unwrap_or_default();
+        let size = usize::from_str_radix(size_hex, 16)
+            .map_err(|_| invalid(format!("malformed chunk size '{}'", size_line.trim())))?;
+        if size == 0 {
+            return Ok(body);
+        }
+
+        let start = body.len();
+        if size > MAX_BODY - start {
+            return Err(too_large());
+        }
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..])?;
+
+        let mut crlf = [0_u8; 2];
+        reader.read_exact(&mut crlf)?;
+    }
+}
+
+fn parse_port(port: &str, raw: &str) -> io::Result<u16> {
+    port.parse::<u16>()
+        .map_err(|_| invalid(format!("invalid port in url '{raw}'")))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("http response body exceeds {MAX_BODY} bytes"),
+    )
+}
+
+#[cfg(feature = "tls")]
+mod tls {
+    use std::io;
+    use std::net::TcpStream;
+    use std::path::Path;
+    use std::sync::{Arc, OnceLock};
+
+    use rustls::pki_types::pem::PemObject;
+    use rustls::pki_types::{CertificateDer, ServerName};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+    pub type Roots = Arc<ClientConfig>;
+
+    pub fn roots(ca_cert: Option<&Path>) -> io::Result<Roots> {
+        static WEBPKI: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+        let Some(path) = ca_cert else {
+            return Ok(Arc::clone(WEBPKI.get_or_init(|| {
+                client_config(RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+                })
+            })));
+        };
+
+        let invalid = |e: &dyn std::fmt::Display| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("ca_cert '{}': {e}", path.display()),
+            )
+        };
+        let mut store = RootCertStore::empty();
+        for cert in CertificateDer::pem_file_iter(path).map_err(|e| invalid(&e))? {
+            store
+                .add(cert.map_err(|e| invalid(&e))?)
+                .map_err(|e| invalid(&e))?;
+        }
+        if store.is_empty() {
+            return Err(invalid(&"no certificates found"));
+        }
+        Ok(client_config(store))
+    }
+
+    fn client_config(roots: RootCertStore) -> Roots {
+        Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        )
+    }
+
+    pub fn connect(
+        stream: TcpStream,
+        host: &str,
+        roots: &Roots,
+    ) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let conn = ClientConnection::new(Arc::clone(roots), name).map_err(io::Error::other)?;
+        Ok(StreamOwned::new(conn, stream))
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod tls {
+    use std::io;
+    use std::net::TcpStream;
+    use std::path::Path;
+
+    pub type Roots = ();
+
+    pub fn roots(ca_cert: Option<&Path>) -> io::Result<Roots> {
+        match ca_cert {
+            Some(path) => Err(unsupported(&format!("ca_cert '{}'", path.display()))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn connect(_stream: TcpStream, host: &str, _roots: &Roots) -> io::Result<TcpStream> {
+        Err(unsupported(&format!("https to '{host}'")))
+    }
+
+    fn unsupported(what: &str) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{what} requires building tcpao-proxy with the 'tls' feature"),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_parses_scheme_host_port_and_path() {
+        let url = Url::parse("https://vault.example:8200/v1").expect("valid url");
+        assert!(url.tls);
+        assert_eq!(url.host, "vault.example");
+        assert_eq!(url.port, 8200);
+        assert_eq!(url.path, "/v1");
+
+        let url = Url::parse("http://127.0.0.1").expect("valid url");
+        assert!(!url.tls);
+        assert_eq!(url.port, 80);
+        assert_eq!(url.path, "/");
+
+        let url = Url::parse("http://[::1]:8200").expect("valid url");
+        assert_eq!(url.host, "::1");
+        assert_eq!(url.port, 8200);
+    }
+
+    #[test]
+    fn url_join_normalizes_slashes() {
+        let url = Url::parse("http://vault:8200/").expect("valid url");
+        assert_eq!(url.join("/v1/secret/data/x").path, "/v1/secret/data/x");
+    }
+
+    #[test]
+    fn response_reads_chunked_body() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nabcd\r\n2\r\nef\r\n0\r\n\r\n";
+        let resp = read_response(&raw[..]).expect("valid response");
+        assert_eq!(resp.status, 200);
+        assert_eq!(resp.body.as_slice(), b"abcdef");
+    }
+
+    #[test]
+    fn response_reads_content_length_body() {
+        let raw = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 2\r\n\r\n{}trailing";
+        let resp = read_response(&raw[..]).expect("valid response");
+        assert_eq!(resp.status, 403);
+        assert_eq!(resp.body.as_slice(), b"{}");
+    }
+
+    #[test]
+    fn response_rejects_bodies_over_the_cap() {
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        let err = read_response(raw.as_bytes()).expect_err("declared length over the cap");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let raw = format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            MAX_BODY + 1
+        );
+        let err = read_response(raw.as_bytes()).expect_err("chunk over the cap");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut raw = b"HTTP/1.1 200 OK\r\n\r\n".to_vec();
+        raw.resize(raw.len() + MAX_BODY + 1, b'x');
+        let err = read_response(&raw[..]).expect_err("unframed body over the cap");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,255 @@
+//! Offline key tooling behind the `keygen`, `fingerprint` and `render`
+//! subcommands.
+//!
+//! Generated keys are printable so the same string works in an `env:` source,
+//! a key file and a router's key chain. Raw keys are only for file-backed
+//! sources on both ends; routers cannot take them.
+
+use std::fs::File;
+use std::io::Read;
+
+use zeroize::Zeroize;
+
+use crate::config::AoPolicyConfig;
+use crate::error::{ProxyError, Result};
+use crate::secret::KeyMaterial;
+use crate::tcpao::policy::normalize_mac_alg;
+
+/// Characters of a text key. 62 symbols give ~5.95 bits per character and
+/// need no quoting in any router CLI or shell.
+const TEXT_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyEncoding {
+    /// Alphanumeric, usable everywhere.
+    Text,
+    /// Uniform random bytes, for `file:`, `dir:` and `credential:` sources.
+    Raw,
+}
+
+/// Key length for a `mac_alg`: the hash output for HMAC, the AES-128 key for
+/// CMAC. Text keys use the same number of characters.
+pub fn key_len(mac_alg: &str) -> Result<usize> {
+    let (alg, _) = normalize_mac_alg(mac_alg).map_err(|e| ProxyError::Config(e.to_string()))?;
+    match alg.as_str() {
+        "hmac(sha1)" => Ok(20),
+        "hmac(sha256)" => Ok(32),
+        "cmac(aes)" => Ok(16),
+        _ => Err(ProxyError::Config(format!(
+            "no key length known for mac_alg '{mac_alg}'; use hmac-sha1, hmac-sha256 or cmac-aes"
+        ))),
+    }
+}
+
+pub fn generate(mac_alg: &str, encoding: KeyEncoding) -> Result<KeyMaterial> {
+    let len = key_len(mac_alg)?;
+    let key = match encoding {
+        KeyEncoding::Raw => random_bytes(len)?,
+        KeyEncoding::Text => {
+            // Rejection sampling keeps every character equally likely.
+            let want = len;
+            let limit = 256 - 256 % TEXT_ALPHABET.len();
+            let mut key = Vec::with_capacity(want);
+            while key.len() < want {
+                let mut pool = random_bytes(want)?;
+                key.extend(
+                    pool.iter()
+                        .filter(|b| usize::from(**b) < limit)
+                        .map(|b| TEXT_ALPHABET[usize::from(*b) % TEXT_ALPHABET.len()])
+                        .take(want - key.len()),
+                );
+                pool.zeroize();
+            }
+            key
+        }
+    };
+    Ok(KeyMaterial::new(key))
+}
+
+fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0_u8; len];
+    File::open("/dev/urandom")?.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Vendor {
+    Junos,
+    IosXr,
+    Eos,
+    Frr,
+}
+
+impl Vendor {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Vendor::Junos => "junos",
+            Vendor::IosXr => "ios-xr",
+            Vendor::Eos => "eos",
+            Vendor::Frr => "frr",
+        }
+    }
+
+    /// The vendor's name for the algorithm with the MAC length the proxy
+    /// uses, or `None` when the vendor cannot do it.
+    fn mac_alg(self, kernel_alg: &str) -> Option<&'static str> {
+        match (self, kernel_alg) {
+            (Vendor::Junos, "hmac(sha1)") => Some("hmac-sha-1-96"),
+            (Vendor::Junos, "cmac(aes)") => Some("aes-128-cmac-96"),
+            (Vendor::IosXr, "hmac(sha1)") => Some("HMAC-SHA1-12"),
+            (Vendor::IosXr, "hmac(sha256)") => Some("HMAC-SHA-256"),
+            (Vendor::IosXr, "cmac(aes)") => Some("AES-128-CMAC-96"),
+            (Vendor::Eos, "hmac(sha1)") => Some("hmac-sha1-96"),
+            (Vendor::Eos, "hmac(sha256)") => Some("hmac-sha-256-128"),
+            (Vendor::Eos, "cmac(aes)") => Some("aes-128-cmac-96"),
+            (Vendor::Frr, "hmac(sha1)") => Some("hmac-sha-1"),
+            (Vendor::Frr, "hmac(sha256)") => Some("hmac-sha-256"),
+            _ => None,
+        }
+    }
+}
+
+/// Router key chain config matching `policy`, using the same key id as both
+/// send and receive id, with the key loaded from its key source. The router
+/// side's `rnextkeyid` follows the proxy's current key, so only `keyid` is
+/// rendered.
+pub fn render(vendor: Vendor, policy: &AoPolicyConfig, key: &KeyMaterial) -> Result<String> {
+    let (kernel_alg, _) =
+        normalize_mac_alg(&policy.mac_alg).map_err(|e| ProxyError::Config(e.to_string()))?;
+    let alg = vendor.mac_alg(&kernel_alg).ok_or_else(|| {
+        ProxyError::Config(format!(
+            "{} has no tcp-ao support for mac_alg '{}'",
+            vendor.as_str(),
+            policy.mac_alg
+        ))
+    })?;
+    let secret = std::str::from_utf8(key.as_bytes())
+        .ok()
+        .filter(|s| s.bytes().all(|b| b.is_ascii_graphic() && b != b'"'))
+        .ok_or_else(|| {
+            ProxyError::Config(format!(
+                "key for policy '{}' is not printable ascii; routers need a text key \
+(tcpao-proxy keygen without --raw)",
+                policy.name
+            ))
+        })?;
+
+    let name = &policy.name;
+    let id = policy.keyid;
+    let peer = policy.peer_ip;
+    let lines = match vendor {
+        Vendor::Junos => vec![
+            "security {".to_string(),
+            "    authentication-key-chains {".to_string(),
+            format!("        key-chain {name} {{"),
+            format!("            key {id} {{"),
+            format!("                secret \"{secret}\";"),
+            "                start-time \"2020-01-01.00:00:00 +0000\";".to_string(),
+            "                algorithm ao;".to_string(),
+            "                ao-attribute {".to_string(),
+            format!("                    send-id {id};"),
+            format!("                    recv-id {id};"),
+            format!("                    cryptographic-algorithm {alg};"),
+            "                }".to_string(),
+            "            }".to_string(),
+            "        }".to_string(),
+            "    }".to_string(),
+            "}".to_string(),
+            format!("/* bmp station towards {peer}: authentication-key-chain {name}; */"),
+        ],
+        Vendor::IosXr => vec![
+            format!("key chain {name}"),
+            format!(" key {id}"),
+            "  accept-lifetime 00:00:00 january 01 2020 infinite".to_string(),
+            format!("  key-string clear {secret}"),
+            "  send-lifetime 00:00:00 january 01 2020 infinite".to_string(),
+            format!("  cryptographic-algorithm {alg}"),
+            " !".to_string(),
+            "!".to_string(),
+            "tcp ao".to_string(),
+            format!(" keychain {name}"),
+            format!("  key {id} SendID {id} ReceiveID {id}"),
+            " !".to_string(),
+            "!".to_string(),
+            format!("! bmp server towards {peer}: ao {name} include-tcp-options enable"),
+        ],
+        Vendor::Eos => vec![
+            "management security".to_string(),
+            format!("   session shared-secret profile {name}"),
+            format!("      secret {id} 0 {secret} infinite infinite"),
+            "!".to_string(),
+            format!("! bmp station towards {peer}: tcp-ao profile {name}, send-id {id}, receive-id {id}, {alg}"),
+        ],
+        Vendor::Frr => vec![
+            format!("key chain {name}"),
+            format!(" key {id}"),
+            format!("  key-string {secret}"),
+            format!("  cryptographic-algorithm {alg}"),
+            " exit".to_string(),
+            "exit".to_string(),
+            "! bmp targets cannot use a key chain; run tcpao-proxy in initiator mode next to bgpd".to_string(),
+            "! and point 'bmp connect' at its listen_plain address".to_string(),
+        ],
+    };
+    Ok(lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mac_alg: &str) -> AoPolicyConfig {
+        AoPolicyConfig {
+            peer_port: Some(1790),
+            keyid: 7,
+            mac_alg: mac_alg.to_string(),
+            ..AoPolicyConfig::for_test("bmp-peer-1", "10.0.0.2", "env:UNUSED")
+        }
+    }
+
+    #[test]
+    fn keys_have_the_length_and_alphabet_for_their_algorithm() {
+        assert_eq!(
+            generate("hmac-sha1", KeyEncoding::Raw).expect("key").len(),
+            20
+        );
+        assert_eq!(
+            generate("cmac-aes", KeyEncoding::Raw).expect("key").len(),
+            16
+        );
+
+        let text = generate("hmac-sha256", KeyEncoding::Text).expect("key");
+        assert_eq!(text.len(), 32);
+        assert!(text.as_bytes().iter().all(|b| TEXT_ALPHABET.contains(b)));
+        assert_ne!(
+            text,
+            generate("hmac-sha256", KeyEncoding::Text).expect("key")
+        );
+
+        assert!(generate("hmac-md5", KeyEncoding::Text).is_err());
+    }
+
+    #[test]
+    fn render_uses_vendor_algorithm_names_and_the_key() {
+        let key = KeyMaterial::from_slice(b"Abc123");
+        let junos = render(Vendor::Junos, &policy("cmac-aes"), &key).expect("junos");
+        assert!(junos.contains("secret \"Abc123\";"));
+        assert!(junos.contains("send-id 7;"));
+        assert!(junos.contains("cryptographic-algorithm aes-128-cmac-96;"));
+
+        let xr = render(Vendor::IosXr, &policy("hmac-sha256"), &key).expect("ios-xr");
+        assert!(xr.contains("key-string clear Abc123"));
+        assert!(xr.contains("key 7 SendID 7 ReceiveID 7"));
+
+        let frr = render(Vendor::Frr, &policy("hmac-sha1"), &key).expect("frr");
+        assert!(frr.contains("cryptographic-algorithm hmac-sha-1"));
+
+        assert!(render(Vendor::Junos, &policy("hmac-sha256"), &key).is_err());
+        assert!(render(
+            Vendor::Eos,
+            &policy("hmac-sha1"),
+            &KeyMaterial::from_slice(&[0, 1])
+        )
+        .is_err());
+    }
+}
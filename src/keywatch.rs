@@ -0,0 +1,253 @@
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::{info, warn};
+use zeroize::Zeroizing;
+
+use crate::config::{AoPolicyConfig, KeySource, KeySourceKind};
+use crate::error::Result;
+use crate::vault;
+
+/// Floor between two reads of one Vault secret, so a short lease or an
+/// expired last-known-good entry does not turn into a busy loop.
+const MIN_VAULT_REFRESH: Duration = Duration::from_secs(1);
+
+struct WatchedKey {
+    policy: String,
+    source: KeySource,
+    digest: Option<[u8; 32]>,
+    due: Instant,
+}
+
+/// HMAC-SHA256 key drawn once per watcher, so the digests it keeps between
+/// reads cannot be matched against a guessed key.
+struct DigestKey(Zeroizing<[u8; 64]>);
+
+impl DigestKey {
+    fn random() -> Result<Self> {
+        let mut key = Zeroizing::new([0_u8; 64]);
+        File::open("/dev/urandom")?.read_exact(&mut key[..])?;
+        Ok(Self(key))
+    }
+
+    fn mac(&self, data: &[u8]) -> [u8; 32] {
+        let mut pad = Zeroizing::new([0_u8; 64]);
+        for (p, k) in pad.iter_mut().zip(self.0.iter()) {
+            *p = k ^ 0x36;
+        }
+        let inner = Sha256::new()
+            .chain_update(&pad[..])
+            .chain_update(data)
+            .finalize();
+        for (p, k) in pad.iter_mut().zip(self.0.iter()) {
+            *p = k ^ 0x5c;
+        }
+        Sha256::new()
+            .chain_update(&pad[..])
+            .chain_update(inner)
+            .finalize()
+            .into()
+    }
+}
+
+/// Spawns a background task that re-reads every watched key source and
+/// signals the returned `Notify` when key material changes. `dir:` sources
+/// are polled every `interval`; `vault:` secrets are re-read when their
+/// cached copy expires (`cache_ttl_secs`, or the lease when shorter), whether
+/// or not polling is enabled. Keys are read on the blocking pool. Returns
+/// `None` when nothing is watched.
+pub async fn spawn_key_watcher(
+    policies: &[AoPolicyConfig],
+    interval: Option<Duration>,
+) -> Result<Option<Arc<Notify>>> {
+    let policies: Vec<_> = policies
+        .iter()
+        .filter(|policy| next_check(&policy.key_source, interval).is_some())
+        .collect();
+    if policies.is_empty() {
+        return Ok(None);
+    }
+
+    let digest_key = Arc::new(DigestKey::random()?);
+    let mut watched = Vec::new();
+    for policy in policies {
+        let digest = key_digest(policy.key_source.clone(), Arc::clone(&digest_key)).await;
+        watched.push(WatchedKey {
+            policy: policy.name.clone(),
+            source: policy.key_source.clone(),
+            digest,
+            due: Instant::now()
+                + next_check(&policy.key_source, interval).unwrap_or(MIN_VAULT_REFRESH),
+        });
+    }
+
+    info!(
+        sources = watched.len(),
+        interval_secs = interval.map(|interval| interval.as_secs()),
+        "watching key sources for changes"
+    );
+
+    let notify = Arc::new(Notify::new());
+    let rekey = Arc::clone(&notify);
+
+    tokio::spawn(async move {
+        loop {
+            let Some(due) = watched.iter().map(|key| key.due).min() else {
+                return;
+            };
+            tokio::time::sleep_until(due).await;
+
+            let mut changed = false;
+            for key in &mut watched {
+                if key.due > Instant::now() {
+                    continue;
+                }
+                let digest = key_digest(key.source.clone(), Arc::clone(&digest_key)).await;
+                key.due =
+                    Instant::now() + next_check(&key.source, interval).unwrap_or(MIN_VAULT_REFRESH);
+                if digest == key.digest {
+                    continue;
+                }
+
+                let fails_closed = key.source.fails_closed();
+                match digest {
+                    Some(_) => info!(policy = %key.policy, "key source changed; re-keying"),
+                    None if fails_closed => warn!(
+                        policy = %key.policy,
+                        "watched key source became unreadable; removing installed key"
+                    ),
+                    None => warn!(
+                        policy = %key.policy,
+                        "watched key source became unreadable; keeping installed key"
+                    ),
+                }
+
+                // An unreadable source keeps the last installed key unless it is
+                // configured to fail closed; the reload still records the
+                // failure for readiness.
+                changed = true;
+                key.digest = digest;
+            }
+
+            if changed {
+                rekey.notify_one();
+            }
+        }
+    });
+
+    Ok(Some(notify))
+}
+
+/// Resolves when `rekey` fires; never resolves when there is no watcher.
+pub async fn wait_for_rekey(rekey: Option<&Notify>) {
+    match rekey {
+        Some(notify) => notify.notified().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// How long until `source` should be read again, or `None` if it is not
+/// watched.
+fn next_check(source: &KeySource, interval: Option<Duration>) -> Option<Duration> {
+    match source.kind().ok()? {
+        KeySourceKind::Vault { path, field } => {
+            vault::refresh_in(&path, &field).map(|due| due.max(MIN_VAULT_REFRESH))
+        }
+        kind if kind.is_watched() => interval,
+        _ => None,
+    }
+}
+
+async fn key_digest(source: KeySource, digest_key: Arc<DigestKey>) -> Option<[u8; 32]> {
+    tokio::task::spawn_blocking(move || {
+        let key = source.load_key().ok()?;
+        Some(digest_key.mac(key.as_bytes()))
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::IpAddr;
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::config::Enforcement;
+
+    fn policy(name: &str, key_source: &str) -> AoPolicyConfig {
+        AoPolicyConfig {
+            name: name.to_string(),
+            peer_ip: IpAddr::from_str("10.0.0.2").expect("valid ip"),
+            peer_port: None,
+            keyid: 1,
+            rnextkeyid: None,
+            mac_alg: "hmac-sha256".to_string(),
+            key_source: KeySource(key_source.to_string()),
+            enforcement: Enforcement::Required,
+        }
+    }
+
+    #[test]
+    fn digest_key_computes_hmac_sha256() {
+        // RFC 4231 test case 2.
+        let mut key = Zeroizing::new([0_u8; 64]);
+        key[..4].copy_from_slice(b"Jefe");
+        let mac = DigestKey(key).mac(b"what do ya want for nothing?");
+        let hex: String = mac.iter().map(|b| format!("{b:02x}")).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[tokio::test]
+    async fn watcher_is_not_started_without_watched_sources() {
+        let policies = vec![policy("env-only", "env:TCPAO_KEY")];
+        assert!(
+            spawn_key_watcher(&policies, Some(Duration::from_millis(10)))
+                .await
+                .expect("watcher setup")
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn watcher_notifies_when_dir_key_changes() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let entry = dir.path().join("bmp-peer-1");
+        fs::write(&entry, b"first-key").expect("write key");
+
+        let policies = vec![policy("peer-1", &format!("dir:{}", entry.display()))];
+        let notify = spawn_key_watcher(&policies, Some(Duration::from_millis(20)))
+            .await
+            .expect("watcher setup")
+            .expect("watcher started");
+
+        fs::write(&entry, b"second-key").expect("rotate key");
+
+        tokio::time::timeout(Duration::from_secs(2), notify.notified())
+            .await
+            .expect("re-key notification");
+    }
+
+    #[tokio::test]
+    async fn dir_sources_are_not_polled_when_watching_is_disabled() {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let entry = dir.path().join("bmp-peer-1");
+        fs::write(&entry, b"first-key").expect("write key");
+
+        let policies = vec![policy("peer-1", &format!("dir:{}", entry.display()))];
+        assert!(spawn_key_watcher(&policies, None)
+            .await
+            .expect("watcher setup")
+            .is_none());
+    }
+}
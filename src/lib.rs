@@ -1,7 +1,24 @@
+pub mod acl;
+pub mod admin;
+pub mod audit;
 pub mod config;
 pub mod error;
 pub mod forward;
+pub mod health;
+pub mod http;
+pub mod keytool;
+pub mod keywatch;
 pub mod metrics;
 pub mod mode_initiator;
 pub mod mode_terminator;
+pub mod plain;
+pub mod proxy_protocol;
+pub mod secret;
+pub mod session;
+pub mod splice;
+pub mod spool;
+pub mod systemd;
 pub mod tcpao;
+pub mod telemetry;
+pub mod upstream;
+pub mod vault;
@@ -1,9 +1,19 @@
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::forward::PumpStats;
 
 #[derive(Debug, Default)]
 pub struct Metrics {
     open_connections: AtomicU64,
     closed_connections: AtomicU64,
+    unprotected_sessions: AtomicU64,
+    rejected_connections: AtomicU64,
+    evicted_connections: AtomicU64,
+    high_water_up: AtomicU64,
+    high_water_down: AtomicU64,
+    write_blocked_up_us: AtomicU64,
+    write_blocked_down_us: AtomicU64,
 }
 
 impl Metrics {
@@ -16,6 +26,36 @@ impl Metrics {
         self.closed_connections.fetch_add(1, Ordering::Relaxed);
     }
 
+    /// A session admitted without AO under a `preferred`/`monitor` policy.
+    pub fn unprotected_session(&self) {
+        self.unprotected_sessions.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A connection refused by admission control before a task was spawned.
+    pub fn conn_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A session closed to make room under `evict-oldest`.
+    pub fn conn_evicted(&self) {
+        self.evicted_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Folds a finished session's buffering numbers in: the largest
+    /// high-water mark seen, and total time blocked on writes.
+    pub fn session_forwarded(&self, stats: &PumpStats) {
+        self.high_water_up
+            .fetch_max(stats.high_water_up, Ordering::Relaxed);
+        self.high_water_down
+            .fetch_max(stats.high_water_down, Ordering::Relaxed);
+        self.write_blocked_up_us
+            .fetch_add(stats.write_blocked_up.as_micros() as u64, Ordering::Relaxed);
+        self.write_blocked_down_us.fetch_add(
+            stats.write_blocked_down.as_micros() as u64,
+            Ordering::Relaxed,
+        );
+    }
+
     pub fn open_connections(&self) -> u64 {
         self.open_connections.load(Ordering::Relaxed)
     }
@@ -23,4 +63,32 @@ impl Metrics {
     pub fn closed_connections(&self) -> u64 {
         self.closed_connections.load(Ordering::Relaxed)
     }
+
+    pub fn unprotected_sessions(&self) -> u64 {
+        self.unprotected_sessions.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn evicted_connections(&self) -> u64 {
+        self.evicted_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn high_water_up(&self) -> u64 {
+        self.high_water_up.load(Ordering::Relaxed)
+    }
+
+    pub fn high_water_down(&self) -> u64 {
+        self.high_water_down.load(Ordering::Relaxed)
+    }
+
+    pub fn write_blocked_up(&self) -> Duration {
+        Duration::from_micros(self.write_blocked_up_us.load(Ordering::Relaxed))
+    }
+
+    pub fn write_blocked_down(&self) -> Duration {
+        Duration::from_micros(self.write_blocked_down_us.load(Ordering::Relaxed))
+    }
 }
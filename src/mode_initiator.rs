@@ -1,17 +1,33 @@
-use std::mem;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsFd, AsRawFd};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tokio::net::{TcpListener, TcpSocket, TcpStream};
-use tracing::{error, info};
+use tokio::net::{TcpSocket, TcpStream};
+use tracing::{error, info, info_span, warn, Instrument, Span};
 
-use crate::config::{Config, GlobalConfig};
+use crate::admin::{self, AdminState};
+use crate::audit;
+use crate::config::{Config, Enforcement, GlobalConfig};
 use crate::error::{ProxyError, Result};
-use crate::forward::{pump, PumpOptions};
-use crate::tcpao::{linux, policy};
+use crate::forward::{forward, PumpOptions};
+use crate::health::Readiness;
+use crate::keywatch;
+use crate::metrics::Metrics;
+use crate::plain::{PlainListener, PlainStream};
+use crate::proxy_protocol::{self, ProxyHeader};
+use crate::session::{self, KillReason, SessionGuard, SessionRegistry};
+use crate::systemd;
+use crate::tcpao::linux;
+use crate::tcpao::policy::{CompiledPolicy, PolicySet, PolicyStore};
+use crate::telemetry;
+use crate::vault;
 
 static CONN_ID: AtomicU64 = AtomicU64::new(1);
 const MODE_LABEL: &str = "initiator";
+/// Bound on the AO connect attempt before a relaxed policy falls back, since a
+/// peer that drops signed SYNs would otherwise hold us for the full SYN retry.
+const AO_FALLBACK_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub async fn run(cfg: Config) -> Result<()> {
     let initiator = cfg
@@ -19,88 +35,307 @@ pub async fn run(cfg: Config) -> Result<()> {
         .as_ref()
         .ok_or(ProxyError::MissingModeConfig("initiator"))?;
 
-    let listen_addr = initiator.listen_plain_addr()?;
-    let remote_ao = initiator.remote_ao_addr()?;
-    let listener = TcpListener::bind(listen_addr).await?;
+    vault::install(cfg.vault.as_ref())?;
+    audit::install(cfg.audit.as_ref())?;
 
-    info!(listen = %listen_addr, remote_ao = %remote_ao, "initiator mode listening");
+    let listen_plain = initiator.listen_plain_endpoint()?;
+    let route = Route {
+        remote_ao: initiator.remote_ao_addr()?,
+        accept_proxy_protocol: initiator.accept_proxy_protocol,
+        pump_options: cfg
+            .global
+            .pump_options(&initiator.timeouts, &initiator.buffers),
+    };
+    let acl = initiator.client_acl()?;
+    let policies = Arc::new(PolicyStore::new(
+        PolicySet::compile_in_background(&cfg.ao_policy).await?,
+    ));
+    let global = Arc::new(cfg.global.clone());
+    let metrics = Arc::new(Metrics::default());
+    telemetry::observe(MODE_LABEL, &metrics);
+    let sessions = Arc::new(SessionRegistry::new(&cfg.global));
+    let listener = match systemd::take_listen_fd()? {
+        Some(fd) => PlainListener::from_inherited(fd)?,
+        None => PlainListener::bind(&listen_plain).await?,
+    };
+    let listen_plain = listener.endpoint()?;
+
+    info!(
+        listen = %listen_plain,
+        remote_ao = %route.remote_ao,
+        proxy_protocol = route.accept_proxy_protocol,
+        "initiator mode listening"
+    );
+
+    // Outbound keys are installed per connection from the current set, so a
+    // key change only needs a recompile; new sessions pick it up on connect.
+    let rekey = keywatch::spawn_key_watcher(&cfg.ao_policy, cfg.global.key_watch_interval())
+        .await?
+        .unwrap_or_default();
+    let _admin = admin::spawn(
+        cfg.admin.as_ref(),
+        AdminState {
+            mode: MODE_LABEL,
+            started: Instant::now(),
+            sessions: Arc::clone(&sessions),
+            policies: Arc::clone(&policies),
+            metrics: Arc::clone(&metrics),
+            listeners: vec![admin::Listener {
+                role: "plain",
+                address: listen_plain.to_string(),
+                target: route.remote_ao.to_string(),
+            }],
+            reload: Arc::clone(&rekey),
+            readiness: Readiness::new(listener.as_fd(), false, None)?,
+        },
+    )
+    .await?;
+    let _heartbeat = systemd::ready(MODE_LABEL, &metrics);
 
     loop {
-        let (plain, plain_peer) = listener.accept().await?;
+        let (plain, plain_peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    session::accept_failed(MODE_LABEL, &err).await;
+                    continue;
+                }
+            },
+            _ = rekey.notified() => {
+                match policies.current().reload_in_background(&cfg.ao_policy).await {
+                    Ok(next) => {
+                        policies.replace(next);
+                        info!(mode = MODE_LABEL, "reloaded ao policies");
+                    }
+                    Err(err) => error!(mode = MODE_LABEL, error = %err, "failed to reload ao policies"),
+                }
+                continue;
+            }
+        };
         let conn_id = CONN_ID.fetch_add(1, Ordering::Relaxed);
-        let ao_policies = cfg.ao_policy.clone();
-        let global = cfg.global.clone();
+        if let Err(reason) = acl.check(&plain_peer) {
+            audit::client_rejected(MODE_LABEL, conn_id, &plain_peer, reason);
+            metrics.conn_rejected();
+            continue;
+        }
+        let session = match sessions.admit(conn_id, plain_peer) {
+            Ok(session) => session,
+            Err(rejection) => {
+                warn!(
+                    mode = MODE_LABEL,
+                    conn_id,
+                    peer = %plain_peer,
+                    reason = %rejection,
+                    "connection rejected"
+                );
+                metrics.conn_rejected();
+                continue;
+            }
+        };
+        let policies = policies.current();
+        let global = Arc::clone(&global);
+        let metrics = Arc::clone(&metrics);
 
-        tokio::spawn(async move {
-            match handle_connection(conn_id, plain, plain_peer, remote_ao, &ao_policies, &global)
-                .await
-            {
-                Ok(()) => {}
-                Err(err) => {
-                    error!(
-                        mode = MODE_LABEL,
-                        conn_id,
-                        peer = %plain_peer,
-                        error = %err,
-                        "connection failed"
-                    )
+        let span = telemetry::connection_span(
+            MODE_LABEL,
+            cfg.service_name(),
+            &listen_plain,
+            conn_id,
+            &plain_peer,
+            &route.remote_ao,
+        );
+        tokio::spawn(
+            async move {
+                metrics.conn_opened();
+                let result = tokio::select! {
+                    result = handle_connection(
+                        &session, plain, route, &policies, &global, &metrics,
+                    ) => result,
+                    reason = session.killed() => {
+                        match reason {
+                            KillReason::Evicted => {
+                                info!("connection evicted to admit a newer session");
+                                metrics.conn_evicted();
+                            }
+                            KillReason::Admin => info!("connection killed via admin api"),
+                        }
+                        Ok(())
+                    }
+                };
+                metrics.conn_closed();
+                drop(session);
+
+                match result {
+                    Ok(()) => {}
+                    Err(err) => error!(error = %err, "connection failed"),
                 }
             }
-        });
+            .instrument(span),
+        );
     }
 }
 
-async fn handle_connection(
-    conn_id: u64,
-    plain: TcpStream,
-    plain_peer: std::net::SocketAddr,
+/// Where plain clients are sent, whether they arrive behind a PROXY protocol
+/// header, and the session timers to forward with.
+#[derive(Debug, Clone, Copy)]
+struct Route {
     remote_ao: std::net::SocketAddr,
-    policies: &[crate::config::AoPolicyConfig],
+    accept_proxy_protocol: bool,
+    pump_options: PumpOptions,
+}
+
+async fn handle_connection(
+    session: &SessionGuard,
+    mut plain: PlainStream,
+    route: Route,
+    policies: &PolicySet,
     global: &GlobalConfig,
+    metrics: &Metrics,
 ) -> Result<()> {
-    let policy = policy::select_policy(policies, remote_ao.ip(), Some(remote_ao.port()))
-        .ok_or_else(|| ProxyError::NoPolicyForPeer(remote_ao.to_string()))?;
+    let conn_id = session.conn_id();
+    if route.accept_proxy_protocol {
+        let header =
+            tokio::time::timeout(proxy_protocol::READ_TIMEOUT, ProxyHeader::read(&mut plain))
+                .await
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "timed out waiting for PROXY protocol header",
+                    )
+                })??;
+        if let Some(source) = header.source {
+            Span::current().record("client", tracing::field::display(source));
+        }
+        info!(
+            upstream_policy = ?header.policy,
+            upstream_keyid = ?header.keyid,
+            "proxy protocol header received"
+        );
+        session.describe(|d| {
+            d.client = header.source;
+            d.upstream_policy = header.policy;
+            d.upstream_keyid = header.keyid;
+        });
+    }
 
-    let socket = match remote_ao {
-        std::net::SocketAddr::V4(_) => TcpSocket::new_v4()?,
-        std::net::SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    let remote_ao = route.remote_ao;
+    let Some(policy) = policies.select(remote_ao.ip(), Some(remote_ao.port())) else {
+        audit::no_policy(MODE_LABEL, conn_id, remote_ao);
+        return Err(ProxyError::NoPolicyForPeer(remote_ao.to_string()));
     };
+    telemetry::record_policy(policy);
+    session.describe(|d| {
+        d.policy = Some(policy.name().to_string());
+        d.keyid = Some(policy.config.keyid);
+        d.rnextkeyid = policy.config.rnextkeyid;
+        d.wire_peer = Some(remote_ao);
+    });
 
-    apply_keepalive(socket.as_raw_fd(), global)?;
-
-    linux::apply_outbound_policy(socket.as_raw_fd(), policy, remote_ao)
-        .map_err(|e| ProxyError::TcpAo(format!("failed to apply outbound AO policy: {e}")))?;
-
-    let wire = socket.connect(remote_ao).await?;
-    apply_keepalive(wire.as_raw_fd(), global)?;
-    apply_keepalive(plain.as_raw_fd(), global)?;
+    let (wire, protected) = connect_wire(conn_id, policy, remote_ao, global, metrics)
+        .instrument(info_span!("ao_connect"))
+        .await?;
+    session.describe(|d| d.protected = Some(protected));
+    session.set_wire(&wire);
+    if let Some(plain) = plain.as_tcp() {
+        apply_keepalive(plain.as_raw_fd(), global)?;
+    }
 
-    let stats = pump(
-        plain,
-        wire,
-        PumpOptions {
-            idle_timeout: global.idle_timeout(),
-        },
-    )
-    .await?;
+    let stats = forward(plain, wire, route.pump_options, session.traffic())
+        .instrument(info_span!("pump"))
+        .await;
 
     info!(
-        mode = MODE_LABEL,
-        conn_id,
-        peer = %plain_peer,
-        policy = %policy.name,
-        keyid = policy.keyid,
-        rnextkeyid = ?policy.rnextkeyid,
         bytes_up = stats.bytes_up,
         bytes_down = stats.bytes_down,
         duration_ms = stats.duration.as_millis() as u64,
         reason = ?stats.reason,
+        first_closed = ?stats.first_closed,
+        up_error = stats.up_error.as_ref().map(tracing::field::display),
+        down_error = stats.down_error.as_ref().map(tracing::field::display),
+        high_water_up = stats.high_water_up,
+        high_water_down = stats.high_water_down,
+        write_blocked_up_ms = stats.write_blocked_up.as_millis() as u64,
+        write_blocked_down_ms = stats.write_blocked_down.as_millis() as u64,
         "connection closed"
     );
+    metrics.session_forwarded(&stats);
 
     Ok(())
 }
 
+/// Connects the AO leg and reports whether it is protected, which is only
+/// false after a relaxed policy fell back.
+async fn connect_wire(
+    conn_id: u64,
+    policy: &CompiledPolicy,
+    remote_ao: std::net::SocketAddr,
+    global: &GlobalConfig,
+    metrics: &Metrics,
+) -> Result<(TcpStream, bool)> {
+    let enforcement = policy.config.enforcement;
+    let socket = new_socket(remote_ao, global)?;
+
+    if let Err(err) = linux::apply_outbound_policy(socket.as_raw_fd(), policy, remote_ao) {
+        if enforcement != Enforcement::Monitor {
+            let reason = format!("failed to apply outbound AO policy: {err}");
+            audit::policy_failed(MODE_LABEL, conn_id, remote_ao, policy.name(), &reason);
+            return Err(ProxyError::TcpAo(reason));
+        }
+
+        audit::unprotected_session(
+            MODE_LABEL,
+            conn_id,
+            remote_ao,
+            policy.name(),
+            enforcement,
+            &format!("ao setup failed: {err}"),
+        );
+        metrics.unprotected_session();
+        let wire = new_socket(remote_ao, global)?.connect(remote_ao).await?;
+        return Ok((wire, false));
+    }
+    audit::policy_applied(
+        MODE_LABEL,
+        conn_id,
+        remote_ao,
+        policy.name(),
+        Some(policy.config.keyid),
+    );
+
+    if enforcement.is_required() {
+        return Ok((socket.connect(remote_ao).await?, true));
+    }
+
+    let reason =
+        match tokio::time::timeout(AO_FALLBACK_CONNECT_TIMEOUT, socket.connect(remote_ao)).await {
+            Ok(Ok(wire)) => return Ok((wire, true)),
+            Ok(Err(err)) => format!("ao connect failed: {err}"),
+            Err(_) => "ao connect timed out".to_string(),
+        };
+
+    audit::unprotected_session(
+        MODE_LABEL,
+        conn_id,
+        remote_ao,
+        policy.name(),
+        enforcement,
+        &reason,
+    );
+    metrics.unprotected_session();
+    let wire = new_socket(remote_ao, global)?.connect(remote_ao).await?;
+    Ok((wire, false))
+}
+
+fn new_socket(remote: std::net::SocketAddr, global: &GlobalConfig) -> Result<TcpSocket> {
+    let socket = match remote {
+        std::net::SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        std::net::SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    apply_keepalive(socket.as_raw_fd(), global)?;
+    Ok(socket)
+}
+
 #[cfg(target_os = "linux")]
 fn apply_keepalive(fd: std::os::fd::RawFd, global: &GlobalConfig) -> Result<()> {
     if !global.tcp_keepalive {
@@ -137,7 +372,7 @@ fn set_sockopt_int(fd: std::os::fd::RawFd, level: i32, optname: i32, value: i32)
             level,
             optname,
             (&value as *const i32).cast(),
-            mem::size_of::<i32>() as libc::socklen_t,
+            std::mem::size_of::<i32>() as libc::socklen_t,
         )
     };
 
@@ -1,141 +1,538 @@
-use std::mem;
-use std::os::fd::AsRawFd;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
 use socket2::{Domain, Protocol, Socket, Type};
-use tokio::net::{TcpListener, TcpSocket, TcpStream};
-use tracing::{error, info};
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, info_span, warn, Instrument, Span};
 
-use crate::config::{AoPolicyConfig, Config, GlobalConfig};
+use crate::admin::{self, AdminState};
+use crate::audit;
+use crate::config::{Config, GlobalConfig, SpoolConfig};
 use crate::error::{ProxyError, Result};
-use crate::forward::{pump, PumpOptions};
-use crate::tcpao::{linux, policy};
+use crate::forward::{forward, PumpOptions};
+use crate::health::Readiness;
+use crate::keywatch;
+use crate::metrics::Metrics;
+use crate::plain::{PlainEndpoint, PlainStream};
+use crate::proxy_protocol::ProxyHeader;
+use crate::session::{self, KillReason, SessionGuard, SessionRegistry};
+use crate::spool;
+use crate::systemd;
+use crate::tcpao::linux::{self, InboundAo, ListenerRole};
+use crate::tcpao::policy::{PolicySet, PolicyStore};
+use crate::telemetry;
+use crate::upstream::UpstreamMonitor;
+use crate::vault;
 
 static CONN_ID: AtomicU64 = AtomicU64::new(1);
 const MODE_LABEL: &str = "terminator";
 
+/// Backlog of a listener the proxy binds itself.
+const LISTEN_BACKLOG: i32 = 1024;
+
 pub async fn run(cfg: Config) -> Result<()> {
     let terminator = cfg
         .terminator
         .as_ref()
         .ok_or(ProxyError::MissingModeConfig("terminator"))?;
 
+    vault::install(cfg.vault.as_ref())?;
+    audit::install(cfg.audit.as_ref())?;
+
     let listen_addr = terminator.listen_ao_addr()?;
-    let forward_plain = terminator.forward_plain_addr()?;
-    let listener = build_ao_listener(listen_addr, &cfg.ao_policy)?;
+    let fallback_addr = terminator.fallback_listen_ao_addr()?;
+    let forward_plain = Arc::new(Forward {
+        endpoint: terminator.forward_plain_endpoint()?,
+        proxy_protocol: terminator.send_proxy_protocol,
+        pump_options: cfg
+            .global
+            .pump_options(&terminator.timeouts, &terminator.buffers),
+        spool: terminator.spool.clone(),
+    });
+    if let Some(spool) = &forward_plain.spool {
+        spool::prepare_dir(&spool.dir).map_err(|e| {
+            ProxyError::Config(format!(
+                "terminator.spool.dir '{}': {e}",
+                spool.dir.display()
+            ))
+        })?;
+        info!(
+            dir = %spool.dir.display(),
+            max_bytes = spool.max_bytes,
+            overflow = ?spool.overflow,
+            "spooling sessions while forward_plain is down"
+        );
+    }
+    let policies = Arc::new(PolicyStore::new(
+        PolicySet::compile_in_background(&cfg.ao_policy).await?,
+    ));
+    let global = Arc::new(cfg.global.clone());
+    let metrics = Arc::new(Metrics::default());
+    telemetry::observe(MODE_LABEL, &metrics);
+    let sessions = Arc::new(SessionRegistry::new(&cfg.global));
+    let listener = build_ao_listener(listen_addr, &policies.current())?;
+    let listen_addr = listener.local_addr()?;
+    let fallback = fallback_addr
+        .map(|addr| build_fallback_listener(addr, &policies.current()))
+        .transpose()?;
+    let mut ao_listeners = vec![(&listener, listen_addr, ListenerRole::Primary)];
+    if let Some(fallback) = &fallback {
+        ao_listeners.push((fallback, fallback.local_addr()?, ListenerRole::Fallback));
+    }
 
     info!(
         listen = %listen_addr,
-        forward_plain = %forward_plain,
+        fallback = ?fallback.as_ref().map(|l| l.local_addr()).transpose()?,
+        forward_plain = %forward_plain.endpoint,
+        proxy_protocol = forward_plain.proxy_protocol,
         "terminator mode listening"
     );
 
+    let rekey = keywatch::spawn_key_watcher(&cfg.ao_policy, cfg.global.key_watch_interval())
+        .await?
+        .unwrap_or_default();
+    let mut upstream =
+        UpstreamMonitor::spawn(forward_plain.endpoint.clone(), &terminator.health_check);
+    let _admin = admin::spawn(
+        cfg.admin.as_ref(),
+        AdminState {
+            mode: MODE_LABEL,
+            started: Instant::now(),
+            sessions: Arc::clone(&sessions),
+            policies: Arc::clone(&policies),
+            metrics: Arc::clone(&metrics),
+            listeners: ao_listeners
+                .iter()
+                .map(|&(_, address, role)| admin::Listener {
+                    role: match role {
+                        ListenerRole::Primary => "ao",
+                        ListenerRole::Fallback => "ao-fallback",
+                    },
+                    address: address.to_string(),
+                    target: forward_plain.endpoint.to_string(),
+                })
+                .collect(),
+            reload: Arc::clone(&rekey),
+            readiness: Readiness::new(listener.as_fd(), true, upstream.status())?,
+        },
+    )
+    .await?;
+    let _heartbeat = systemd::ready(MODE_LABEL, &metrics);
+
     loop {
-        let (wire, wire_peer) = listener.accept().await?;
+        let ((wire, wire_peer), accepted_on) = tokio::select! {
+            accepted = accept_session(&listener, fallback.as_ref(), &mut upstream) => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    session::accept_failed(MODE_LABEL, &err).await;
+                    continue;
+                }
+            },
+            _ = rekey.notified() => {
+                rekey_listeners(&ao_listeners, &policies, &cfg).await;
+                continue;
+            }
+        };
         let conn_id = CONN_ID.fetch_add(1, Ordering::Relaxed);
-        let ao_policies = cfg.ao_policy.clone();
-        let global = cfg.global.clone();
+        let session = match sessions.admit(conn_id, wire_peer) {
+            Ok(session) => session,
+            Err(rejection) => {
+                warn!(
+                    mode = MODE_LABEL,
+                    conn_id,
+                    peer = %wire_peer,
+                    reason = %rejection,
+                    "connection rejected"
+                );
+                metrics.conn_rejected();
+                continue;
+            }
+        };
+        let policies = policies.current();
+        let global = Arc::clone(&global);
+        let forward_plain = Arc::clone(&forward_plain);
+        let metrics = Arc::clone(&metrics);
 
-        tokio::spawn(async move {
-            match handle_connection(
-                conn_id,
-                wire,
-                wire_peer,
-                forward_plain,
-                &ao_policies,
-                &global,
-            )
-            .await
-            {
-                Ok(()) => {}
-                Err(err) => {
-                    error!(
-                        mode = MODE_LABEL,
-                        conn_id,
-                        peer = %wire_peer,
-                        error = %err,
-                        "connection failed"
-                    )
+        let span = telemetry::connection_span(
+            MODE_LABEL,
+            cfg.service_name(),
+            &accepted_on,
+            conn_id,
+            &wire_peer,
+            &wire_peer,
+        );
+        tokio::spawn(
+            async move {
+                metrics.conn_opened();
+                let result = tokio::select! {
+                    result = handle_connection(
+                        &session, wire, wire_peer, &forward_plain, &policies, &global, &metrics,
+                    ) => result,
+                    reason = session.killed() => {
+                        match reason {
+                            KillReason::Evicted => {
+                                info!("connection evicted to admit a newer session");
+                                metrics.conn_evicted();
+                            }
+                            KillReason::Admin => info!("connection killed via admin api"),
+                        }
+                        Ok(())
+                    }
+                };
+                metrics.conn_closed();
+                drop(session);
+
+                match result {
+                    Ok(()) => {}
+                    Err(err) => error!(error = %err, "connection failed"),
                 }
             }
-        });
+            .instrument(span),
+        );
     }
 }
 
+/// Where accepted sessions are forwarded in the clear.
+struct Forward {
+    endpoint: PlainEndpoint,
+    proxy_protocol: bool,
+    pump_options: PumpOptions,
+    spool: Option<SpoolConfig>,
+}
+
 async fn handle_connection(
-    conn_id: u64,
+    session: &SessionGuard,
     wire: TcpStream,
     wire_peer: std::net::SocketAddr,
-    forward_plain: std::net::SocketAddr,
-    policies: &[crate::config::AoPolicyConfig],
+    forward_plain: &Forward,
+    policies: &PolicySet,
     global: &GlobalConfig,
+    metrics: &Metrics,
 ) -> Result<()> {
-    let policy = policy::select_policy(policies, wire_peer.ip(), None)
-        .ok_or_else(|| ProxyError::NoPolicyForPeer(wire_peer.to_string()))?;
-
-    linux::ensure_inbound_session_has_ao(wire.as_raw_fd(), wire_peer)
-        .map_err(|e| ProxyError::TcpAo(format!("inbound AO verification failed: {e}")))?;
+    let conn_id = session.conn_id();
+    let Some(policy) = policies.select(wire_peer.ip(), None) else {
+        audit::no_policy(MODE_LABEL, conn_id, wire_peer);
+        return Err(ProxyError::NoPolicyForPeer(wire_peer.to_string()));
+    };
+    telemetry::record_policy(policy);
+    session.describe(|d| {
+        d.policy = Some(policy.name().to_string());
+        d.keyid = Some(policy.config.keyid);
+        d.rnextkeyid = policy.config.rnextkeyid;
+        d.wire_peer = Some(wire_peer);
+    });
+    session.set_wire(&wire);
 
-    let socket = match forward_plain {
-        std::net::SocketAddr::V4(_) => TcpSocket::new_v4()?,
-        std::net::SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    let enforcement = policy.config.enforcement;
+    let verify = info_span!("ao_verify").entered();
+    let inbound = match linux::inbound_session_ao(wire.as_raw_fd(), wire_peer) {
+        Ok(InboundAo::Signed(keys)) => Ok(Some(keys)),
+        Ok(InboundAo::Unchecked) => Ok(None),
+        Ok(InboundAo::Unsigned) => Err("session is not tcp-ao protected".to_string()),
+        Err(err) => Err(format!("inbound AO inspection failed: {err}")),
+    };
+    let session_key = match inbound {
+        Ok(keys) => {
+            let verified = keys
+                .map(|keys| {
+                    let key = linux::check_session_keys(&keys, policy)
+                        .map_err(|e| format!("inbound AO key verification failed: {e}"))?;
+                    linux::prune_session_keys(wire.as_raw_fd(), wire_peer, policy)
+                        .map_err(|e| format!("failed to prune inherited AO keys: {e}"))?;
+                    Ok::<_, String>(key)
+                })
+                .transpose();
+            let key = match verified {
+                Ok(key) => key,
+                Err(reason) => {
+                    audit::policy_failed(MODE_LABEL, conn_id, wire_peer, policy.name(), &reason);
+                    return Err(ProxyError::TcpAo(reason));
+                }
+            };
+            audit::policy_applied(
+                MODE_LABEL,
+                conn_id,
+                wire_peer,
+                policy.name(),
+                key.map(|key| key.sndid),
+            );
+            session.describe(|d| d.protected = Some(true));
+            key
+        }
+        Err(reason) if !enforcement.is_required() => {
+            audit::unprotected_session(
+                MODE_LABEL,
+                conn_id,
+                wire_peer,
+                policy.name(),
+                enforcement,
+                &reason,
+            );
+            metrics.unprotected_session();
+            session.describe(|d| d.protected = Some(false));
+            None
+        }
+        Err(reason) => {
+            audit::policy_failed(MODE_LABEL, conn_id, wire_peer, policy.name(), &reason);
+            return Err(ProxyError::TcpAo(reason));
+        }
     };
 
-    apply_keepalive(socket.as_raw_fd(), global)?;
+    drop(verify);
+    if let Some(key) = session_key {
+        Span::current().record("session_keyid", key.sndid);
+    }
 
-    let plain = socket.connect(forward_plain).await?;
-    apply_keepalive(plain.as_raw_fd(), global)?;
+    let proxy_header = if forward_plain.proxy_protocol {
+        let header = ProxyHeader {
+            policy: Some(policy.name().to_string()),
+            keyid: session_key.map(|key| key.sndid),
+            ..ProxyHeader::new(wire_peer, wire.local_addr()?)
+        };
+        Some(header.encode())
+    } else {
+        None
+    };
     apply_keepalive(wire.as_raw_fd(), global)?;
 
-    let stats = pump(
-        wire,
-        plain,
-        PumpOptions {
-            idle_timeout: global.idle_timeout(),
-        },
-    )
-    .await?;
+    let endpoint = &forward_plain.endpoint;
+    let proxy_header = proxy_header.as_deref();
+    let stats = match &forward_plain.spool {
+        Some(spool) => {
+            let connect = || {
+                connect_plain(endpoint, proxy_header, global)
+                    .instrument(info_span!("plain_connect"))
+            };
+            spool::pump(
+                wire,
+                connect,
+                spool,
+                conn_id,
+                forward_plain.pump_options,
+                session.traffic(),
+            )
+            .instrument(info_span!("pump"))
+            .await
+        }
+        None => {
+            let plain = connect_plain(endpoint, proxy_header, global)
+                .instrument(info_span!("plain_connect"))
+                .await?;
+            forward(wire, plain, forward_plain.pump_options, session.traffic())
+                .instrument(info_span!("pump"))
+                .await
+        }
+    };
 
     info!(
-        mode = MODE_LABEL,
-        conn_id,
-        peer = %wire_peer,
-        policy = %policy.name,
-        keyid = policy.keyid,
-        rnextkeyid = ?policy.rnextkeyid,
         bytes_up = stats.bytes_up,
         bytes_down = stats.bytes_down,
         duration_ms = stats.duration.as_millis() as u64,
         reason = ?stats.reason,
+        first_closed = ?stats.first_closed,
+        up_error = stats.up_error.as_ref().map(tracing::field::display),
+        down_error = stats.down_error.as_ref().map(tracing::field::display),
+        high_water_up = stats.high_water_up,
+        high_water_down = stats.high_water_down,
+        write_blocked_up_ms = stats.write_blocked_up.as_millis() as u64,
+        write_blocked_down_ms = stats.write_blocked_down.as_millis() as u64,
         "connection closed"
     );
+    metrics.session_forwarded(&stats);
 
     Ok(())
 }
 
+/// Connects to `forward_plain` and sends the PROXY header, if any.
+async fn connect_plain(
+    endpoint: &PlainEndpoint,
+    proxy_header: Option<&[u8]>,
+    global: &GlobalConfig,
+) -> io::Result<PlainStream> {
+    let mut plain = endpoint
+        .connect_with(|socket| {
+            apply_keepalive(socket.as_raw_fd(), global).map_err(io::Error::other)
+        })
+        .await?;
+    if let Some(header) = proxy_header {
+        plain.write_all(header).await?;
+    }
+    Ok(plain)
+}
+
+/// The next session on `listen_ao` or `fallback_listen_ao`, with the address
+/// that accepted it. Nothing is accepted while `forward_plain` is down: the
+/// kernel keeps completed handshakes in the backlog and drops SYNs once it is
+/// full, so routers wait or retry instead of seeing sessions open and reset.
+/// Queued sessions are accepted once a health check succeeds again.
+async fn accept_session(
+    listener: &TcpListener,
+    fallback: Option<&TcpListener>,
+    upstream: &mut UpstreamMonitor,
+) -> io::Result<((TcpStream, std::net::SocketAddr), std::net::SocketAddr)> {
+    loop {
+        if !upstream.is_up() {
+            debug!(mode = MODE_LABEL, "forward_plain is down; not accepting");
+            while !upstream.changed().await {}
+            continue;
+        }
+        tokio::select! {
+            accepted = accept_on(Some(listener)) => return accepted,
+            accepted = accept_on(fallback) => return accepted,
+            _ = upstream.changed() => {}
+        }
+    }
+}
+
+/// Sessions on `fallback_listen_ao` go through the same per-policy checks as
+/// `listen_ao`: `required` peers are keyed there and must sign, others are
+/// admitted without AO and audited.
+async fn accept_on(
+    listener: Option<&TcpListener>,
+) -> io::Result<((TcpStream, std::net::SocketAddr), std::net::SocketAddr)> {
+    match listener {
+        Some(listener) => Ok((listener.accept().await?, listener.local_addr()?)),
+        None => std::future::pending().await,
+    }
+}
+
+/// Re-keys every AO listener; the new policies are only used once all of
+/// them hold the new keys.
+async fn rekey_listeners(
+    listeners: &[(&TcpListener, std::net::SocketAddr, ListenerRole)],
+    policies: &PolicyStore,
+    cfg: &Config,
+) {
+    let previous = policies.current();
+    let next = match Arc::clone(&previous)
+        .reload_in_background(&cfg.ao_policy)
+        .await
+    {
+        Ok(next) => next,
+        Err(err) => {
+            error!(mode = MODE_LABEL, error = %err, "failed to reload ao policies");
+            return;
+        }
+    };
+
+    for (done, &(listener, listen_addr, role)) in listeners.iter().enumerate() {
+        if let Err(err) =
+            linux::rekey_listener(listener.as_raw_fd(), listen_addr, &previous, &next, role)
+        {
+            error!(
+                mode = MODE_LABEL,
+                listen = %listen_addr,
+                error = %err,
+                "failed to re-key listener; previous keys stay installed"
+            );
+            for &(listener, listen_addr, role) in listeners[..done].iter().rev() {
+                if let Err(undo) =
+                    linux::rekey_listener(listener.as_raw_fd(), listen_addr, &next, &previous, role)
+                {
+                    warn!(listen = %listen_addr, error = %undo, "failed to restore listener keys");
+                }
+            }
+            return;
+        }
+    }
+    policies.replace(next);
+}
+
+/// Binds `listen_addr`, or takes the socket systemd passed instead, and
+/// installs the listener keys before it accepts anything.
 fn build_ao_listener(
     listen_addr: std::net::SocketAddr,
-    policies: &[AoPolicyConfig],
+    policies: &PolicySet,
+) -> Result<TcpListener> {
+    let inherited = systemd::take_listen_fd()?;
+    let activated = inherited.is_some();
+    let socket = match inherited {
+        Some(fd) => inherited_ao_socket(fd, listen_addr)?,
+        None => bind_socket(listen_addr)?,
+    };
+    listen_ao(socket, policies, ListenerRole::Primary, activated)
+}
+
+/// Binds `fallback_listen_ao`. It is never socket-activated.
+fn build_fallback_listener(
+    listen_addr: std::net::SocketAddr,
+    policies: &PolicySet,
 ) -> Result<TcpListener> {
+    let socket = bind_socket(listen_addr)?;
+    listen_ao(socket, policies, ListenerRole::Fallback, false)
+}
+
+fn bind_socket(listen_addr: std::net::SocketAddr) -> Result<Socket> {
     let domain = match listen_addr {
         std::net::SocketAddr::V4(_) => Domain::IPV4,
         std::net::SocketAddr::V6(_) => Domain::IPV6,
     };
-
     let socket = Socket::new(domain, Type::STREAM, Some(Protocol::TCP))?;
     socket.set_reuse_address(true)?;
     socket.bind(&listen_addr.into())?;
+    Ok(socket)
+}
+
+fn listen_ao(
+    socket: Socket,
+    policies: &PolicySet,
+    role: ListenerRole,
+    activated: bool,
+) -> Result<TcpListener> {
+    let local_addr = socket
+        .local_addr()?
+        .as_socket()
+        .ok_or_else(|| ProxyError::Config("ao listener has no inet address".to_string()))?;
 
-    linux::configure_listener(socket.as_raw_fd(), listen_addr, policies)
+    linux::configure_listener(socket.as_raw_fd(), local_addr, policies, role)
         .map_err(|e| ProxyError::TcpAo(format!("failed to configure listener AO policies: {e}")))?;
 
-    socket.listen(1024)?;
+    // An inherited socket keeps the unit's Backlog=.
+    if !activated {
+        socket.listen(LISTEN_BACKLOG)?;
+    }
     socket.set_nonblocking(true)?;
 
     let std_listener: std::net::TcpListener = socket.into();
     Ok(TcpListener::from_std(std_listener)?)
 }
 
+/// A socket from systemd is already listening when the keys go on. Signed
+/// SYNs that arrive before that are dropped by the kernel and retried by the
+/// router; unsigned ones are refused by the per-session verification.
+#[cfg(target_os = "linux")]
+fn inherited_ao_socket(fd: OwnedFd, listen_addr: std::net::SocketAddr) -> Result<Socket> {
+    let socket = Socket::from(fd);
+    if socket.r#type()? != Type::STREAM {
+        return Err(ProxyError::Config(
+            "socket passed by systemd is not a stream socket".to_string(),
+        ));
+    }
+    let local_addr = socket.local_addr()?.as_socket().ok_or_else(|| {
+        ProxyError::Config("socket passed by systemd is not a tcp socket".to_string())
+    })?;
+    if local_addr != listen_addr {
+        warn!(
+            inherited = %local_addr,
+            listen_ao = %listen_addr,
+            "socket passed by systemd differs from listen_ao; using the inherited one"
+        );
+    }
+    info!(listen = %local_addr, "using ao listener socket passed by systemd");
+    Ok(socket)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn inherited_ao_socket(_fd: OwnedFd, _listen_addr: std::net::SocketAddr) -> Result<Socket> {
+    Err(ProxyError::Config(
+        "systemd socket activation is only supported on linux".to_string(),
+    ))
+}
+
 #[cfg(target_os = "linux")]
 fn apply_keepalive(fd: std::os::fd::RawFd, global: &GlobalConfig) -> Result<()> {
     if !global.tcp_keepalive {
@@ -172,7 +569,7 @@ fn set_sockopt_int(fd: std::os::fd::RawFd, level: i32, optname: i32, value: i32)
             level,
             optname,
             (&value as *const i32).cast(),
-            mem::size_of::<i32>() as libc::socklen_t,
+            std::mem::size_of::<i32>() as libc::socklen_t,
         )
     };
 
@@ -182,3 +579,61 @@ fn set_sockopt_int(fd: std::os::fd::RawFd, level: i32, optname: i32, value: i32)
 
     Err(std::io::Error::last_os_error().into())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::config::HealthCheck;
+
+    #[tokio::test]
+    async fn sessions_wait_in_the_backlog_while_forward_plain_is_down() {
+        let target = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let target_addr = target.local_addr().expect("addr");
+        drop(target);
+        let cfg = HealthCheck {
+            health_check_interval_secs: Some(1),
+            health_check_timeout_secs: 1,
+            health_check_failures: 1,
+        };
+        let mut upstream = UpstreamMonitor::spawn(PlainEndpoint::Tcp(target_addr), &cfg);
+        let down = tokio::time::timeout(Duration::from_secs(5), upstream.changed())
+            .await
+            .expect("went down");
+        assert!(!down);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let listen_addr = listener.local_addr().expect("addr");
+        let mut client = TcpStream::connect(listen_addr).await.expect("connect");
+        assert!(
+            tokio::time::timeout(
+                Duration::from_millis(1500),
+                accept_session(&listener, None, &mut upstream)
+            )
+            .await
+            .is_err(),
+            "accepted while forward_plain is down"
+        );
+        // Neither reset nor closed: the session waits in the backlog.
+        let mut buf = [0_u8; 1];
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), client.read(&mut buf))
+                .await
+                .is_err()
+        );
+
+        let _target = TcpListener::bind(target_addr).await.expect("rebind");
+        let ((_, peer), accepted_on) = tokio::time::timeout(
+            Duration::from_secs(5),
+            accept_session(&listener, None, &mut upstream),
+        )
+        .await
+        .expect("accepted once forward_plain is back")
+        .expect("accept");
+        assert_eq!(peer, client.local_addr().expect("client addr"));
+        assert_eq!(accepted_on, listen_addr);
+    }
+}
@@ -0,0 +1,348 @@
+//! The plain (non-AO) leg of the proxy: either TCP or a unix domain socket.
+//!
+//! `listen_plain` and `forward_plain` take a socket address or
+//! `unix:/path/to.sock`. A unix listener removes a stale socket file left by a
+//! previous run before binding and removes its own file when dropped. A
+//! listener inherited from systemd leaves its file to the socket unit.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpListener, TcpSocket, TcpStream, UnixListener, UnixStream};
+use tracing::warn;
+
+use crate::error::{ProxyError, Result};
+use crate::session::Peer;
+
+const UNIX_PREFIX: &str = "unix:";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlainEndpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl PlainEndpoint {
+    pub fn parse(raw: &str) -> Result<Self> {
+        let Some(path) = raw.strip_prefix(UNIX_PREFIX) else {
+            return Ok(PlainEndpoint::Tcp(raw.parse()?));
+        };
+
+        if path.is_empty() {
+            return Err(ProxyError::Config(format!(
+                "unix socket endpoint '{raw}' is missing a path"
+            )));
+        }
+        Ok(PlainEndpoint::Unix(PathBuf::from(path)))
+    }
+
+    pub fn is_unix(&self) -> bool {
+        matches!(self, PlainEndpoint::Unix(_))
+    }
+
+    pub async fn connect(&self) -> io::Result<PlainStream> {
+        self.connect_with(|_| Ok(())).await
+    }
+
+    /// Like [`connect`](Self::connect), with `configure` applied to a TCP
+    /// socket before it connects, so options such as keepalive are in place
+    /// before the first byte moves.
+    pub async fn connect_with(
+        &self,
+        configure: impl FnOnce(&TcpSocket) -> io::Result<()>,
+    ) -> io::Result<PlainStream> {
+        match self {
+            PlainEndpoint::Tcp(addr) => {
+                let socket = match addr {
+                    SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                    SocketAddr::V6(_) => TcpSocket::new_v6()?,
+                };
+                configure(&socket)?;
+                Ok(PlainStream::Tcp(socket.connect(*addr).await?))
+            }
+            PlainEndpoint::Unix(path) => Ok(PlainStream::Unix(UnixStream::connect(path).await?)),
+        }
+    }
+}
+
+impl fmt::Display for PlainEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlainEndpoint::Tcp(addr) => write!(f, "{addr}"),
+            PlainEndpoint::Unix(path) => write!(f, "{UNIX_PREFIX}{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PlainListener {
+    Tcp(TcpListener),
+    Unix {
+        listener: UnixListener,
+        path: PathBuf,
+        /// Whether the socket file was created here and is removed on drop.
+        owned: bool,
+    },
+}
+
+impl PlainListener {
+    pub async fn bind(endpoint: &PlainEndpoint) -> io::Result<Self> {
+        match endpoint {
+            PlainEndpoint::Tcp(addr) => Ok(PlainListener::Tcp(TcpListener::bind(addr).await?)),
+            PlainEndpoint::Unix(path) => {
+                remove_stale_socket(path)?;
+                Ok(PlainListener::Unix {
+                    listener: UnixListener::bind(path)?,
+                    path: path.clone(),
+                    owned: true,
+                })
+            }
+        }
+    }
+
+    /// Adopts a listening TCP or unix socket inherited from systemd.
+    #[cfg(target_os = "linux")]
+    pub fn from_inherited(fd: OwnedFd) -> io::Result<Self> {
+        let socket = socket2::Socket::from(fd);
+        if !socket.is_listener()? || socket.r#type()? != socket2::Type::STREAM {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "inherited socket is not a listening stream socket",
+            ));
+        }
+        socket.set_nonblocking(true)?;
+
+        if socket.domain()? == socket2::Domain::UNIX {
+            let listener = std::os::unix::net::UnixListener::from(OwnedFd::from(socket));
+            let path = listener
+                .local_addr()?
+                .as_pathname()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            return Ok(PlainListener::Unix {
+                listener: UnixListener::from_std(listener)?,
+                path,
+                owned: false,
+            });
+        }
+        Ok(PlainListener::Tcp(TcpListener::from_std(socket.into())?))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn from_inherited(_fd: OwnedFd) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "socket activation is only supported on linux",
+        ))
+    }
+
+    /// The address actually listened on, which for an inherited socket may
+    /// differ from the configured one.
+    pub fn endpoint(&self) -> io::Result<PlainEndpoint> {
+        match self {
+            PlainListener::Tcp(listener) => Ok(PlainEndpoint::Tcp(listener.local_addr()?)),
+            PlainListener::Unix { path, .. } => Ok(PlainEndpoint::Unix(path.clone())),
+        }
+    }
+
+    /// Unix clients whose credentials cannot be read are dropped here rather
+    /// than surfaced, so one bad client cannot stop the accept loop.
+    pub async fn accept(&self) -> io::Result<(PlainStream, Peer)> {
+        match self {
+            PlainListener::Tcp(listener) => {
+                let (stream, peer) = listener.accept().await?;
+                Ok((PlainStream::Tcp(stream), Peer::Tcp(peer)))
+            }
+            PlainListener::Unix { listener, path, .. } => loop {
+                let (stream, _) = listener.accept().await?;
+                match stream.peer_cred() {
+                    Ok(cred) => {
+                        let peer = Peer::Unix {
+                            pid: cred.pid(),
+                            uid: cred.uid(),
+                            gid: cred.gid(),
+                        };
+                        return Ok((PlainStream::Unix(stream), peer));
+                    }
+                    Err(err) => warn!(
+                        socket = %path.display(),
+                        error = %err,
+                        "dropping unix client without peer credentials"
+                    ),
+                }
+            },
+        }
+    }
+}
+
+impl AsFd for PlainListener {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        match self {
+            PlainListener::Tcp(listener) => listener.as_fd(),
+            PlainListener::Unix { listener, .. } => listener.as_fd(),
+        }
+    }
+}
+
+impl Drop for PlainListener {
+    fn drop(&mut self) {
+        if let PlainListener::Unix {
+            path, owned: true, ..
+        } = self
+        {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Only ever removes a socket; any other file at the path is left alone and
+/// the bind fails.
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a unix socket", path.display()),
+        )),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[derive(Debug)]
+pub enum PlainStream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl PlainStream {
+    /// TCP options such as keepalive only apply to the TCP variant.
+    pub fn as_tcp(&self) -> Option<&TcpStream> {
+        match self {
+            PlainStream::Tcp(stream) => Some(stream),
+            PlainStream::Unix(_) => None,
+        }
+    }
+}
+
+impl AsRawFd for PlainStream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            PlainStream::Tcp(stream) => stream.as_raw_fd(),
+            PlainStream::Unix(stream) => stream.as_raw_fd(),
+        }
+    }
+}
+
+impl AsyncRead for PlainStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            PlainStream::Tcp(stream) => Pin::new(stream).poll_read(cx, buf),
+            PlainStream::Unix(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for PlainStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            PlainStream::Tcp(stream) => Pin::new(stream).poll_write(cx, buf),
+            PlainStream::Unix(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            PlainStream::Tcp(stream) => Pin::new(stream).poll_flush(cx),
+            PlainStream::Unix(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            PlainStream::Tcp(stream) => Pin::new(stream).poll_shutdown(cx),
+            PlainStream::Unix(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    #[test]
+    fn endpoint_parses_tcp_and_unix() {
+        assert_eq!(
+            PlainEndpoint::parse("127.0.0.1:5000").expect("tcp endpoint"),
+            PlainEndpoint::Tcp("127.0.0.1:5000".parse().expect("valid addr"))
+        );
+        let unix = PlainEndpoint::parse("unix:/run/tcpao/bmp.sock").expect("unix endpoint");
+        assert_eq!(
+            unix,
+            PlainEndpoint::Unix(PathBuf::from("/run/tcpao/bmp.sock"))
+        );
+        assert_eq!(unix.to_string(), "unix:/run/tcpao/bmp.sock");
+
+        assert!(PlainEndpoint::parse("unix:").is_err());
+        assert!(PlainEndpoint::parse("localhost").is_err());
+    }
+
+    #[tokio::test]
+    async fn unix_listener_replaces_stale_socket_and_cleans_up() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("plain.sock");
+        let endpoint = PlainEndpoint::Unix(path.clone());
+
+        // A socket file left behind by a killed process.
+        drop(std::os::unix::net::UnixListener::bind(&path).expect("stale socket"));
+        assert!(path.exists());
+
+        let listener = PlainListener::bind(&endpoint)
+            .await
+            .expect("bind over stale socket");
+        let mut client = endpoint.connect().await.expect("connect");
+        let (mut server, peer) = listener.accept().await.expect("accept");
+        match peer {
+            Peer::Unix { uid, .. } => assert_eq!(uid, unsafe { libc::geteuid() }),
+            other => panic!("unexpected peer {other:?}"),
+        }
+
+        client.write_all(b"bmp").await.expect("write");
+        let mut buf = [0_u8; 3];
+        server.read_exact(&mut buf).await.expect("read");
+        assert_eq!(&buf, b"bmp");
+
+        drop(listener);
+        assert!(!path.exists(), "socket file removed on shutdown");
+    }
+
+    #[tokio::test]
+    async fn unix_listener_refuses_to_replace_regular_file() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("plain.file");
+        std::fs::write(&path, b"not a socket").expect("regular file");
+
+        let err = PlainListener::bind(&PlainEndpoint::Unix(path.clone()))
+            .await
+            .expect_err("regular file must not be removed");
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(path.exists());
+    }
+}
@@ -0,0 +1,251 @@
+//! HAProxy PROXY protocol v2 headers for the plain legs.
+//!
+//! The terminator can prefix `forward_plain` connections with a header that
+//! carries the router's wire address, so the collector does not see every BMP
+//! session as coming from the proxy. The matched AO policy name and key id
+//! travel in custom TLVs. The initiator can accept the same header on its
+//! plain listener when it sits behind another proxy.
+
+use std::io;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+const VERSION_2: u8 = 0x20;
+const CMD_LOCAL: u8 = 0x00;
+const CMD_PROXY: u8 = 0x01;
+const FAMILY_UNSPEC: u8 = 0x00;
+const TCP_OVER_IPV4: u8 = 0x11;
+const TCP_OVER_IPV6: u8 = 0x21;
+const IPV4_ADDRS_LEN: usize = 12;
+const IPV6_ADDRS_LEN: usize = 36;
+
+/// TLV carrying the name of the AO policy that matched the wire peer.
+pub const TLV_AO_POLICY: u8 = 0xE0;
+/// TLV carrying the AO key id the session was authenticated with.
+pub const TLV_AO_KEYID: u8 = 0xE1;
+
+/// Bound on how long a client may take to send its header.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxyHeader {
+    /// `None` for `LOCAL` headers and address families other than TCP.
+    pub source: Option<SocketAddr>,
+    pub destination: Option<SocketAddr>,
+    pub policy: Option<String>,
+    pub keyid: Option<u8>,
+}
+
+impl ProxyHeader {
+    pub fn new(source: SocketAddr, destination: SocketAddr) -> Self {
+        Self {
+            source: Some(source),
+            destination: Some(destination),
+            ..Self::default()
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        let (command, family) = match (self.source, self.destination) {
+            (Some(SocketAddr::V4(src)), Some(SocketAddr::V4(dst))) => {
+                body.extend_from_slice(&src.ip().octets());
+                body.extend_from_slice(&dst.ip().octets());
+                body.extend_from_slice(&src.port().to_be_bytes());
+                body.extend_from_slice(&dst.port().to_be_bytes());
+                (CMD_PROXY, TCP_OVER_IPV4)
+            }
+            (Some(src), Some(dst)) => {
+                // Mixed families are sent as IPv4-mapped IPv6.
+                body.extend_from_slice(&to_v6(src.ip()).octets());
+                body.extend_from_slice(&to_v6(dst.ip()).octets());
+                body.extend_from_slice(&src.port().to_be_bytes());
+                body.extend_from_slice(&dst.port().to_be_bytes());
+                (CMD_PROXY, TCP_OVER_IPV6)
+            }
+            _ => (CMD_LOCAL, FAMILY_UNSPEC),
+        };
+
+        if let Some(policy) = &self.policy {
+            push_tlv(&mut body, TLV_AO_POLICY, policy.as_bytes());
+        }
+        if let Some(keyid) = self.keyid {
+            push_tlv(&mut body, TLV_AO_KEYID, &[keyid]);
+        }
+
+        let mut out = Vec::with_capacity(16 + body.len());
+        out.extend_from_slice(&SIGNATURE);
+        out.push(VERSION_2 | command);
+        out.push(family);
+        out.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        out.extend_from_slice(&body);
+        out
+    }
+
+    /// Reads exactly one header from `reader`, leaving any payload behind it
+    /// unread.
+    pub async fn read<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Self> {
+        let mut fixed = [0_u8; 16];
+        reader.read_exact(&mut fixed).await?;
+
+        if fixed[..12] != SIGNATURE {
+            return Err(invalid("missing PROXY protocol v2 signature"));
+        }
+        if fixed[12] & 0xF0 != VERSION_2 {
+            return Err(invalid("unsupported PROXY protocol version"));
+        }
+        let command = fixed[12] & 0x0F;
+        if command != CMD_LOCAL && command != CMD_PROXY {
+            return Err(invalid("unsupported PROXY protocol command"));
+        }
+
+        let len = usize::from(u16::from_be_bytes([fixed[14], fixed[15]]));
+        let mut body = vec![0_u8; len];
+        reader.read_exact(&mut body).await?;
+
+        Self::decode(command, fixed[13], &body)
+    }
+
+    fn decode(command: u8, family: u8, body: &[u8]) -> io::Result<Self> {
+        let mut header = Self::default();
+        let addrs_len = match family {
+            TCP_OVER_IPV4 => IPV4_ADDRS_LEN,
+            TCP_OVER_IPV6 => IPV6_ADDRS_LEN,
+            _ => 0,
+        };
+        if body.len() < addrs_len {
+            return Err(invalid("truncated PROXY protocol address block"));
+        }
+
+        if command == CMD_PROXY && addrs_len > 0 {
+            let (src, dst, ports) = if family == TCP_OVER_IPV4 {
+                let src: [u8; 4] = body[0..4].try_into().expect("4 byte slice");
+                let dst: [u8; 4] = body[4..8].try_into().expect("4 byte slice");
+                (IpAddr::from(src), IpAddr::from(dst), &body[8..12])
+            } else {
+                let src: [u8; 16] = body[0..16].try_into().expect("16 byte slice");
+                let dst: [u8; 16] = body[16..32].try_into().expect("16 byte slice");
+                (
+                    IpAddr::from(src).to_canonical(),
+                    IpAddr::from(dst).to_canonical(),
+                    &body[32..36],
+                )
+            };
+            header.source = Some(SocketAddr::new(
+                src,
+                u16::from_be_bytes([ports[0], ports[1]]),
+            ));
+            header.destination = Some(SocketAddr::new(
+                dst,
+                u16::from_be_bytes([ports[2], ports[3]]),
+            ));
+        }
+
+        let mut tlvs = &body[addrs_len..];
+        while !tlvs.is_empty() {
+            if tlvs.len() < 3 {
+                return Err(invalid("truncated PROXY protocol TLV"));
+            }
+            let kind = tlvs[0];
+            let len = usize::from(u16::from_be_bytes([tlvs[1], tlvs[2]]));
+            let value = tlvs
+                .get(3..3 + len)
+                .ok_or_else(|| invalid("truncated PROXY protocol TLV"))?;
+
+            match kind {
+                TLV_AO_POLICY => {
+                    header.policy = Some(String::from_utf8_lossy(value).into_owned());
+                }
+                TLV_AO_KEYID => header.keyid = value.first().copied(),
+                _ => {}
+            }
+            tlvs = &tlvs[3 + len..];
+        }
+
+        Ok(header)
+    }
+}
+
+fn push_tlv(body: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    body.push(kind);
+    body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    body.extend_from_slice(value);
+}
+
+fn to_v6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(v4) => v4.to_ipv6_mapped(),
+        IpAddr::V6(v6) => v6,
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(raw: &str) -> SocketAddr {
+        raw.parse().expect("valid addr")
+    }
+
+    async fn round_trip(header: &ProxyHeader) -> ProxyHeader {
+        let mut wire = header.encode();
+        wire.extend_from_slice(b"BMP");
+        let mut reader = &wire[..];
+        let decoded = ProxyHeader::read(&mut reader).await.expect("valid header");
+        assert_eq!(reader, b"BMP", "payload after the header is left unread");
+        decoded
+    }
+
+    #[tokio::test]
+    async fn ipv4_header_round_trips_with_tlvs() {
+        let header = ProxyHeader {
+            policy: Some("bmp-peer-1".to_string()),
+            keyid: Some(7),
+            ..ProxyHeader::new(addr("10.0.0.2:40000"), addr("10.0.0.1:1790"))
+        };
+        let encoded = header.encode();
+        assert_eq!(&encoded[..12], &SIGNATURE);
+        assert_eq!(encoded[12], 0x21);
+        assert_eq!(encoded[13], TCP_OVER_IPV4);
+
+        assert_eq!(round_trip(&header).await, header);
+    }
+
+    #[tokio::test]
+    async fn ipv6_and_mixed_family_headers_round_trip() {
+        let v6 = ProxyHeader::new(addr("[2001:db8::2]:40000"), addr("[2001:db8::1]:1790"));
+        assert_eq!(round_trip(&v6).await, v6);
+
+        let mixed = ProxyHeader::new(addr("192.0.2.1:40000"), addr("[2001:db8::1]:1790"));
+        assert_eq!(mixed.encode()[13], TCP_OVER_IPV6);
+        assert_eq!(round_trip(&mixed).await, mixed);
+    }
+
+    #[tokio::test]
+    async fn local_header_has_no_addresses() {
+        let local = ProxyHeader::default();
+        assert_eq!(local.encode()[12], 0x20);
+        assert_eq!(round_trip(&local).await, local);
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_signature_and_truncated_tlv() {
+        let mut reader = &b"PROXY TCP4 10.0.0.1 10.0.0.2 1 2\r\n"[..];
+        let err = ProxyHeader::read(&mut reader).await.expect_err("v1 header");
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let mut wire = ProxyHeader::new(addr("10.0.0.2:1"), addr("10.0.0.1:2")).encode();
+        wire.extend_from_slice(&[TLV_AO_POLICY, 0, 9, b'x']);
+        let len = (wire.len() - 16) as u16;
+        wire[14..16].copy_from_slice(&len.to_be_bytes());
+        let mut reader = &wire[..];
+        assert!(ProxyHeader::read(&mut reader).await.is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! In-memory handling of AO master keys.
+//!
+//! `KeyMaterial` owns key bytes in a dedicated page-aligned allocation that is
+//! locked into RAM (best effort) and wiped on drop. Giving every key its own
+//! pages keeps `munlock` on drop from unlocking a neighbouring key. Its
+//! `Debug` output only shows the length and fingerprint.
+
+use std::alloc::{self, Layout};
+use std::fmt;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicBool;
+
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+static MLOCK_WARNED: AtomicBool = AtomicBool::new(false);
+
+pub struct KeyMaterial {
+    ptr: NonNull<u8>,
+    len: usize,
+    layout: Layout,
+    locked: bool,
+}
+
+// The allocation is exclusively owned and never mutated after construction.
+unsafe impl Send for KeyMaterial {}
+unsafe impl Sync for KeyMaterial {}
+
+impl KeyMaterial {
+    /// Takes ownership of `raw` and wipes its whole buffer, spare capacity
+    /// included.
+    pub fn new(mut raw: Vec<u8>) -> Self {
+        let page = page_size();
+        let size = raw.len().max(1).div_ceil(page) * page;
+        let layout = Layout::from_size_align(size, page).expect("page-aligned key layout");
+
+        let ptr = NonNull::new(unsafe { alloc::alloc_zeroed(layout) })
+            .unwrap_or_else(|| alloc::handle_alloc_error(layout));
+        unsafe { std::ptr::copy_nonoverlapping(raw.as_ptr(), ptr.as_ptr(), raw.len()) };
+        let len = raw.len();
+        raw.zeroize();
+
+        let locked = lock_pages(ptr.as_ptr(), size);
+        Self {
+            ptr,
+            len,
+            layout,
+            locked,
+        }
+    }
+
+    pub fn from_slice(raw: &[u8]) -> Self {
+        Self::new(raw.to_vec())
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Short, non-reversible identifier safe to log and compare across hosts.
+    pub fn fingerprint(&self) -> String {
+        fingerprint(self.as_bytes())
+    }
+}
+
+impl Clone for KeyMaterial {
+    fn clone(&self) -> Self {
+        Self::from_slice(self.as_bytes())
+    }
+}
+
+impl PartialEq for KeyMaterial {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl Eq for KeyMaterial {}
+
+impl Drop for KeyMaterial {
+    fn drop(&mut self) {
+        let whole =
+            unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.layout.size()) };
+        whole.zeroize();
+        if self.locked {
+            unlock_pages(self.ptr.as_ptr(), self.layout.size());
+        }
+        unsafe { alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+impl fmt::Debug for KeyMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyMaterial")
+            .field("len", &self.len)
+            .field("fingerprint", &self.fingerprint())
+            .finish()
+    }
+}
+
+/// `sha256:` followed by the first 8 bytes of the SHA-256 digest in hex.
+pub fn fingerprint(key: &[u8]) -> String {
+    let digest = Sha256::digest(key);
+    let mut out = String::from("sha256:");
+    for b in &digest[..8] {
+        out.push_str(&format!("{b:02x}"));
+    }
+    out
+}
+
+/// Marks the process non-dumpable so key material never lands in a core file
+/// or becomes readable through `/proc/<pid>/mem` by other same-uid processes.
+#[cfg(target_os = "linux")]
+pub fn disable_core_dumps() -> std::io::Result<()> {
+    let rc = unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) };
+    if rc == 0 {
+        return Ok(());
+    }
+
+    Err(std::io::Error::last_os_error())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn disable_core_dumps() -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn lock_pages(ptr: *const u8, size: usize) -> bool {
+    let rc = unsafe { libc::mlock(ptr.cast(), size) };
+    if rc == 0 {
+        return true;
+    }
+
+    if !MLOCK_WARNED.swap(true, std::sync::atomic::Ordering::Relaxed) {
+        tracing::warn!(
+            error = %std::io::Error::last_os_error(),
+            "mlock of key material failed; keys may be swapped (raise RLIMIT_MEMLOCK or grant CAP_IPC_LOCK)"
+        );
+    }
+    false
+}
+
+#[cfg(target_os = "linux")]
+fn unlock_pages(ptr: *const u8, size: usize) {
+    let _ = unsafe { libc::munlock(ptr.cast(), size) };
+}
+
+#[cfg(target_os = "linux")]
+fn page_size() -> usize {
+    let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    if size > 0 {
+        size as usize
+    } else {
+        4096
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn lock_pages(_ptr: *const u8, _size: usize) -> bool {
+    let _ = &MLOCK_WARNED;
+    false
+}
+
+#[cfg(not(target_os = "linux"))]
+fn unlock_pages(_ptr: *const u8, _size: usize) {}
+
+#[cfg(not(target_os = "linux"))]
+fn page_size() -> usize {
+    4096
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_output_hides_key_bytes() {
+        let key = KeyMaterial::from_slice(b"super-secret-key");
+        let rendered = format!("{key:?}");
+        assert!(!rendered.contains("super-secret-key"));
+        assert!(rendered.contains("len: 16"));
+        assert!(rendered.contains("sha256:"));
+    }
+
+    #[test]
+    fn fingerprint_is_stable_and_short() {
+        let fp = fingerprint(b"tcpao-functional-key");
+        assert_eq!(fp, fingerprint(b"tcpao-functional-key"));
+        assert_eq!(fp.len(), "sha256:".len() + 16);
+        assert_ne!(fp, fingerprint(b"other-key"));
+    }
+
+    #[test]
+    fn clone_and_empty_keys_are_independent_allocations() {
+        let key = KeyMaterial::from_slice(b"abc");
+        let copy = key.clone();
+        drop(key);
+        assert_eq!(copy.as_bytes(), b"abc");
+
+        let empty = KeyMaterial::new(Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.as_bytes(), b"");
+    }
+
+    #[test]
+    fn new_trims_spare_capacity() {
+        let mut raw = Vec::with_capacity(64);
+        raw.extend_from_slice(b"abc");
+        let key = KeyMaterial::new(raw);
+        assert_eq!(key.as_bytes(), b"abc");
+        assert_eq!(key.len(), 3);
+    }
+}
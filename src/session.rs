@@ -0,0 +1,601 @@
+//! Live session tracking and admission control.
+//!
+//! Every accepted connection is admitted through a `SessionRegistry` before a
+//! task is spawned for it. The registry enforces the global and per-peer
+//! connection limits and the accept rate, and hands back a `SessionGuard` that
+//! unregisters the session when dropped. The admin API reads live sessions
+//! from the same registry and can ask one to close.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tracing::warn;
+
+use crate::config::{GlobalConfig, LimitAction};
+use crate::forward::Traffic;
+
+/// Pause after a failed `accept` before the listener is polled again.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Logs a failed `accept` and waits briefly before the caller retries.
+/// Accept errors concern one connection (ECONNABORTED) or a passing resource
+/// shortage (EMFILE, ENFILE, ENOBUFS); the listener itself stays usable, so
+/// they must not end the accept loop. The pause keeps an fd shortage from
+/// turning into a busy loop while sessions close.
+pub async fn accept_failed(mode: &'static str, err: &io::Error) {
+    warn!(mode, error = %err, "accept failed; retrying");
+    tokio::time::sleep(ACCEPT_BACKOFF).await;
+}
+
+/// Why a new connection was not admitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    RateLimited,
+    MaxConnections,
+    MaxConnectionsPerPeer,
+}
+
+impl Rejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rejection::RateLimited => "accept_rate_limited",
+            Rejection::MaxConnections => "max_connections",
+            Rejection::MaxConnectionsPerPeer => "max_connections_per_peer",
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The client side of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    /// A unix socket client, identified by its `SO_PEERCRED` credentials.
+    Unix {
+        pid: Option<i32>,
+        uid: u32,
+        gid: u32,
+    },
+}
+
+impl Peer {
+    /// What `max_connections_per_peer` counts: the source IP for TCP clients
+    /// and the uid for unix clients.
+    fn limit_key(&self) -> PeerKey {
+        match self {
+            Peer::Tcp(addr) => PeerKey::Ip(addr.ip()),
+            Peer::Unix { uid, .. } => PeerKey::Uid(*uid),
+        }
+    }
+}
+
+impl From<SocketAddr> for Peer {
+    fn from(addr: SocketAddr) -> Self {
+        Peer::Tcp(addr)
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{addr}"),
+            Peer::Unix { pid, uid, gid } => {
+                write!(f, "unix(uid={uid},gid={gid}")?;
+                if let Some(pid) = pid {
+                    write!(f, ",pid={pid}")?;
+                }
+                f.write_str(")")
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PeerKey {
+    Ip(IpAddr),
+    Uid(u32),
+}
+
+/// Why a live session was told to close.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KillReason {
+    /// Evicted under `evict-oldest` to admit a newer session.
+    Evicted,
+    /// Closed through the admin API.
+    Admin,
+}
+
+/// What is known about a session beyond its client, filled in by the mode
+/// handler as the session is set up.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionDetails {
+    pub policy: Option<String>,
+    pub keyid: Option<u8>,
+    pub rnextkeyid: Option<u8>,
+    /// The far end of the AO leg.
+    pub wire_peer: Option<SocketAddr>,
+    /// Whether the AO leg is actually protected.
+    pub protected: Option<bool>,
+    /// The original client, from an accepted PROXY protocol header.
+    pub client: Option<SocketAddr>,
+    /// The upstream AO policy and key id carried in that header.
+    pub upstream_policy: Option<String>,
+    pub upstream_keyid: Option<u8>,
+}
+
+/// A live session as shared between its task and the registry.
+#[derive(Debug)]
+pub struct Session {
+    conn_id: u64,
+    peer: Peer,
+    opened: Instant,
+    kill: Notify,
+    kill_reason: Mutex<Option<KillReason>>,
+    details: Mutex<SessionDetails>,
+    traffic: Traffic,
+    wire: OnceLock<OwnedFd>,
+}
+
+impl Session {
+    pub fn conn_id(&self) -> u64 {
+        self.conn_id
+    }
+
+    pub fn peer(&self) -> Peer {
+        self.peer
+    }
+
+    pub fn age(&self) -> Duration {
+        self.opened.elapsed()
+    }
+
+    pub fn details(&self) -> SessionDetails {
+        self.details
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn traffic(&self) -> &Traffic {
+        &self.traffic
+    }
+
+    /// The AO socket, for reading its live key counters.
+    pub fn wire_fd(&self) -> Option<BorrowedFd<'_>> {
+        self.wire.get().map(AsFd::as_fd)
+    }
+
+    fn kill(&self, reason: KillReason) {
+        self.kill_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_or_insert(reason);
+        self.kill.notify_one();
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    // Connection ids are handed out in accept order, so the first entry is
+    // always the oldest session.
+    sessions: BTreeMap<u64, Arc<Session>>,
+    per_peer: HashMap<PeerKey, usize>,
+    bucket: Option<TokenBucket>,
+}
+
+impl State {
+    fn remove(&mut self, conn_id: u64) -> Option<Arc<Session>> {
+        let session = self.sessions.remove(&conn_id)?;
+        let key = session.peer.limit_key();
+        if let Some(count) = self.per_peer.get_mut(&key) {
+            *count -= 1;
+            if *count == 0 {
+                self.per_peer.remove(&key);
+            }
+        }
+        Some(session)
+    }
+
+    fn kill(&mut self, conn_id: u64, reason: KillReason) -> bool {
+        match self.remove(conn_id) {
+            Some(session) => {
+                session.kill(reason);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SessionRegistry {
+    max_connections: Option<usize>,
+    max_connections_per_peer: Option<usize>,
+    on_limit: LimitAction,
+    state: Mutex<State>,
+}
+
+impl SessionRegistry {
+    pub fn new(global: &GlobalConfig) -> Self {
+        let bucket = global.accept_rate_per_sec.map(|rate| {
+            TokenBucket::new(rate, global.accept_burst.unwrap_or(rate), Instant::now())
+        });
+
+        Self {
+            max_connections: global.max_connections,
+            max_connections_per_peer: global.max_connections_per_peer,
+            on_limit: global.on_connection_limit,
+            state: Mutex::new(State {
+                bucket,
+                ..State::default()
+            }),
+        }
+    }
+
+    /// Registers a new session or reports why it must be refused. With
+    /// `evict-oldest`, the oldest session in the exhausted scope is signalled
+    /// to close and its slot is handed to the new connection.
+    pub fn admit(
+        self: &Arc<Self>,
+        conn_id: u64,
+        peer: impl Into<Peer>,
+    ) -> Result<SessionGuard, Rejection> {
+        let peer = peer.into();
+        let key = peer.limit_key();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(bucket) = state.bucket.as_mut() {
+            if !bucket.ready(Instant::now()) {
+                return Err(Rejection::RateLimited);
+            }
+        }
+
+        if let Some(limit) = self.max_connections_per_peer {
+            if state.per_peer.get(&key).copied().unwrap_or(0) >= limit {
+                if self.on_limit == LimitAction::Reject {
+                    return Err(Rejection::MaxConnectionsPerPeer);
+                }
+                let oldest = state
+                    .sessions
+                    .iter()
+                    .find(|(_, session)| session.peer.limit_key() == key)
+                    .map(|(id, _)| *id);
+                if let Some(oldest) = oldest {
+                    state.kill(oldest, KillReason::Evicted);
+                }
+            }
+        }
+
+        if let Some(limit) = self.max_connections {
+            if state.sessions.len() >= limit {
+                if self.on_limit == LimitAction::Reject {
+                    return Err(Rejection::MaxConnections);
+                }
+                let oldest = state.sessions.keys().next().copied();
+                if let Some(oldest) = oldest {
+                    state.kill(oldest, KillReason::Evicted);
+                }
+            }
+        }
+
+        // Only an admitted connection spends a token, so a flood refused by
+        // the other limits cannot drain the budget.
+        if let Some(bucket) = state.bucket.as_mut() {
+            bucket.consume();
+        }
+
+        let session = Arc::new(Session {
+            conn_id,
+            peer,
+            opened: Instant::now(),
+            kill: Notify::new(),
+            kill_reason: Mutex::new(None),
+            details: Mutex::new(SessionDetails::default()),
+            traffic: Traffic::default(),
+            wire: OnceLock::new(),
+        });
+        state.sessions.insert(conn_id, Arc::clone(&session));
+        *state.per_peer.entry(key).or_default() += 1;
+
+        Ok(SessionGuard {
+            registry: Arc::clone(self),
+            session,
+        })
+    }
+
+    /// Live sessions, oldest first.
+    pub fn snapshot(&self) -> Vec<Arc<Session>> {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .sessions
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Asks a session to close. Returns `false` if no such session is live.
+    pub fn kill(&self, conn_id: u64) -> bool {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .kill(conn_id, KillReason::Admin)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .sessions
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Keeps a session registered for as long as it is alive.
+#[derive(Debug)]
+pub struct SessionGuard {
+    registry: Arc<SessionRegistry>,
+    session: Arc<Session>,
+}
+
+impl SessionGuard {
+    pub fn conn_id(&self) -> u64 {
+        self.session.conn_id
+    }
+
+    /// Resolves once the registry evicts this session to make room for a
+    /// newer one, or an admin kills it.
+    pub async fn killed(&self) -> KillReason {
+        self.session.kill.notified().await;
+        self.session
+            .kill_reason
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .unwrap_or(KillReason::Evicted)
+    }
+
+    pub fn describe(&self, update: impl FnOnce(&mut SessionDetails)) {
+        update(
+            &mut self
+                .session
+                .details
+                .lock()
+                .unwrap_or_else(|e| e.into_inner()),
+        );
+    }
+
+    /// Keeps a duplicate of the AO socket so its key counters can be read
+    /// while the session runs. Best effort: without it they are not shown.
+    pub fn set_wire(&self, wire: &impl AsFd) {
+        if let Ok(fd) = wire.as_fd().try_clone_to_owned() {
+            let _ = self.session.wire.set(fd);
+        }
+    }
+
+    pub fn traffic(&self) -> &Traffic {
+        &self.session.traffic
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.registry
+            .state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(self.session.conn_id);
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, burst: u32, now: Instant) -> Self {
+        Self {
+            rate: f64::from(rate),
+            capacity: f64::from(burst),
+            tokens: f64::from(burst),
+            updated: now,
+        }
+    }
+
+    /// Refills for the time since the last call and reports whether a token
+    /// is available, without spending it.
+    fn ready(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.capacity);
+        self.updated = now;
+        self.tokens >= 1.0
+    }
+
+    fn consume(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const EVICT_WAIT: Duration = Duration::from_secs(1);
+
+    fn addr(raw: &str) -> SocketAddr {
+        raw.parse().expect("valid addr")
+    }
+
+    fn registry(global: GlobalConfig) -> Arc<SessionRegistry> {
+        Arc::new(SessionRegistry::new(&global))
+    }
+
+    #[test]
+    fn rejects_above_global_limit_and_frees_slot_on_drop() {
+        let sessions = registry(GlobalConfig {
+            max_connections: Some(2),
+            ..GlobalConfig::default()
+        });
+
+        let first = sessions.admit(1, addr("192.0.2.1:1000")).expect("admit");
+        let _second = sessions.admit(2, addr("192.0.2.2:1000")).expect("admit");
+        assert_eq!(
+            sessions.admit(3, addr("192.0.2.3:1000")).unwrap_err(),
+            Rejection::MaxConnections
+        );
+
+        drop(first);
+        assert!(sessions.admit(4, addr("192.0.2.3:1000")).is_ok());
+    }
+
+    #[test]
+    fn per_peer_limit_only_counts_that_peer() {
+        let sessions = registry(GlobalConfig {
+            max_connections_per_peer: Some(1),
+            ..GlobalConfig::default()
+        });
+
+        let _a = sessions.admit(1, addr("192.0.2.1:1000")).expect("admit");
+        assert_eq!(
+            sessions.admit(2, addr("192.0.2.1:1001")).unwrap_err(),
+            Rejection::MaxConnectionsPerPeer
+        );
+        assert!(sessions.admit(3, addr("192.0.2.2:1000")).is_ok());
+    }
+
+    #[test]
+    fn refused_connections_do_not_spend_accept_tokens() {
+        let sessions = registry(GlobalConfig {
+            max_connections_per_peer: Some(1),
+            accept_rate_per_sec: Some(1),
+            accept_burst: Some(2),
+            ..GlobalConfig::default()
+        });
+
+        let _a = sessions.admit(1, addr("192.0.2.1:1000")).expect("admit");
+        for conn_id in 2..10 {
+            assert_eq!(
+                sessions.admit(conn_id, addr("192.0.2.1:1001")).unwrap_err(),
+                Rejection::MaxConnectionsPerPeer
+            );
+        }
+        assert!(sessions.admit(10, addr("192.0.2.2:1000")).is_ok());
+        assert_eq!(
+            sessions.admit(11, addr("192.0.2.3:1000")).unwrap_err(),
+            Rejection::RateLimited
+        );
+    }
+
+    #[test]
+    fn unix_clients_are_limited_per_uid() {
+        let sessions = registry(GlobalConfig {
+            max_connections_per_peer: Some(1),
+            ..GlobalConfig::default()
+        });
+        let unix = |uid| Peer::Unix {
+            pid: None,
+            uid,
+            gid: 0,
+        };
+
+        let _a = sessions.admit(1, unix(1000)).expect("admit");
+        assert_eq!(
+            sessions.admit(2, unix(1000)).unwrap_err(),
+            Rejection::MaxConnectionsPerPeer
+        );
+        assert!(sessions.admit(3, unix(0)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn evict_oldest_signals_the_oldest_session_of_the_peer() {
+        let sessions = registry(GlobalConfig {
+            max_connections_per_peer: Some(2),
+            on_connection_limit: LimitAction::EvictOldest,
+            ..GlobalConfig::default()
+        });
+
+        let other = sessions.admit(1, addr("192.0.2.9:1000")).expect("admit");
+        let oldest = sessions.admit(2, addr("192.0.2.1:1000")).expect("admit");
+        let _newer = sessions.admit(3, addr("192.0.2.1:1001")).expect("admit");
+        let _newest = sessions.admit(4, addr("192.0.2.1:1002")).expect("admit");
+
+        let reason = tokio::time::timeout(EVICT_WAIT, oldest.killed())
+            .await
+            .expect("oldest session of the peer is evicted");
+        assert_eq!(reason, KillReason::Evicted);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), other.killed())
+                .await
+                .is_err(),
+            "other peers are untouched"
+        );
+        assert_eq!(sessions.len(), 3);
+
+        drop(oldest);
+        assert_eq!(sessions.len(), 3, "dropping an evicted guard is a no-op");
+    }
+
+    #[tokio::test]
+    async fn admin_kill_reports_reason_and_snapshot_tracks_sessions() {
+        let sessions = registry(GlobalConfig::default());
+        let first = sessions.admit(1, addr("192.0.2.1:1000")).expect("admit");
+        let _second = sessions.admit(2, addr("192.0.2.2:1000")).expect("admit");
+        first.describe(|d| d.policy = Some("bmp-peer-1".to_string()));
+
+        let live = sessions.snapshot();
+        assert_eq!(
+            live.iter().map(|s| s.conn_id()).collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+        assert_eq!(live[0].details().policy.as_deref(), Some("bmp-peer-1"));
+
+        assert!(sessions.kill(1));
+        assert!(!sessions.kill(1), "already removed");
+        assert!(!sessions.kill(99));
+        let reason = tokio::time::timeout(EVICT_WAIT, first.killed())
+            .await
+            .expect("killed session is signalled");
+        assert_eq!(reason, KillReason::Admin);
+        assert_eq!(sessions.len(), 1);
+    }
+
+    #[test]
+    fn token_bucket_refills_at_rate() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(2, 2, start);
+        let mut take = |now| {
+            let ready = bucket.ready(now);
+            if ready {
+                bucket.consume();
+            }
+            ready
+        };
+
+        assert!(take(start));
+        assert!(take(start));
+        assert!(!take(start));
+        assert!(take(start + Duration::from_millis(500)));
+        assert!(!take(start + Duration::from_millis(600)));
+        assert!(take(start + Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,347 @@
+//! Zero-copy forwarding with `splice(2)`.
+//!
+//! Each direction moves bytes socket -> pipe -> socket inside the kernel, so
+//! payload never reaches userspace. The pipe is always drained before the
+//! next read, which means `EAGAIN` from either splice call can only come from
+//! the socket side and is safe to report to tokio as a readiness miss.
+//!
+//! The copy loop's limits apply here too: each read moves at most
+//! `buffer_size` bytes, and each pipe is sized to `max_buffered_bytes`
+//! (rounded up to whole pages by the kernel) so that much at most sits
+//! between the two sockets.
+
+use std::future::poll_fn;
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use tokio::io::Interest;
+use tokio::net::{TcpStream, UnixStream};
+
+use crate::forward::{
+    drive, Activity, CloseReason, Direction, Flow, PumpOptions, PumpStats, Traffic,
+};
+use crate::plain::PlainStream;
+
+/// A socket that tokio drives and whose fd can be handed to `splice`.
+pub trait SpliceIo: AsRawFd {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> io::Result<R>) -> io::Result<R>;
+}
+
+macro_rules! impl_splice_io {
+    ($ty:ty) => {
+        impl SpliceIo for $ty {
+            fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                <$ty>::poll_read_ready(self, cx)
+            }
+
+            fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                <$ty>::poll_write_ready(self, cx)
+            }
+
+            fn try_io<R>(
+                &self,
+                interest: Interest,
+                f: impl FnOnce() -> io::Result<R>,
+            ) -> io::Result<R> {
+                <$ty>::try_io(self, interest, f)
+            }
+        }
+    };
+}
+
+impl_splice_io!(TcpStream);
+impl_splice_io!(UnixStream);
+
+impl SpliceIo for PlainStream {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            PlainStream::Tcp(stream) => stream.poll_read_ready(cx),
+            PlainStream::Unix(stream) => stream.poll_read_ready(cx),
+        }
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            PlainStream::Tcp(stream) => stream.poll_write_ready(cx),
+            PlainStream::Unix(stream) => stream.poll_write_ready(cx),
+        }
+    }
+
+    fn try_io<R>(&self, interest: Interest, f: impl FnOnce() -> io::Result<R>) -> io::Result<R> {
+        match self {
+            PlainStream::Tcp(stream) => stream.try_io(interest, f),
+            PlainStream::Unix(stream) => stream.try_io(interest, f),
+        }
+    }
+}
+
+/// A non-blocking pipe used as the in-kernel buffer for one direction.
+pub struct Pipe {
+    read: OwnedFd,
+    write: OwnedFd,
+}
+
+#[cfg(target_os = "linux")]
+impl Pipe {
+    fn new(capacity: usize) -> io::Result<Self> {
+        use std::os::fd::FromRawFd;
+
+        let mut fds = [0; 2];
+        let rc = unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let pipe = unsafe {
+            Self {
+                read: OwnedFd::from_raw_fd(fds[0]),
+                write: OwnedFd::from_raw_fd(fds[1]),
+            }
+        };
+        // The pipe-max-size sysctl may refuse a large pipe; the default one
+        // still bounds what is buffered, just not at the configured size.
+        let size = i32::try_from(capacity).unwrap_or(i32::MAX);
+        let rc = unsafe { libc::fcntl(pipe.write.as_raw_fd(), libc::F_SETPIPE_SZ, size) };
+        if rc < 0 {
+            tracing::debug!(
+                capacity,
+                error = %io::Error::last_os_error(),
+                "could not resize splice pipe; keeping the default size"
+            );
+        }
+        Ok(pipe)
+    }
+}
+
+/// Creates both pipes up front, sized to `opts.max_buffered_bytes`, so a
+/// caller can fall back to the copy loop before any bytes have moved.
+#[cfg(target_os = "linux")]
+pub fn pipes(opts: &PumpOptions) -> io::Result<(Pipe, Pipe)> {
+    Ok((
+        Pipe::new(opts.max_buffered_bytes)?,
+        Pipe::new(opts.max_buffered_bytes)?,
+    ))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pipes(_opts: &PumpOptions) -> io::Result<(Pipe, Pipe)> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "splice is only supported on linux",
+    ))
+}
+
+/// Same contract as [`crate::forward::pump`]: each direction half-closes its
+/// destination on EOF, and both sockets are shut down after an error or idle
+/// timeout.
+pub async fn pump<S, D>(
+    source: &S,
+    destination: &D,
+    pipes: (Pipe, Pipe),
+    opts: PumpOptions,
+    traffic: &Traffic,
+) -> PumpStats
+where
+    S: SpliceIo,
+    D: SpliceIo,
+{
+    let activity = Activity::new();
+    let (up_pipe, down_pipe) = pipes;
+
+    let chunk = opts.buffer_size.max(1);
+    let upstream = one_way(
+        source,
+        destination,
+        &up_pipe,
+        chunk,
+        &traffic.up,
+        &activity,
+        Direction::Up,
+    );
+    let downstream = one_way(
+        destination,
+        source,
+        &down_pipe,
+        chunk,
+        &traffic.down,
+        &activity,
+        Direction::Down,
+    );
+    let stats = drive(upstream, downstream, traffic, &activity, opts).await;
+
+    if stats.reason != CloseReason::Completed {
+        shutdown_write(source.as_raw_fd());
+        shutdown_write(destination.as_raw_fd());
+    }
+    stats
+}
+
+async fn one_way<S, D>(
+    from: &S,
+    to: &D,
+    pipe: &Pipe,
+    chunk: usize,
+    flow: &Flow,
+    activity: &Activity,
+    direction: Direction,
+) -> io::Result<()>
+where
+    S: SpliceIo,
+    D: SpliceIo,
+{
+    loop {
+        let moved = loop {
+            poll_fn(|cx| from.poll_read_ready(cx)).await?;
+            match from.try_io(Interest::READABLE, || {
+                splice(from.as_raw_fd(), pipe.write.as_raw_fd(), chunk)
+            }) {
+                Ok(moved) => break moved,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            }
+        };
+        if moved == 0 {
+            shutdown_write(to.as_raw_fd());
+            return Ok(());
+        }
+        flow.queued(moved);
+        activity.touch(direction);
+
+        let mut pending = moved;
+        let mut waiting_since = Instant::now();
+        while pending > 0 {
+            poll_fn(|cx| to.poll_write_ready(cx)).await?;
+            match to.try_io(Interest::WRITABLE, || {
+                splice(pipe.read.as_raw_fd(), to.as_raw_fd(), pending)
+            }) {
+                Ok(written) => {
+                    pending -= written;
+                    flow.written(written, waiting_since.elapsed());
+                    activity.touch(direction);
+                    waiting_since = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Best effort, as the peer may already be gone entirely.
+fn shutdown_write(fd: i32) {
+    let _ = unsafe { libc::shutdown(fd, libc::SHUT_WR) };
+}
+
+#[cfg(target_os = "linux")]
+fn splice(from: i32, to: i32, len: usize) -> io::Result<usize> {
+    let rc = unsafe {
+        libc::splice(
+            from,
+            std::ptr::null_mut(),
+            to,
+            std::ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+    if rc < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(rc as usize)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn splice(_from: i32, _to: i32, _len: usize) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "splice is only supported on linux",
+    ))
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    async fn tcp_pair() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        (client.expect("connect"), accepted.expect("accept").0)
+    }
+
+    #[tokio::test]
+    async fn splice_moves_both_directions_across_half_close() {
+        let (mut client, proxy_in) = tcp_pair().await;
+        let (proxy_out, mut server) = tcp_pair().await;
+
+        let payload = vec![0xA5_u8; 3 * (1 << 20) + 17];
+        let opts = PumpOptions {
+            idle_timeout: Some(Duration::from_secs(5)),
+            ..PumpOptions::default()
+        };
+        let task = tokio::spawn(async move {
+            pump(
+                &proxy_in,
+                &proxy_out,
+                pipes(&opts).expect("pipes"),
+                opts,
+                &Traffic::default(),
+            )
+            .await
+        });
+
+        // The collector only answers once the router has half-closed.
+        let expected = payload.clone();
+        let collector = tokio::spawn(async move {
+            let mut got = Vec::new();
+            server.read_to_end(&mut got).await.expect("read payload");
+            assert_eq!(got, expected);
+            server.write_all(b"ack").await.expect("write ack");
+        });
+
+        client.write_all(&payload).await.expect("write payload");
+        client.shutdown().await.expect("half-close");
+        collector.await.expect("collector");
+
+        let mut reply = Vec::new();
+        client.read_to_end(&mut reply).await.expect("read reply");
+        assert_eq!(reply, b"ack");
+
+        let stats = task.await.expect("pump task");
+        assert_eq!(stats.bytes_up, payload.len() as u64);
+        assert!(stats.high_water_up <= opts.buffer_size as u64);
+        assert_eq!(stats.bytes_down, 3);
+        assert_eq!(stats.reason, CloseReason::Completed);
+        assert_eq!(stats.first_closed, Some(crate::forward::Side::Source));
+    }
+
+    #[tokio::test]
+    async fn splice_stops_on_idle_timeout() {
+        let (_client, proxy_in) = tcp_pair().await;
+        let (proxy_out, _server) = tcp_pair().await;
+
+        let opts = PumpOptions {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..PumpOptions::default()
+        };
+        let stats = pump(
+            &proxy_in,
+            &proxy_out,
+            pipes(&opts).expect("pipes"),
+            opts,
+            &Traffic::default(),
+        )
+        .await;
+        assert_eq!(stats.reason, CloseReason::IdleTimeout);
+        assert_eq!(stats.first_closed, None);
+    }
+}
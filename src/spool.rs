@@ -0,0 +1,772 @@
+//! Terminator sessions that survive `forward_plain` restarts.
+//!
+//! With `[terminator.spool]` set, router -> collector traffic is read as BMP
+//! messages (the RFC 7854 common header) rather than raw bytes. While the
+//! collector is reachable each message is written straight through. When a
+//! connect or write fails the AO session stays open: messages are appended
+//! to `<dir>/<conn_id>.spool` and the collector is reconnected with
+//! exponential backoff. A restarted collector has lost the session's state,
+//! so every reconnect first replays the Initiation message and the Peer Up
+//! of each peer still up, then the spool in order.
+//!
+//! Spool file I/O runs on the blocking pool, so a slow disk holds back only
+//! the session it belongs to.
+//!
+//! Messages still in socket buffers when the collector went away are lost;
+//! a write that failed part way is sent again in full. Collectors do not
+//! send BMP, so anything read from `forward_plain` is discarded.
+
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::{debug, info, warn};
+
+use crate::config::{SpoolConfig, SpoolOverflow};
+use crate::forward::{
+    Activity, CloseReason, Direction, Flow, PumpOptions, PumpStats, Side, Traffic,
+};
+
+/// Version, length and type.
+const HEADER_LEN: usize = 6;
+const BMP_VERSION: u8 = 3;
+/// Largest message accepted from the router. BGP messages are at most 64 KiB
+/// (RFC 8654), which leaves ample room for BMP's own headers and TLVs.
+pub const MAX_MESSAGE_LEN: usize = 1024 * 1024;
+
+const MSG_PEER_DOWN: u8 = 2;
+const MSG_PEER_UP: u8 = 3;
+const MSG_INITIATION: u8 = 4;
+/// Type, flags, distinguisher, address, AS, BGP ID and timestamp.
+const PER_PEER_HEADER_LEN: usize = 42;
+
+/// How long one connect attempt to `forward_plain` may take.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const SPOOL_EXTENSION: &str = "spool";
+
+/// Creates `dir` and removes spool files left by a previous run; their
+/// sessions ended with it.
+pub fn prepare_dir(dir: &Path) -> io::Result<()> {
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)?;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SPOOL_EXTENSION) {
+            warn!(path = %path.display(), "removing spool file left by a previous run");
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Forwards router -> collector until the router closes, either side fails
+/// for good or a timer in `opts` expires. `connect` opens a ready-to-use
+/// `forward_plain` connection and is called again after every failure.
+///
+/// `opts.zero_copy` does not apply, as every message is framed.
+pub async fn pump<W, P, C, F>(
+    wire: W,
+    connect: C,
+    cfg: &SpoolConfig,
+    conn_id: u64,
+    opts: PumpOptions,
+    traffic: &Traffic,
+) -> PumpStats
+where
+    W: AsyncRead + AsyncWrite,
+    P: AsyncRead + AsyncWrite,
+    C: FnMut() -> F,
+    F: Future<Output = io::Result<P>>,
+{
+    let (mut wire_read, mut wire_write) = tokio::io::split(wire);
+    let activity = Activity::new();
+    // Nothing is forwarded collector -> router, so its idle timer never runs.
+    activity.finish(Direction::Down);
+
+    let budget = Budget::new(opts.max_buffered_bytes.max(opts.buffer_size));
+    let (queue, pending) = mpsc::unbounded_channel::<Vec<u8>>();
+
+    let (reason, first_closed, error) = {
+        let read_side = async {
+            // Dropping the sender at EOF is what tells delivery to finish.
+            let queue = queue;
+            while let Some(message) = read_message(&mut wire_read).await? {
+                budget.reserve(message.len()).await;
+                traffic.up.queued(message.len());
+                activity.touch(Direction::Up);
+                if queue.send(message).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        };
+        let read_side = async { read_side.await.map_err(|err| (Side::Source, err)) };
+        let deliver_side = async {
+            deliver(
+                pending,
+                connect,
+                cfg,
+                conn_id,
+                &budget,
+                &traffic.up,
+                &activity,
+            )
+            .await
+            .map_err(|err| (Side::Destination, err))
+        };
+
+        tokio::select! {
+            result = async { tokio::try_join!(read_side, deliver_side) } => match result {
+                Ok(_) => (CloseReason::Completed, Some(Side::Source), None),
+                Err((side, err)) => (CloseReason::Error, Some(side), Some(err)),
+            },
+            reason = activity.expired(&opts) => (reason, None, None),
+        }
+    };
+
+    let _ = wire_write.shutdown().await;
+    PumpStats::collect(traffic, &activity, reason, first_closed, error, None)
+}
+
+/// Moves queued messages to `forward_plain`, through the spool whenever it
+/// is down or still replaying.
+async fn deliver<P, C, F>(
+    mut queue: mpsc::UnboundedReceiver<Vec<u8>>,
+    connect: C,
+    cfg: &SpoolConfig,
+    conn_id: u64,
+    budget: &Budget,
+    flow: &Flow,
+    activity: &Activity,
+) -> io::Result<()>
+where
+    P: AsyncRead + AsyncWrite,
+    C: FnMut() -> F,
+    F: Future<Output = io::Result<P>>,
+{
+    let mut spool = Spool::new(cfg, conn_id);
+    let mut link = Link::new(connect, cfg);
+    let mut plain: Option<(ReadHalf<P>, WriteHalf<P>)> = None;
+    let mut preamble = Preamble::default();
+    // A message that did not fit a full spool, with reads from the router
+    // paused until it does.
+    let mut held: Option<Vec<u8>> = None;
+    let mut router_open = true;
+    let mut discard = [0_u8; 512];
+
+    loop {
+        if let Some((_, writer)) = &mut plain {
+            if let Some(message) = spool.front().await? {
+                match write_message(writer, &message, flow, activity).await {
+                    Ok(()) => {
+                        preamble.delivered(&message);
+                        spool.pop(message.len()).await?;
+                        if let Some(message) = held.take() {
+                            held = spool.push(message).await?;
+                        }
+                    }
+                    Err(err) => {
+                        plain = None;
+                        link.lost(err, spool.pending());
+                    }
+                }
+                continue;
+            }
+        }
+
+        if !router_open {
+            match &mut plain {
+                Some((_, writer)) => {
+                    // Best effort, as the collector may already be gone.
+                    let _ = writer.shutdown().await;
+                }
+                None if spool.pending() > 0 => warn!(
+                    spooled_bytes = spool.pending(),
+                    "router closed while forward_plain is down; dropping spooled messages"
+                ),
+                None => {}
+            }
+            spool.remove().await;
+            return Ok(());
+        }
+
+        tokio::select! {
+            message = queue.recv(), if held.is_none() => {
+                let Some(message) = message else {
+                    router_open = false;
+                    continue;
+                };
+                budget.release(message.len());
+                if let (Some((_, writer)), 0) = (&mut plain, spool.pending()) {
+                    match write_message(writer, &message, flow, activity).await {
+                        Ok(()) => {
+                            preamble.delivered(&message);
+                            continue;
+                        }
+                        Err(err) => {
+                            plain = None;
+                            link.lost(err, 0);
+                        }
+                    }
+                }
+                held = spool.push(message).await?;
+                if held.is_some() {
+                    warn!(
+                        spooled_bytes = spool.pending(),
+                        "spool full; pausing reads from the router"
+                    );
+                }
+            }
+            connected = link.attempted() => match connected {
+                Ok(stream) => {
+                    let (reader, mut writer) = tokio::io::split(stream);
+                    match replay(&mut writer, &preamble).await {
+                        Ok(()) => {
+                            link.connected(spool.pending());
+                            plain = Some((reader, writer));
+                        }
+                        Err(err) => link.lost(err, spool.pending()),
+                    }
+                }
+                Err(err) => link.lost(err, spool.pending()),
+            },
+            read = async { plain.as_mut().expect("branch requires a connection").0.read(&mut discard).await }, if plain.is_some() => {
+                let err = match read {
+                    Ok(0) => io::Error::new(io::ErrorKind::UnexpectedEof, "forward_plain closed the connection"),
+                    Ok(_) => continue,
+                    Err(err) => err,
+                };
+                plain = None;
+                link.lost(err, spool.pending());
+            }
+        }
+    }
+}
+
+async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &[u8],
+    flow: &Flow,
+    activity: &Activity,
+) -> io::Result<()> {
+    let started = Instant::now();
+    writer.write_all(message).await?;
+    flow.written(message.len(), started.elapsed());
+    activity.touch(Direction::Up);
+    Ok(())
+}
+
+/// Sends a fresh collector what it needs before the spool makes sense.
+async fn replay<W: AsyncWrite + Unpin>(writer: &mut W, preamble: &Preamble) -> io::Result<()> {
+    for message in preamble.messages() {
+        writer.write_all(message).await?;
+    }
+    Ok(())
+}
+
+/// One BMP message, or `None` at EOF between messages.
+async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0_u8; HEADER_LEN];
+    let first = reader.read(&mut header).await?;
+    if first == 0 {
+        return Ok(None);
+    }
+    reader.read_exact(&mut header[first..]).await?;
+
+    let mut message = vec![0_u8; message_len(&header)?];
+    message[..HEADER_LEN].copy_from_slice(&header);
+    reader.read_exact(&mut message[HEADER_LEN..]).await?;
+    Ok(Some(message))
+}
+
+fn message_len(header: &[u8; HEADER_LEN]) -> io::Result<usize> {
+    if header[0] != BMP_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported bmp version {}", header[0]),
+        ));
+    }
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if !(HEADER_LEN..=MAX_MESSAGE_LEN).contains(&len) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("bmp message length {len} out of range"),
+        ));
+    }
+    Ok(len)
+}
+
+/// Peer type, distinguisher and address; flags and timestamps vary between
+/// a peer's messages.
+type PeerKey = [u8; 25];
+
+fn peer_key(message: &[u8]) -> Option<PeerKey> {
+    let header = message.get(HEADER_LEN..HEADER_LEN + PER_PEER_HEADER_LEN)?;
+    let mut key = [0_u8; 25];
+    key[0] = header[0];
+    key[1..].copy_from_slice(&header[2..26]);
+    Some(key)
+}
+
+/// Session state the collector has seen, rebuilt from delivered messages.
+#[derive(Debug, Default)]
+struct Preamble {
+    initiation: Option<Vec<u8>>,
+    peers_up: Vec<(PeerKey, Vec<u8>)>,
+}
+
+impl Preamble {
+    fn delivered(&mut self, message: &[u8]) {
+        match message[5] {
+            MSG_INITIATION => self.initiation = Some(message.to_vec()),
+            kind @ (MSG_PEER_UP | MSG_PEER_DOWN) => {
+                let Some(key) = peer_key(message) else {
+                    return;
+                };
+                self.peers_up.retain(|(peer, _)| *peer != key);
+                if kind == MSG_PEER_UP {
+                    self.peers_up.push((key, message.to_vec()));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn messages(&self) -> impl Iterator<Item = &[u8]> {
+        self.initiation
+            .iter()
+            .chain(self.peers_up.iter().map(|(_, message)| message))
+            .map(Vec::as_slice)
+    }
+}
+
+/// Bytes of router messages held in memory between read and delivery.
+struct Budget {
+    permits: Semaphore,
+    capacity: usize,
+}
+
+impl Budget {
+    fn new(capacity: usize) -> Self {
+        Self {
+            permits: Semaphore::new(capacity),
+            capacity,
+        }
+    }
+
+    /// A message larger than the whole budget waits for all of it.
+    async fn reserve(&self, len: usize) {
+        self.permits
+            .acquire_many(len.min(self.capacity) as u32)
+            .await
+            .expect("budget semaphore is never closed")
+            .forget();
+    }
+
+    fn release(&self, len: usize) {
+        self.permits.add_permits(len.min(self.capacity));
+    }
+}
+
+/// Runs spool file I/O on the blocking pool.
+async fn blocking<T, F>(io: F) -> io::Result<T>
+where
+    T: Send + 'static,
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+{
+    tokio::task::spawn_blocking(io)
+        .await
+        .map_err(io::Error::other)?
+}
+
+/// A session's undelivered messages, created on first use. Messages are
+/// appended at `tail` and replayed from `head`; the file is truncated
+/// whenever it drains and removed with the session.
+struct Spool<'a> {
+    cfg: &'a SpoolConfig,
+    path: PathBuf,
+    file: Option<Arc<File>>,
+    /// Set when the session ends, so a create still in flight removes the
+    /// file it made.
+    closed: Arc<AtomicBool>,
+    head: u64,
+    tail: u64,
+}
+
+impl Drop for Spool<'_> {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if self.file.take().is_none() {
+            return;
+        }
+        let path = self.path.clone();
+        let remove = move || {
+            let _ = fs::remove_file(path);
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(remove)),
+            Err(_) => remove(),
+        }
+    }
+}
+
+impl<'a> Spool<'a> {
+    fn new(cfg: &'a SpoolConfig, conn_id: u64) -> Self {
+        Self {
+            cfg,
+            path: cfg.dir.join(format!("{conn_id}.{SPOOL_EXTENSION}")),
+            file: None,
+            closed: Arc::new(AtomicBool::new(false)),
+            head: 0,
+            tail: 0,
+        }
+    }
+
+    fn pending(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    /// Removes the file at the end of a session.
+    async fn remove(mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        if self.file.take().is_some() {
+            let path = self.path.clone();
+            let _ = blocking(move || fs::remove_file(path)).await;
+        }
+    }
+
+    /// Appends `message`, or hands it back when the spool is full and
+    /// overflow is `block`.
+    async fn push(&mut self, message: Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+        if self.pending() + message.len() as u64 > self.cfg.max_bytes {
+            return match self.cfg.overflow {
+                SpoolOverflow::Close => Err(io::Error::other(format!(
+                    "spool full at {} bytes while forward_plain is down",
+                    self.pending()
+                ))),
+                SpoolOverflow::Block => Ok(Some(message)),
+            };
+        }
+
+        let file = match &self.file {
+            Some(file) => Arc::clone(file),
+            None => {
+                let (path, closed) = (self.path.clone(), Arc::clone(&self.closed));
+                let file = Arc::new(blocking(move || create(&path, &closed)).await?);
+                Arc::clone(self.file.insert(file))
+            }
+        };
+        let (tail, len) = (self.tail, message.len() as u64);
+        blocking(move || file.write_all_at(&message, tail)).await?;
+        self.tail += len;
+        Ok(None)
+    }
+
+    async fn front(&self) -> io::Result<Option<Vec<u8>>> {
+        let Some(file) = self.file.as_ref().filter(|_| self.pending() > 0) else {
+            return Ok(None);
+        };
+        let (file, head) = (Arc::clone(file), self.head);
+        blocking(move || {
+            let mut header = [0_u8; HEADER_LEN];
+            file.read_exact_at(&mut header, head)?;
+            let mut message = vec![0_u8; message_len(&header)?];
+            file.read_exact_at(&mut message, head)?;
+            Ok(Some(message))
+        })
+        .await
+    }
+
+    async fn pop(&mut self, len: usize) -> io::Result<()> {
+        self.head += len as u64;
+        if self.head == self.tail {
+            if let Some(file) = &self.file {
+                let file = Arc::clone(file);
+                blocking(move || file.set_len(0)).await?;
+            }
+            self.head = 0;
+            self.tail = 0;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the spool file at `path`, removing it again if the session ended
+/// while this was queued.
+fn create(path: &Path, closed: &AtomicBool) -> io::Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    if closed.load(Ordering::SeqCst) {
+        let _ = fs::remove_file(path);
+        return Err(io::Error::other("session closed while creating its spool"));
+    }
+    Ok(file)
+}
+
+/// Reconnects to `forward_plain` with exponential backoff.
+struct Link<C, F> {
+    connect: C,
+    initial: Duration,
+    max: Duration,
+    delay: Duration,
+    /// The next attempt and when it may start. Kept across loop iterations
+    /// so traffic from the router does not restart it.
+    attempt: Option<(tokio::time::Instant, Pin<Box<F>>)>,
+    down_since: Option<Instant>,
+}
+
+impl<P, C, F> Link<C, F>
+where
+    C: FnMut() -> F,
+    F: Future<Output = io::Result<P>>,
+{
+    fn new(mut connect: C, cfg: &SpoolConfig) -> Self {
+        let attempt = Some((tokio::time::Instant::now(), Box::pin(connect())));
+        Self {
+            connect,
+            initial: cfg.reconnect_initial(),
+            max: cfg.reconnect_max(),
+            delay: cfg.reconnect_initial(),
+            attempt,
+            down_since: None,
+        }
+    }
+
+    /// Resolves with the outcome of the pending attempt; never when the
+    /// connection is up.
+    async fn attempted(&mut self) -> io::Result<P> {
+        let Some((start, attempt)) = &mut self.attempt else {
+            return std::future::pending().await;
+        };
+        tokio::time::sleep_until(*start).await;
+        let result = match tokio::time::timeout_at(*start + CONNECT_TIMEOUT, attempt.as_mut()).await
+        {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "connect to forward_plain timed out",
+            )),
+        };
+        self.attempt = None;
+        result
+    }
+
+    fn lost(&mut self, err: io::Error, spooled_bytes: u64) {
+        let retry_in = self.delay;
+        self.delay = (self.delay * 2).min(self.max);
+        self.attempt = Some((
+            tokio::time::Instant::now() + retry_in,
+            Box::pin((self.connect)()),
+        ));
+
+        let retry_ms = retry_in.as_millis() as u64;
+        if self.down_since.is_none() {
+            self.down_since = Some(Instant::now());
+            warn!(error = %err, retry_ms, "forward_plain unavailable; spooling until it is back");
+        } else {
+            debug!(error = %err, retry_ms, spooled_bytes, "forward_plain reconnect failed");
+        }
+    }
+
+    fn connected(&mut self, spooled_bytes: u64) {
+        self.delay = self.initial;
+        if let Some(since) = self.down_since.take() {
+            info!(
+                outage_ms = since.elapsed().as_millis() as u64,
+                spooled_bytes, "forward_plain is back; replaying spooled messages"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::duplex;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    fn message(kind: u8, peer: u8, body: &[u8]) -> Vec<u8> {
+        let mut per_peer = [0_u8; PER_PEER_HEADER_LEN];
+        per_peer[25] = peer;
+        let len = HEADER_LEN + PER_PEER_HEADER_LEN + body.len();
+        let mut message = vec![BMP_VERSION];
+        message.extend_from_slice(&(len as u32).to_be_bytes());
+        message.push(kind);
+        message.extend_from_slice(&per_peer);
+        message.extend_from_slice(body);
+        message
+    }
+
+    fn config(dir: &Path, max_bytes: u64, overflow: SpoolOverflow) -> SpoolConfig {
+        SpoolConfig {
+            dir: dir.to_path_buf(),
+            max_bytes,
+            overflow,
+            reconnect_initial_ms: 20,
+            reconnect_max_secs: 1,
+        }
+    }
+
+    async fn read_messages(collector: &mut TcpStream, count: usize) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        while messages.len() < count {
+            let message = tokio::time::timeout(Duration::from_secs(5), read_message(collector))
+                .await
+                .expect("collector read timed out")
+                .expect("read")
+                .expect("message before eof");
+            messages.push(message);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn replays_preamble_and_spool_to_a_restarted_collector() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let cfg = config(dir.path(), MAX_MESSAGE_LEN as u64, SpoolOverflow::Close);
+        let collector = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = collector.local_addr().expect("addr");
+
+        let (mut router, wire) = duplex(64 * 1024);
+        let traffic = Traffic::default();
+        let pump = pump(
+            wire,
+            || TcpStream::connect(addr),
+            &cfg,
+            7,
+            PumpOptions::default(),
+            &traffic,
+        );
+
+        let initiation = message(MSG_INITIATION, 0, b"router-1");
+        let peer_a = message(MSG_PEER_UP, 1, b"a");
+        let peer_b = message(MSG_PEER_UP, 2, b"b");
+        let peer_b_down = message(MSG_PEER_DOWN, 2, b"");
+        let route_1 = message(0, 1, b"route-1");
+        let route_2 = message(0, 1, b"route-2");
+
+        let session = async {
+            let (mut first, _) = collector.accept().await.expect("accept");
+            for message in [&initiation, &peer_a, &peer_b, &peer_b_down] {
+                router.write_all(message).await.expect("router write");
+            }
+            assert_eq!(
+                read_messages(&mut first, 4).await,
+                vec![
+                    initiation.clone(),
+                    peer_a.clone(),
+                    peer_b.clone(),
+                    peer_b_down.clone()
+                ]
+            );
+
+            // The collector restarts; what the router sends meanwhile is spooled.
+            drop(first);
+            drop(collector);
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            router.write_all(&route_1).await.expect("router write");
+            router.write_all(&route_2).await.expect("router write");
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            assert!(dir.path().join("7.spool").exists());
+
+            let collector = TcpListener::bind(addr).await.expect("rebind");
+            let (mut second, _) = collector.accept().await.expect("accept");
+            assert_eq!(
+                read_messages(&mut second, 4).await,
+                vec![
+                    initiation.clone(),
+                    peer_a.clone(),
+                    route_1.clone(),
+                    route_2.clone()
+                ]
+            );
+
+            router.shutdown().await.expect("router close");
+            let mut rest = Vec::new();
+            second.read_to_end(&mut rest).await.expect("collector eof");
+            assert!(rest.is_empty());
+        };
+
+        let (stats, ()) = tokio::join!(pump, session);
+        assert_eq!(stats.reason, CloseReason::Completed, "{stats:?}");
+        assert!(!dir.path().join("7.spool").exists());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn overflow_closes_or_holds_back_the_router() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let big = message(0, 1, &vec![0_u8; 600 * 1024]);
+
+        let cfg = config(dir.path(), MAX_MESSAGE_LEN as u64, SpoolOverflow::Close);
+        let (mut router, wire) = duplex(64 * 1024);
+        let refused =
+            || async { Err::<TcpStream, _>(io::Error::from(io::ErrorKind::ConnectionRefused)) };
+        let traffic = Traffic::default();
+        let session = async {
+            for _ in 0..2 {
+                if router.write_all(&big).await.is_err() {
+                    break;
+                }
+            }
+        };
+        let (stats, ()) = tokio::join!(
+            pump(wire, refused, &cfg, 1, PumpOptions::default(), &traffic),
+            session
+        );
+        assert_eq!(stats.reason, CloseReason::Error);
+        assert_eq!(stats.first_closed, Some(Side::Destination));
+        assert!(stats
+            .up_error
+            .is_some_and(|err| err.to_string().contains("spool full")));
+
+        // The clock is paused and only moves once every task is waiting, so
+        // the write times out only when the router is really held back.
+        let cfg = config(dir.path(), MAX_MESSAGE_LEN as u64, SpoolOverflow::Block);
+        let (mut router, wire) = duplex(64 * 1024);
+        let session = async {
+            let mut sent = 0;
+            while sent < 8 {
+                let write = tokio::time::timeout(Duration::from_secs(30), router.write_all(&big));
+                if write.await.is_err() {
+                    break;
+                }
+                sent += 1;
+            }
+            let spooled = fs::metadata(dir.path().join("2.spool")).expect("spool");
+            assert_eq!(spooled.len(), big.len() as u64);
+            sent
+        };
+        let sent = tokio::select! {
+            stats = pump(wire, refused, &cfg, 2, PumpOptions::default(), &traffic) => {
+                panic!("pump ended while the router was held back: {stats:?}")
+            }
+            sent = session => sent,
+        };
+        assert!(sent < 8, "router was never held back");
+    }
+
+    #[test]
+    fn rejects_anything_but_bmp_v3_frames() {
+        assert!(message_len(&[3, 0, 0, 0, 6, 4]).is_ok());
+        assert!(message_len(&[4, 0, 0, 0, 6, 4]).is_err());
+        assert!(message_len(&[3, 0, 0, 0, 5, 4]).is_err());
+        assert!(message_len(&[3, 0, 0x20, 0, 0, 0]).is_err());
+    }
+}
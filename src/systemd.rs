@@ -0,0 +1,306 @@
+//! systemd service integration without linking libsystemd.
+//!
+//! With `Type=notify` the proxy reports `READY=1` once its listener has AO
+//! keys installed, then keeps `STATUS=` current and sends `WATCHDOG=1` from
+//! the runtime when `WatchdogSec=` is set. With a `.socket` unit the listener
+//! is inherited through `LISTEN_FDS` instead of bound. Everything here is a
+//! no-op when the process was not started by systemd.
+
+use std::env;
+use std::ffi::OsString;
+use std::io;
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::error::{ProxyError, Result};
+use crate::metrics::Metrics;
+
+const NOTIFY_SOCKET_ENV: &str = "NOTIFY_SOCKET";
+const WATCHDOG_USEC_ENV: &str = "WATCHDOG_USEC";
+const WATCHDOG_PID_ENV: &str = "WATCHDOG_PID";
+const LISTEN_PID_ENV: &str = "LISTEN_PID";
+const LISTEN_FDS_ENV: &str = "LISTEN_FDS";
+const LISTEN_FDNAMES_ENV: &str = "LISTEN_FDNAMES";
+
+/// First inherited descriptor, `SD_LISTEN_FDS_START`.
+const LISTEN_FDS_START: i32 = 3;
+
+/// How often `STATUS=` is refreshed when no watchdog asks for more.
+const STATUS_INTERVAL: Duration = Duration::from_secs(10);
+
+static LISTEN_FD_TAKEN: AtomicBool = AtomicBool::new(false);
+static ENVIRONMENT: OnceLock<Environment> = OnceLock::new();
+
+/// What systemd passed through the environment.
+#[derive(Debug)]
+struct Environment {
+    notify_socket: Option<OsString>,
+    watchdog_usec: Option<String>,
+    watchdog_pid: Option<String>,
+    listen_pid: Option<String>,
+    listen_fds: Option<String>,
+}
+
+impl Environment {
+    fn take() -> Self {
+        let taken = Environment {
+            notify_socket: env::var_os(NOTIFY_SOCKET_ENV),
+            watchdog_usec: env::var(WATCHDOG_USEC_ENV).ok(),
+            watchdog_pid: env::var(WATCHDOG_PID_ENV).ok(),
+            listen_pid: env::var(LISTEN_PID_ENV).ok(),
+            listen_fds: env::var(LISTEN_FDS_ENV).ok(),
+        };
+        for name in [
+            NOTIFY_SOCKET_ENV,
+            WATCHDOG_USEC_ENV,
+            WATCHDOG_PID_ENV,
+            LISTEN_PID_ENV,
+            LISTEN_FDS_ENV,
+            LISTEN_FDNAMES_ENV,
+        ] {
+            env::remove_var(name);
+        }
+        taken
+    }
+}
+
+/// Moves systemd's variables out of the environment, as `sd_listen_fds(3)`
+/// does with `unset_environment`, so `exec:` key helpers and other children
+/// cannot notify or take sockets on behalf of this unit. Call it before any
+/// other thread starts; everything else here reads the saved copy.
+pub fn take_environment() {
+    environment();
+}
+
+fn environment() -> &'static Environment {
+    ENVIRONMENT.get_or_init(Environment::take)
+}
+
+/// Sends `state` to the service manager. Returns `false` when not running
+/// under systemd.
+pub fn notify(state: &str) -> bool {
+    let Some(socket) = &environment().notify_socket else {
+        return false;
+    };
+    match send(&socket.to_string_lossy(), state) {
+        Ok(()) => true,
+        Err(err) => {
+            warn!(socket = ?socket, error = %err, "failed to notify systemd");
+            false
+        }
+    }
+}
+
+/// `@`-prefixed paths are in the abstract namespace.
+fn send(socket: &str, state: &str) -> io::Result<()> {
+    let sock = UnixDatagram::unbound()?;
+    match socket.strip_prefix('@') {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            sock.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "abstract notify sockets are only supported on linux",
+            ))
+        }
+        None => {
+            sock.send_to(state.as_bytes(), socket)?;
+        }
+    }
+    Ok(())
+}
+
+/// Tells systemd shutdown has begun.
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Keeps `STATUS=` and the watchdog fed until dropped.
+#[derive(Debug)]
+pub struct Heartbeat {
+    task: JoinHandle<()>,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Reports the service ready and starts the heartbeat. `None` when not
+/// running under systemd.
+pub fn ready(mode: &'static str, metrics: &Arc<Metrics>) -> Option<Heartbeat> {
+    let ready = format!("READY=1\nSTATUS={}", status(mode, metrics));
+    if !notify(&ready) {
+        return None;
+    }
+
+    let environment = environment();
+    let watchdog = watchdog_interval(
+        environment.watchdog_usec.as_deref(),
+        environment.watchdog_pid.as_deref(),
+        std::process::id(),
+    );
+    info!(
+        mode,
+        watchdog_ms = ?watchdog.map(|d| d.as_millis() as u64),
+        "notified systemd of readiness"
+    );
+
+    let metrics = Arc::clone(metrics);
+    let period = watchdog.map_or(STATUS_INTERVAL, |w| w.min(STATUS_INTERVAL));
+    let task = tokio::spawn(async move {
+        let mut tick = tokio::time::interval(period);
+        tick.tick().await;
+        loop {
+            tick.tick().await;
+            let mut state = format!("STATUS={}", status(mode, &metrics));
+            if watchdog.is_some() {
+                state.push_str("\nWATCHDOG=1");
+            }
+            notify(&state);
+        }
+    });
+    Some(Heartbeat { task })
+}
+
+fn status(mode: &str, metrics: &Metrics) -> String {
+    format!(
+        "{mode}: {} open sessions, {} closed, {} rejected",
+        metrics.open_connections(),
+        metrics.closed_connections(),
+        metrics.rejected_connections(),
+    )
+}
+
+/// Half the watchdog timeout, as sd_watchdog_enabled(3) recommends, when the
+/// watchdog is meant for this process.
+fn watchdog_interval(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(own_pid) {
+            return None;
+        }
+    }
+    let usec: u64 = usec?.parse().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec / 2))
+}
+
+/// Number of sockets passed to this process, or `None` when they were meant
+/// for another one.
+fn listen_fds(pid: Option<&str>, fds: Option<&str>, own_pid: u32) -> Result<Option<i32>> {
+    let (Some(pid), Some(fds)) = (pid, fds) else {
+        return Ok(None);
+    };
+    if pid.parse::<u32>().ok() != Some(own_pid) {
+        return Ok(None);
+    }
+    match fds.parse::<i32>() {
+        Ok(0) => Ok(None),
+        Ok(n) if n > 0 => Ok(Some(n)),
+        _ => Err(ProxyError::Config(format!(
+            "invalid {LISTEN_FDS_ENV}='{fds}' from systemd"
+        ))),
+    }
+}
+
+/// The listening socket passed by systemd socket activation, if any. Only
+/// the first call can return it; the proxy has one listener per mode, so a
+/// unit passing more than one socket is rejected.
+pub fn take_listen_fd() -> Result<Option<OwnedFd>> {
+    let environment = environment();
+    let count = listen_fds(
+        environment.listen_pid.as_deref(),
+        environment.listen_fds.as_deref(),
+        std::process::id(),
+    )?;
+    let Some(count) = count else {
+        return Ok(None);
+    };
+    if count > 1 {
+        return Err(ProxyError::Config(format!(
+            "systemd passed {count} sockets; the socket unit must have exactly one listen directive"
+        )));
+    }
+    if LISTEN_FD_TAKEN.swap(true, Ordering::SeqCst) {
+        return Ok(None);
+    }
+
+    // SAFETY: systemd hands LISTEN_FDS descriptors from SD_LISTEN_FDS_START
+    // to this pid, and the flag above makes sure it is only wrapped once.
+    let fd = unsafe { <OwnedFd as std::os::fd::FromRawFd>::from_raw_fd(LISTEN_FDS_START) };
+    set_cloexec(&fd)?;
+    debug!(
+        fd = LISTEN_FDS_START,
+        "inherited listening socket from systemd"
+    );
+    Ok(Some(fd))
+}
+
+fn set_cloexec(fd: &OwnedFd) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: plain fcntl calls on a descriptor we own.
+    let flags = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GETFD) };
+    if flags < 0
+        || unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_SETFD, flags | libc::FD_CLOEXEC) } < 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sends_state_to_the_notify_socket() {
+        let dir = tempfile::tempdir().expect("tempdir");
+        let path = dir.path().join("notify");
+        let receiver = UnixDatagram::bind(&path).expect("bind");
+
+        send(path.to_str().expect("utf8 path"), "READY=1\nSTATUS=ok").expect("send");
+        let mut buf = [0_u8; 64];
+        let n = receiver.recv(&mut buf).expect("recv");
+        assert_eq!(&buf[..n], b"READY=1\nSTATUS=ok");
+
+        let metrics = Metrics::default();
+        metrics.conn_opened();
+        assert_eq!(
+            status("terminator", &metrics),
+            "terminator: 1 open sessions, 0 closed, 0 rejected"
+        );
+    }
+
+    #[test]
+    fn watchdog_and_listen_fds_only_apply_to_this_process() {
+        assert_eq!(
+            watchdog_interval(Some("20000000"), None, 7),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            watchdog_interval(Some("20000000"), Some("7"), 7),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(watchdog_interval(Some("20000000"), Some("8"), 7), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 7), None);
+        assert_eq!(watchdog_interval(None, None, 7), None);
+
+        assert_eq!(listen_fds(Some("7"), Some("1"), 7).expect("fds"), Some(1));
+        assert_eq!(listen_fds(Some("8"), Some("1"), 7).expect("fds"), None);
+        assert_eq!(listen_fds(Some("7"), Some("0"), 7).expect("fds"), None);
+        assert_eq!(listen_fds(None, None, 7).expect("fds"), None);
+        assert!(listen_fds(Some("7"), Some("x"), 7).is_err());
+    }
+}
@@ -1,21 +1,50 @@
 use std::io;
 use std::net::{IpAddr, SocketAddr};
+use std::os::fd::{AsRawFd, BorrowedFd};
 
-use crate::config::AoPolicyConfig;
+use crate::tcpao::policy::{CompiledPolicy, PolicySet};
 
 #[cfg(target_os = "linux")]
 use std::{mem, os::fd::RawFd, ptr};
 
+#[cfg(target_os = "linux")]
+use crate::config::Enforcement;
 #[cfg(target_os = "linux")]
 use linux_raw_sys::net;
 #[cfg(target_os = "linux")]
-use tracing::{debug, info};
+use zeroize::Zeroize;
+
+#[cfg(target_os = "linux")]
+use tracing::{debug, info, warn};
 
 #[cfg(target_os = "linux")]
 const TEST_BYPASS_ENV: &str = "TCPAO_PROXY_TEST_NO_AO";
 #[cfg(target_os = "linux")]
 const TEST_BEST_EFFORT_INBOUND_ENV: &str = "TCPAO_PROXY_TEST_ALLOW_BEST_EFFORT_INBOUND_AO";
 
+/// Which peers get their key installed on a listening socket. The kernel
+/// drops unsigned SYNs from any peer that matches a listener key, so a peer
+/// can only connect without AO to a listener that holds no key for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListenerRole {
+    /// `listen_ao`: keys for `required` and `preferred` peers; `monitor`
+    /// peers connect without AO.
+    Primary,
+    /// `fallback_listen_ao`: keys for `required` peers only, so `preferred`
+    /// peers that cannot sign can still connect.
+    Fallback,
+}
+
+#[cfg(target_os = "linux")]
+impl ListenerRole {
+    fn installs_key(self, policy: &CompiledPolicy) -> bool {
+        match self {
+            ListenerRole::Primary => policy.config.enforcement != Enforcement::Monitor,
+            ListenerRole::Fallback => policy.config.enforcement.is_required(),
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 pub fn probe_tcpao_support() -> io::Result<()> {
     if allow_test_bypass() {
@@ -57,28 +86,29 @@ pub fn probe_tcpao_support() -> io::Result<()> {
 #[cfg(target_os = "linux")]
 pub fn apply_outbound_policy(
     socket_fd: RawFd,
-    policy: &AoPolicyConfig,
+    policy: &CompiledPolicy,
     remote: SocketAddr,
 ) -> io::Result<()> {
     if allow_test_bypass() {
         info!(
             env = TEST_BYPASS_ENV,
-            policy = %policy.name,
+            policy = %policy.name(),
             peer = %remote,
             "tcp-ao test bypass enabled; skipping outbound ao setsockopt"
         );
         return Ok(());
     }
 
-    let key = policy_key_bytes(policy)?;
-    install_key(socket_fd, policy, remote, &key, true)?;
-    set_ao_required(socket_fd, true)?;
+    let key = policy.key()?;
+    install_key(socket_fd, policy, remote, true)?;
+    set_ao_required(socket_fd, policy.config.enforcement.is_required())?;
 
     info!(
-        policy = %policy.name,
+        policy = %policy.name(),
         peer = %remote,
-        keyid = policy.keyid,
-        mac_alg = %policy.mac_alg,
+        keyid = policy.config.keyid,
+        mac_alg = %policy.alg_name,
+        key_fingerprint = %key.fingerprint(),
         "applied outbound tcp-ao policy"
     );
 
@@ -88,7 +118,7 @@ pub fn apply_outbound_policy(
 #[cfg(not(target_os = "linux"))]
 pub fn apply_outbound_policy(
     _socket_fd: i32,
-    _policy: &AoPolicyConfig,
+    _policy: &CompiledPolicy,
     _remote: SocketAddr,
 ) -> io::Result<()> {
     Err(io::Error::new(
@@ -101,7 +131,8 @@ pub fn apply_outbound_policy(
 pub fn configure_listener(
     socket_fd: RawFd,
     listen_addr: SocketAddr,
-    policies: &[AoPolicyConfig],
+    policies: &PolicySet,
+    role: ListenerRole,
 ) -> io::Result<()> {
     if allow_test_bypass() {
         info!(
@@ -117,33 +148,58 @@ pub fn configure_listener(
         SocketAddr::V6(_) => libc::AF_INET6,
     };
 
+    // Listening sockets have no current key, and the kernel refuses to set
+    // one; a socket passed by systemd is already listening.
+    // SAFETY: the caller's socket stays open for the duration of this call.
+    let listening = is_listening(unsafe { BorrowedFd::borrow_raw(socket_fd) })?;
     let mut installed = 0usize;
-    for policy in policies {
-        if !policy_matches_family(policy.peer_ip, family) {
+    let mut relaxed = 0usize;
+    for policy in policies.iter() {
+        if !policy_matches_family(policy.config.peer_ip, family) {
+            continue;
+        }
+
+        if !policy.config.enforcement.is_required() {
+            relaxed += 1;
+        }
+        if !role.installs_key(policy) {
             continue;
         }
 
-        let peer = SocketAddr::new(policy.peer_ip, policy.peer_port.unwrap_or(0));
-        let key = policy_key_bytes(policy)?;
-        // At least one listener key must be active for the kernel to authenticate
-        // and send AO segments on accepted sessions.
-        let set_current = installed == 0;
-        install_key(socket_fd, policy, peer, &key, set_current)?;
+        let peer = listener_peer(policy);
+        let key = policy.key()?;
+        // Before listen, one key must be current for the kernel to
+        // authenticate and send AO segments on accepted sessions.
+        let set_current = installed == 0 && !listening;
+        install_key(socket_fd, policy, peer, set_current)?;
+        debug!(
+            policy = %policy.name(),
+            keyid = policy.config.keyid,
+            key_fingerprint = %key.fingerprint(),
+            "installed listener tcp-ao key"
+        );
         installed += 1;
     }
 
-    if installed == 0 {
+    if installed == 0 && relaxed == 0 {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             "no AO policies matched listener address family",
         ));
     }
 
-    set_ao_required(socket_fd, true)?;
+    // A listener-wide AO requirement would also reject peers whose policy is
+    // relaxed, so it is only set when every policy requires AO.
+    let ao_required = relaxed == 0;
+    if installed > 0 {
+        set_ao_required(socket_fd, ao_required)?;
+    }
 
     info!(
         listen = %listen_addr,
         installed,
+        relaxed,
+        ao_required,
         "configured tcp-ao policies on listener"
     );
 
@@ -154,7 +210,188 @@ pub fn configure_listener(
 pub fn configure_listener(
     _socket_fd: i32,
     _listen_addr: SocketAddr,
-    _policies: &[AoPolicyConfig],
+    _policies: &PolicySet,
+    _role: ListenerRole,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tcp-ao is only supported on linux",
+    ))
+}
+
+/// Checks that the keys [`configure_listener`] installs are still on the
+/// listener: `TCP_AO_INFO` must be readable whenever a policy for its address
+/// family puts a key there.
+#[cfg(target_os = "linux")]
+pub fn check_listener(
+    socket_fd: RawFd,
+    listen_addr: SocketAddr,
+    policies: &PolicySet,
+    role: ListenerRole,
+) -> io::Result<()> {
+    if allow_test_bypass() {
+        return Ok(());
+    }
+
+    let family = match listen_addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let expects_keys = policies.iter().any(|policy| {
+        policy_matches_family(policy.config.peer_ip, family)
+            && role.installs_key(policy)
+            && policy.key.is_some()
+    });
+    if !expects_keys {
+        return Ok(());
+    }
+    get_ao_info(socket_fd).map(|_| ())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn check_listener(
+    _socket_fd: i32,
+    _listen_addr: SocketAddr,
+    _policies: &PolicySet,
+    _role: ListenerRole,
+) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tcp-ao is only supported on linux",
+    ))
+}
+
+/// Brings the listener's installed keys from `previous` to `next`: changed keys
+/// are replaced, unchanged ones are left alone and policies without a key
+/// (fail-closed sources) are removed. When a step fails, the steps already
+/// taken are undone so the listener keeps exactly the `previous` keys.
+#[cfg(target_os = "linux")]
+pub fn rekey_listener(
+    socket_fd: RawFd,
+    listen_addr: SocketAddr,
+    previous: &PolicySet,
+    next: &PolicySet,
+    role: ListenerRole,
+) -> io::Result<()> {
+    if allow_test_bypass() {
+        info!(
+            env = TEST_BYPASS_ENV,
+            listen = %listen_addr,
+            "tcp-ao test bypass enabled; skipping listener re-key"
+        );
+        return Ok(());
+    }
+
+    let family = match listen_addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+
+    let mut applied: Vec<(Option<&CompiledPolicy>, Option<&CompiledPolicy>)> = Vec::new();
+    let mut rekeyed = 0usize;
+    let mut removed = 0usize;
+    for policy in next.iter() {
+        if !policy_matches_family(policy.config.peer_ip, family) || !role.installs_key(policy) {
+            continue;
+        }
+
+        let old = previous
+            .get(policy.name())
+            .filter(|old| old.key.is_some() && role.installs_key(old));
+        let new = policy.key.as_ref().map(|_| policy);
+        if let (Some(new), Some(old)) = (new, old) {
+            if std::sync::Arc::ptr_eq(
+                new.key.as_ref().expect("checked"),
+                old.key.as_ref().expect("checked"),
+            ) {
+                continue;
+            }
+        }
+        if new.is_none() && old.is_none() {
+            continue;
+        }
+
+        if let Err(err) = replace_listener_key(socket_fd, old, new) {
+            for (old, new) in applied.into_iter().rev() {
+                if let Err(undo) = replace_listener_key(socket_fd, new, old) {
+                    warn!(error = %undo, "failed to restore listener tcp-ao key");
+                }
+            }
+            return Err(io::Error::new(
+                err.kind(),
+                format!("policy '{}': {err}", policy.name()),
+            ));
+        }
+        applied.push((old, new));
+
+        if new.is_some() {
+            rekeyed += 1;
+        } else {
+            removed += 1;
+            info!(
+                policy = %policy.name(),
+                "removed tcp-ao key from listener; key source failed closed"
+            );
+        }
+    }
+
+    info!(
+        listen = %listen_addr,
+        rekeyed,
+        removed,
+        "re-installed tcp-ao keys on listener"
+    );
+
+    Ok(())
+}
+
+/// Swaps one policy's MKT on a listening socket. The kernel refuses a second
+/// MKT with the same ids and peer, so an MKT that keeps its ids is deleted
+/// before the new key goes in, and put back if that fails; otherwise the new
+/// MKT is added first. Deletes use `del_async`, the only way to remove a key
+/// a listener may still hold as current.
+#[cfg(target_os = "linux")]
+fn replace_listener_key(
+    socket_fd: RawFd,
+    old: Option<&CompiledPolicy>,
+    new: Option<&CompiledPolicy>,
+) -> io::Result<()> {
+    match (old, new) {
+        (None, None) => Ok(()),
+        (Some(old), None) => delete_listener_key(socket_fd, old),
+        (None, Some(new)) => install_key(socket_fd, new, listener_peer(new), false),
+        (Some(old), Some(new)) if !same_mkt(old, new) => {
+            install_key(socket_fd, new, listener_peer(new), false)?;
+            if let Err(err) = delete_listener_key(socket_fd, old) {
+                let _ = delete_listener_key(socket_fd, new);
+                return Err(err);
+            }
+            Ok(())
+        }
+        (Some(old), Some(new)) => {
+            delete_listener_key(socket_fd, old)?;
+            if let Err(err) = install_key(socket_fd, new, listener_peer(new), false) {
+                install_key(socket_fd, old, listener_peer(old), false)?;
+                return Err(err);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Whether two policies' listener keys occupy the same kernel MKT slot.
+#[cfg(target_os = "linux")]
+fn same_mkt(a: &CompiledPolicy, b: &CompiledPolicy) -> bool {
+    a.config.keyid == b.config.keyid && a.config.peer_ip == b.config.peer_ip
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rekey_listener(
+    _socket_fd: i32,
+    _listen_addr: SocketAddr,
+    _previous: &PolicySet,
+    _next: &PolicySet,
+    _role: ListenerRole,
 ) -> io::Result<()> {
     Err(io::Error::new(
         io::ErrorKind::Unsupported,
@@ -162,39 +399,210 @@ pub fn configure_listener(
     ))
 }
 
+/// How an accepted session is protected, read from the MKTs the kernel
+/// attached to it. The kernel only copies keys onto a socket accepted from a
+/// signed SYN; the `ao_required` flag is inherited from the listener and is
+/// off whenever the listener also serves relaxed policies, so it says
+/// nothing about the session itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InboundAo {
+    /// The SYN was signed and the kernel attached these MKTs.
+    Signed(Vec<SessionKey>),
+    /// No MKT matched the peer; the session runs without AO.
+    Unsigned,
+    /// AO state was not inspected under the debug/test bypasses.
+    Unchecked,
+}
+
 #[cfg(target_os = "linux")]
-pub fn ensure_inbound_session_has_ao(socket_fd: RawFd, peer: SocketAddr) -> io::Result<()> {
+pub fn inbound_session_ao(socket_fd: RawFd, peer: SocketAddr) -> io::Result<InboundAo> {
     if allow_test_bypass() {
         debug!(
             env = TEST_BYPASS_ENV,
             peer = %peer,
             "tcp-ao test bypass enabled; skipping inbound ao verification"
         );
-        return Ok(());
+        return Ok(InboundAo::Unchecked);
     }
 
-    let info = match get_ao_info(socket_fd) {
-        Ok(info) => info,
+    match get_session_keys(socket_fd) {
+        Ok(keys) if !keys.is_empty() => Ok(InboundAo::Signed(keys)),
+        Ok(_) => Ok(InboundAo::Unsigned),
         Err(err) if err.kind() == io::ErrorKind::NotFound => {
-            return handle_missing_inbound_ao_info(peer, err);
+            match handle_missing_inbound_ao_info(peer, err) {
+                Ok(()) => Ok(InboundAo::Unchecked),
+                Err(_) => Ok(InboundAo::Unsigned),
+            }
         }
-        Err(err) => return Err(err),
-    };
+        Err(err) => Err(err),
+    }
+}
 
-    ensure_ao_required(info)?;
+#[cfg(not(target_os = "linux"))]
+pub fn inbound_session_ao(_socket_fd: i32, _peer: SocketAddr) -> io::Result<InboundAo> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tcp-ao is only supported on linux",
+    ))
+}
 
-    debug!(peer = %peer, "verified inbound tcp-ao session state");
-    Ok(())
+/// One MKT the kernel attached to an accepted session, as reported by
+/// `TCP_AO_GET_KEYS`. Key bytes are never copied out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionKey {
+    pub peer: IpAddr,
+    pub prefix: u8,
+    pub sndid: u8,
+    pub rcvid: u8,
+    pub is_current: bool,
+    pub is_rnext: bool,
+    pub pkt_good: u64,
+    pub pkt_bad: u64,
+}
+
+/// Deletes every MKT the accepted socket inherited from the listener that is
+/// not the selected policy's own key, so the session authenticates only with
+/// its peer's key and later listener changes for other peers cannot touch it.
+#[cfg(target_os = "linux")]
+pub fn prune_session_keys(
+    socket_fd: RawFd,
+    peer: SocketAddr,
+    policy: &CompiledPolicy,
+) -> io::Result<usize> {
+    if allow_test_bypass() {
+        return Ok(0);
+    }
+
+    let foreign: Vec<SessionKey> = get_session_keys(socket_fd)?
+        .into_iter()
+        .filter(|key| !key.is_current && !key.is_rnext && !is_policy_key(key, policy))
+        .collect();
+
+    for key in &foreign {
+        let mut del: net::tcp_ao_del = unsafe { mem::zeroed() };
+        del.addr = socket_addr_to_kernel_storage(SocketAddr::new(key.peer, 0));
+        del.prefix = key.prefix;
+        del.sndid = key.sndid;
+        del.rcvid = key.rcvid;
+
+        setsockopt_tcp(
+            socket_fd,
+            net::TCP_AO_DEL_KEY as i32,
+            &del as *const _ as *const libc::c_void,
+            mem::size_of::<net::tcp_ao_del>() as libc::socklen_t,
+            "TCP_AO_DEL_KEY",
+        )?;
+    }
+
+    if !foreign.is_empty() {
+        debug!(
+            peer = %peer,
+            policy = %policy.name(),
+            pruned = foreign.len(),
+            "pruned inherited tcp-ao keys from accepted session"
+        );
+    }
+    Ok(foreign.len())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn prune_session_keys(
+    _socket_fd: i32,
+    _peer: SocketAddr,
+    _policy: &CompiledPolicy,
+) -> io::Result<usize> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "tcp-ao is only supported on linux",
+    ))
+}
+
+/// Every MKT on a connected socket with its live packet counters, for status
+/// reporting.
+#[cfg(target_os = "linux")]
+pub fn session_keys(socket_fd: RawFd) -> io::Result<Vec<SessionKey>> {
+    get_session_keys(socket_fd)
 }
 
 #[cfg(not(target_os = "linux"))]
-pub fn ensure_inbound_session_has_ao(_socket_fd: i32, _peer: SocketAddr) -> io::Result<()> {
+pub fn session_keys(_socket_fd: i32) -> io::Result<Vec<SessionKey>> {
     Err(io::Error::new(
         io::ErrorKind::Unsupported,
         "tcp-ao is only supported on linux",
     ))
 }
 
+/// Whether `key` is the MKT `configure_listener` installs for `policy`.
+pub fn is_policy_key(key: &SessionKey, policy: &CompiledPolicy) -> bool {
+    key.peer == policy.config.peer_ip
+        && key.sndid == policy.config.keyid
+        && key.rcvid == policy.config.keyid
+}
+
+/// The current key must be the policy's MKT for its peer; the rnext key, if
+/// the kernel tracks one, must be one the policy announces.
+pub fn check_session_keys(keys: &[SessionKey], policy: &CompiledPolicy) -> io::Result<SessionKey> {
+    let keyid = policy.config.keyid;
+
+    let current = keys.iter().find(|key| key.is_current).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "tcp-ao session has no current key",
+        )
+    })?;
+
+    if !is_policy_key(current, policy) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "tcp-ao session uses key sndid={} rcvid={} for {}, expected keyid {keyid} for {} from policy '{}'",
+                current.sndid,
+                current.rcvid,
+                current.peer,
+                policy.config.peer_ip,
+                policy.name()
+            ),
+        ));
+    }
+
+    if let Some(rnext) = keys.iter().find(|key| key.is_rnext) {
+        let announced = rnext.rcvid == keyid || Some(rnext.rcvid) == policy.config.rnextkeyid;
+        if rnext.peer != policy.config.peer_ip || !announced {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!(
+                    "tcp-ao session rnext key rcvid={} for {} does not belong to policy '{}'",
+                    rnext.rcvid,
+                    rnext.peer,
+                    policy.name()
+                ),
+            ));
+        }
+    }
+
+    Ok(*current)
+}
+
+/// Whether the socket is in the listening state (`SO_ACCEPTCONN`).
+pub fn is_listening(fd: BorrowedFd<'_>) -> io::Result<bool> {
+    let mut value: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: `value` and `len` describe a valid int-sized buffer.
+    let rc = unsafe {
+        libc::getsockopt(
+            fd.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_ACCEPTCONN,
+            (&mut value as *mut libc::c_int).cast(),
+            &mut len,
+        )
+    };
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(value != 0)
+}
+
 #[cfg(target_os = "linux")]
 fn policy_matches_family(peer_ip: IpAddr, family: i32) -> bool {
     matches!(
@@ -250,33 +658,18 @@ set {TEST_BEST_EFFORT_INBOUND_ENV}=1 only for debug/test best-effort mode"
 }
 
 #[cfg(target_os = "linux")]
-fn ensure_ao_required(info: net::tcp_ao_info_opt) -> io::Result<()> {
-    if info.ao_required() == 0 {
-        return Err(io::Error::new(
-            io::ErrorKind::PermissionDenied,
-            "tcp-ao not required on inbound session",
-        ));
-    }
-
-    Ok(())
-}
-
-#[cfg(target_os = "linux")]
-fn policy_key_bytes(policy: &AoPolicyConfig) -> io::Result<Vec<u8>> {
-    policy
-        .key_source
-        .load_key_bytes()
-        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err.to_string()))
+fn listener_peer(policy: &CompiledPolicy) -> SocketAddr {
+    SocketAddr::new(policy.config.peer_ip, policy.config.peer_port.unwrap_or(0))
 }
 
 #[cfg(target_os = "linux")]
 fn install_key(
     socket_fd: RawFd,
-    policy: &AoPolicyConfig,
+    policy: &CompiledPolicy,
     peer: SocketAddr,
-    key: &[u8],
     set_current: bool,
 ) -> io::Result<()> {
+    let key = policy.key()?.as_bytes();
     if key.len() > net::TCP_AO_MAXKEYLEN as usize {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -288,20 +681,12 @@ fn install_key(
         ));
     }
 
-    let (alg_name, maclen) = normalize_mac_alg(&policy.mac_alg)?;
-    if alg_name.len() >= 64 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "mac_alg string is too long for kernel tcp_ao_add",
-        ));
-    }
-
     let mut add: net::tcp_ao_add = unsafe { mem::zeroed() };
     add.addr = socket_addr_to_kernel_storage(peer);
     add.prefix = prefix_len_for_ip(peer.ip());
-    add.sndid = policy.keyid;
-    add.rcvid = policy.keyid;
-    add.maclen = maclen;
+    add.sndid = policy.config.keyid;
+    add.rcvid = policy.config.keyid;
+    add.maclen = policy.maclen;
     add.keylen = key.len() as u8;
     add.key[..key.len()].copy_from_slice(key);
 
@@ -309,46 +694,63 @@ fn install_key(
         add.set_set_current(1);
     }
 
-    if policy.rnextkeyid.is_some() {
+    if policy.config.rnextkeyid.is_some() {
         add.set_set_rnext(1);
         debug!(
-            policy = %policy.name,
+            policy = %policy.name(),
             "rnextkeyid configured but rollover semantics are not fully implemented yet"
         );
     }
 
-    for (idx, b) in alg_name.as_bytes().iter().enumerate() {
+    for (idx, b) in policy.alg_name.as_bytes().iter().enumerate() {
         add.alg_name[idx] = *b as libc::c_char;
     }
 
-    setsockopt_tcp(
+    let result = setsockopt_tcp(
         socket_fd,
         net::TCP_AO_ADD_KEY as i32,
         &add as *const _ as *const libc::c_void,
         mem::size_of::<net::tcp_ao_add>() as libc::socklen_t,
         "TCP_AO_ADD_KEY",
-    )
+    );
+
+    // The kernel keeps its own copy; do not leave the key on our stack.
+    add.key.zeroize();
+
+    result
 }
 
 #[cfg(target_os = "linux")]
-fn normalize_mac_alg(value: &str) -> io::Result<(String, u8)> {
-    let trimmed = value.trim();
-    if trimmed.is_empty() {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidInput,
-            "mac_alg must not be empty",
-        ));
+fn delete_key(
+    socket_fd: RawFd,
+    policy: &CompiledPolicy,
+    peer: SocketAddr,
+    del_async: bool,
+) -> io::Result<()> {
+    let mut del: net::tcp_ao_del = unsafe { mem::zeroed() };
+    if del_async {
+        del.set_del_async(1);
     }
+    del.addr = socket_addr_to_kernel_storage(peer);
+    del.prefix = prefix_len_for_ip(peer.ip());
+    del.sndid = policy.config.keyid;
+    del.rcvid = policy.config.keyid;
 
-    let lower = trimmed.to_ascii_lowercase();
-    let mapped = match lower.as_str() {
-        "hmac-sha1" | "hmac-sha-1" | "hmac(sha1)" => ("hmac(sha1)".to_string(), 12_u8),
-        "hmac-sha256" | "hmac-sha-256" | "hmac(sha256)" => ("hmac(sha256)".to_string(), 16_u8),
-        "cmac-aes" | "cmac-aes-128" | "cmac(aes)" => ("cmac(aes)".to_string(), 12_u8),
-        _ => (trimmed.to_string(), 12_u8),
-    };
+    setsockopt_tcp(
+        socket_fd,
+        net::TCP_AO_DEL_KEY as i32,
+        &del as *const _ as *const libc::c_void,
+        mem::size_of::<net::tcp_ao_del>() as libc::socklen_t,
+        "TCP_AO_DEL_KEY",
+    )
+}
 
-    Ok(mapped)
+#[cfg(target_os = "linux")]
+fn delete_listener_key(socket_fd: RawFd, policy: &CompiledPolicy) -> io::Result<()> {
+    match delete_key(socket_fd, policy, listener_peer(policy), true) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -390,6 +792,80 @@ fn get_ao_info(socket_fd: RawFd) -> io::Result<net::tcp_ao_info_opt> {
     ))
 }
 
+/// Reads every MKT on the socket. The kernel writes the number of matching
+/// keys into the first entry's `nkeys`, so a short buffer is grown and retried.
+#[cfg(target_os = "linux")]
+fn get_session_keys(socket_fd: RawFd) -> io::Result<Vec<SessionKey>> {
+    let mut capacity = 8_usize;
+
+    loop {
+        let mut raw: Vec<net::tcp_ao_getsockopt> = vec![unsafe { mem::zeroed() }; capacity];
+        raw[0].nkeys = capacity as u32;
+        raw[0].set_get_all(1);
+        let mut optlen = mem::size_of::<net::tcp_ao_getsockopt>() as libc::socklen_t;
+
+        let rc = unsafe {
+            libc::getsockopt(
+                socket_fd,
+                libc::IPPROTO_TCP,
+                net::TCP_AO_GET_KEYS as i32,
+                raw.as_mut_ptr().cast(),
+                &mut optlen,
+            )
+        };
+        let err = io::Error::last_os_error();
+
+        // The kernel copies key bytes out along with the ids; wipe them.
+        for entry in &mut raw {
+            entry.key.zeroize();
+        }
+
+        if rc != 0 {
+            return Err(normalize_ao_error(err, "TCP_AO_GET_KEYS getsockopt"));
+        }
+
+        let matched = raw[0].nkeys as usize;
+        if matched > capacity {
+            capacity = matched;
+            continue;
+        }
+
+        return Ok(raw[..matched]
+            .iter()
+            .map(|entry| SessionKey {
+                peer: kernel_storage_ip(&entry.addr),
+                prefix: entry.prefix,
+                sndid: entry.sndid,
+                rcvid: entry.rcvid,
+                is_current: entry.is_current() != 0,
+                is_rnext: entry.is_rnext() != 0,
+                pkt_good: entry.pkt_good,
+                pkt_bad: entry.pkt_bad,
+            })
+            .collect());
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn kernel_storage_ip(storage: &net::__kernel_sockaddr_storage) -> IpAddr {
+    let storage: libc::sockaddr_storage = unsafe { mem::transmute(*storage) };
+
+    match i32::from(storage.ss_family) {
+        libc::AF_INET6 => {
+            let sin6: libc::sockaddr_in6 = unsafe {
+                ptr::read((&storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>())
+            };
+            IpAddr::from(sin6.sin6_addr.s6_addr)
+        }
+        _ => {
+            let sin: libc::sockaddr_in = unsafe {
+                ptr::read((&storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>())
+            };
+            IpAddr::from(sin.sin_addr.s_addr.to_ne_bytes())
+        }
+    }
+}
+
 #[cfg(target_os = "linux")]
 fn setsockopt_tcp(
     socket_fd: RawFd,
@@ -529,17 +1005,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn normalize_mac_alg_maps_known_values() {
-        let (name, maclen) = normalize_mac_alg("hmac-sha1").expect("valid alg");
-        assert_eq!(name, "hmac(sha1)");
-        assert_eq!(maclen, 12);
-
-        let (name, maclen) = normalize_mac_alg("hmac-sha256").expect("valid alg");
-        assert_eq!(name, "hmac(sha256)");
-        assert_eq!(maclen, 16);
-    }
-
     #[test]
     fn prefix_len_uses_full_prefix_for_specific_addresses() {
         assert_eq!(
@@ -583,17 +1048,208 @@ mod tests {
         .is_ok());
     }
 
+    fn policy(keyid: u8, rnextkeyid: Option<u8>) -> CompiledPolicy {
+        CompiledPolicy {
+            config: crate::config::AoPolicyConfig {
+                name: "peer-a".to_string(),
+                peer_ip: "192.0.2.10".parse().expect("valid ip"),
+                peer_port: None,
+                keyid,
+                rnextkeyid,
+                mac_alg: "hmac-sha256".to_string(),
+                key_source: crate::config::KeySource("env:UNUSED".to_string()),
+                enforcement: Enforcement::Required,
+            },
+            alg_name: "hmac(sha256)".to_string(),
+            maclen: 12,
+            key: None,
+            key_error: None,
+        }
+    }
+
+    fn session_key(peer: &str, sndid: u8, rcvid: u8, current: bool, rnext: bool) -> SessionKey {
+        SessionKey {
+            peer: peer.parse().expect("valid ip"),
+            prefix: 32,
+            sndid,
+            rcvid,
+            is_current: current,
+            is_rnext: rnext,
+            pkt_good: 3,
+            pkt_bad: 0,
+        }
+    }
+
+    #[test]
+    fn session_keys_accept_policy_key() {
+        let keys = [session_key("192.0.2.10", 7, 7, true, true)];
+        let current = check_session_keys(&keys, &policy(7, None)).expect("matching key");
+        assert_eq!(current.sndid, 7);
+        assert_eq!(current.pkt_good, 3);
+    }
+
     #[test]
-    fn ensure_ao_required_rejects_zero_flag() {
-        let info: net::tcp_ao_info_opt = unsafe { mem::zeroed() };
-        let err = ensure_ao_required(info).expect_err("ao_required=0 should fail");
+    fn session_keys_reject_other_policy_key() {
+        let wrong_id = [session_key("192.0.2.10", 9, 9, true, false)];
+        let err = check_session_keys(&wrong_id, &policy(7, None)).expect_err("wrong keyid");
         assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+
+        let wrong_peer = [session_key("192.0.2.99", 7, 7, true, false)];
+        assert!(check_session_keys(&wrong_peer, &policy(7, None)).is_err());
+
+        assert!(check_session_keys(&[], &policy(7, None)).is_err());
     }
 
     #[test]
-    fn ensure_ao_required_accepts_nonzero_flag() {
-        let mut info: net::tcp_ao_info_opt = unsafe { mem::zeroed() };
-        info.set_ao_required(1);
-        assert!(ensure_ao_required(info).is_ok());
+    fn session_keys_check_rnext_against_policy() {
+        let keys = [
+            session_key("192.0.2.10", 7, 7, true, false),
+            session_key("192.0.2.10", 8, 8, false, true),
+        ];
+        assert!(check_session_keys(&keys, &policy(7, None)).is_err());
+        assert!(check_session_keys(&keys, &policy(7, Some(8))).is_ok());
+    }
+
+    #[test]
+    fn only_the_policy_key_is_kept_on_accepted_sessions() {
+        let policy = policy(7, None);
+        assert!(is_policy_key(
+            &session_key("192.0.2.10", 7, 7, false, false),
+            &policy
+        ));
+        assert!(!is_policy_key(
+            &session_key("192.0.2.10", 8, 8, false, false),
+            &policy
+        ));
+        assert!(!is_policy_key(
+            &session_key("0.0.0.0", 7, 7, false, false),
+            &policy
+        ));
+    }
+
+    #[test]
+    fn only_required_peers_are_keyed_on_the_fallback_listener() {
+        for (enforcement, primary, fallback) in [
+            (Enforcement::Required, true, true),
+            (Enforcement::Preferred, true, false),
+            (Enforcement::Monitor, false, false),
+        ] {
+            let mut policy = policy(7, None);
+            policy.config.enforcement = enforcement;
+            assert_eq!(ListenerRole::Primary.installs_key(&policy), primary);
+            assert_eq!(ListenerRole::Fallback.installs_key(&policy), fallback);
+        }
+    }
+
+    #[test]
+    fn required_sessions_stay_signed_on_a_listener_shared_with_preferred_peers() {
+        let _guard = env_lock().lock().expect("env lock");
+        let _bypass = ScopedEnvVar::set(TEST_BYPASS_ENV, None);
+        let _best_effort = ScopedEnvVar::set(TEST_BEST_EFFORT_INBOUND_ENV, None);
+        if let Err(err) = probe_tcpao_support() {
+            eprintln!("skipping: kernel without tcp-ao ({err})");
+            return;
+        }
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let key = dir.path().join("key");
+        std::fs::write(&key, "shared-listener-key").expect("write key");
+        let mut required = policy(7, None).config;
+        required.name = "required".to_string();
+        required.peer_ip = "127.0.0.1".parse().expect("valid ip");
+        required.key_source = crate::config::KeySource(format!("file:{}", key.display()));
+        let mut preferred = required.clone();
+        preferred.name = "preferred".to_string();
+        preferred.peer_ip = "127.0.0.2".parse().expect("valid ip");
+        preferred.enforcement = Enforcement::Preferred;
+        let set = PolicySet::compile(&[required, preferred]).expect("compiled");
+        let required = set.get("required").expect("required policy");
+
+        let connect = |from: &str, to: SocketAddr, sign: bool| {
+            let socket = socket2::Socket::new(socket2::Domain::IPV4, socket2::Type::STREAM, None)
+                .expect("socket");
+            let from: SocketAddr = format!("{from}:0").parse().expect("valid addr");
+            socket.bind(&from.into()).expect("bind client");
+            if sign {
+                apply_outbound_policy(socket.as_raw_fd(), required, to).expect("client key");
+            }
+            socket.connect(&to.into()).expect("connect");
+            socket
+        };
+
+        // The listener serves a relaxed policy, so it is not ao_required; a
+        // signed session from the required peer must still count as signed.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        configure_listener(listener.as_raw_fd(), addr, &set, ListenerRole::Primary)
+            .expect("listener keys");
+        let _signed = connect("127.0.0.1", addr, true);
+        let (accepted, peer) = listener.accept().expect("accept signed");
+        match inbound_session_ao(accepted.as_raw_fd(), peer).expect("inspect") {
+            InboundAo::Signed(keys) => {
+                check_session_keys(&keys, required).expect("required peer's key");
+            }
+            other => panic!("signed session reported as {other:?}"),
+        }
+
+        // The fallback listener holds no key for the preferred peer, which
+        // connects there without AO.
+        let fallback = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let fallback_addr = fallback.local_addr().expect("addr");
+        configure_listener(
+            fallback.as_raw_fd(),
+            fallback_addr,
+            &set,
+            ListenerRole::Fallback,
+        )
+        .expect("fallback keys");
+        let _unsigned = connect("127.0.0.2", fallback_addr, false);
+        let (accepted, peer) = fallback.accept().expect("accept unsigned");
+        assert_eq!(
+            inbound_session_ao(accepted.as_raw_fd(), peer).expect("inspect"),
+            InboundAo::Unsigned
+        );
+    }
+
+    #[test]
+    fn rekeys_a_live_listener_with_one_policy() {
+        let _guard = env_lock().lock().expect("env lock");
+        let _bypass = ScopedEnvVar::set(TEST_BYPASS_ENV, None);
+        if let Err(err) = probe_tcpao_support() {
+            eprintln!("skipping: kernel without tcp-ao ({err})");
+            return;
+        }
+
+        let dir = tempfile::tempdir().expect("tempdir");
+        let key = dir.path().join("key");
+        std::fs::write(&key, "first-listener-key").expect("write key");
+        let mut config = policy(7, None).config;
+        config.peer_ip = "127.0.0.1".parse().expect("valid ip");
+        config.key_source = crate::config::KeySource(format!("file:{}", key.display()));
+        let first = PolicySet::compile(std::slice::from_ref(&config)).expect("compiled");
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("addr");
+        let fd = listener.as_raw_fd();
+        configure_listener(fd, addr, &first, ListenerRole::Primary)
+            .expect("keys on a listening socket");
+
+        std::fs::write(&key, "second-listener-key").expect("rotate key");
+        let second = first
+            .reload(std::slice::from_ref(&config))
+            .expect("reloaded");
+        rekey_listener(fd, addr, &first, &second, ListenerRole::Primary)
+            .expect("rekeyed the only key");
+        check_listener(fd, addr, &second, ListenerRole::Primary).expect("key still installed");
+        rekey_listener(fd, addr, &second, &first, ListenerRole::Primary).expect("rotated back");
+    }
+
+    #[test]
+    fn kernel_storage_ip_round_trips() {
+        for addr in ["192.0.2.10:0", "[2001:db8::1]:0"] {
+            let addr: SocketAddr = addr.parse().expect("valid addr");
+            let storage = socket_addr_to_kernel_storage(addr);
+            assert_eq!(kernel_storage_ip(&storage), addr.ip());
+        }
     }
 }
@@ -1,27 +1,51 @@
+use std::io;
 use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
 
+use tracing::warn;
+
+use crate::audit;
 use crate::config::AoPolicyConfig;
+use crate::error::{ProxyError, Result};
+use crate::secret::KeyMaterial;
+
+/// Peer criteria shared by raw and compiled policies so both can be matched
+/// with the same selection rules.
+pub trait PeerMatch {
+    fn peer_ip(&self) -> IpAddr;
+    fn peer_port(&self) -> Option<u16>;
+}
+
+impl PeerMatch for AoPolicyConfig {
+    fn peer_ip(&self) -> IpAddr {
+        self.peer_ip
+    }
+
+    fn peer_port(&self) -> Option<u16> {
+        self.peer_port
+    }
+}
 
-pub fn select_policy(
-    policies: &[AoPolicyConfig],
+pub fn select_policy<P: PeerMatch>(
+    policies: &[P],
     peer_ip: IpAddr,
     peer_port: Option<u16>,
-) -> Option<&AoPolicyConfig> {
+) -> Option<&P> {
     if let Some(port) = peer_port {
         if let Some(exact) = policies
             .iter()
-            .find(|policy| policy.peer_ip == peer_ip && policy.peer_port == Some(port))
+            .find(|policy| policy.peer_ip() == peer_ip && policy.peer_port() == Some(port))
         {
             return Some(exact);
         }
         return policies
             .iter()
-            .find(|policy| policy.peer_ip == peer_ip && policy.peer_port.is_none());
+            .find(|policy| policy.peer_ip() == peer_ip && policy.peer_port().is_none());
     }
 
     let mut ip_only = policies
         .iter()
-        .filter(|policy| policy.peer_ip == peer_ip && policy.peer_port.is_none());
+        .filter(|policy| policy.peer_ip() == peer_ip && policy.peer_port().is_none());
     let ip_only_first = ip_only.next();
     if ip_only_first.is_some() {
         return if ip_only.next().is_none() {
@@ -31,7 +55,7 @@ pub fn select_policy(
         };
     }
 
-    let mut any_for_ip = policies.iter().filter(|policy| policy.peer_ip == peer_ip);
+    let mut any_for_ip = policies.iter().filter(|policy| policy.peer_ip() == peer_ip);
     let first = any_for_ip.next();
     if any_for_ip.next().is_none() {
         first
@@ -40,14 +64,298 @@ pub fn select_policy(
     }
 }
 
+/// Maps a configured `mac_alg` to the kernel algorithm name and MAC length.
+pub fn normalize_mac_alg(value: &str) -> io::Result<(String, u8)> {
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "mac_alg must not be empty",
+        ));
+    }
+
+    let lower = trimmed.to_ascii_lowercase();
+    let mapped = match lower.as_str() {
+        "hmac-sha1" | "hmac-sha-1" | "hmac(sha1)" => ("hmac(sha1)".to_string(), 12_u8),
+        "hmac-sha256" | "hmac-sha-256" | "hmac(sha256)" => ("hmac(sha256)".to_string(), 16_u8),
+        "cmac-aes" | "cmac-aes-128" | "cmac(aes)" => ("cmac(aes)".to_string(), 12_u8),
+        _ => (trimmed.to_string(), 12_u8),
+    };
+
+    Ok(mapped)
+}
+
+/// An `[[ao_policy]]` entry with its algorithm normalized and key loaded.
+///
+/// `key` is `None` only after a reload in which a fail-closed key source could
+/// not be read; such a policy matches peers but cannot authenticate them.
+#[derive(Debug)]
+pub struct CompiledPolicy {
+    pub config: AoPolicyConfig,
+    pub alg_name: String,
+    pub maclen: u8,
+    pub key: Option<Arc<KeyMaterial>>,
+    /// Why the key source could not be read on the last reload, if it failed.
+    pub key_error: Option<String>,
+}
+
+impl PeerMatch for CompiledPolicy {
+    fn peer_ip(&self) -> IpAddr {
+        self.config.peer_ip
+    }
+
+    fn peer_port(&self) -> Option<u16> {
+        self.config.peer_port
+    }
+}
+
+impl CompiledPolicy {
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn key(&self) -> io::Result<&KeyMaterial> {
+        self.key.as_deref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("key for policy '{}' is unavailable", self.config.name),
+            )
+        })
+    }
+}
+
+/// Policies compiled once at startup and on reload. Connection handlers only
+/// read from it, so the per-connection path does no key I/O.
+#[derive(Debug, Default)]
+pub struct PolicySet {
+    policies: Vec<CompiledPolicy>,
+}
+
+impl PolicySet {
+    /// Compiles every policy; any unreadable key or invalid algorithm fails.
+    pub fn compile(configs: &[AoPolicyConfig]) -> Result<Self> {
+        let mut policies = Vec::with_capacity(configs.len());
+        for config in configs {
+            let (alg_name, maclen) = compile_alg(config)?;
+            let key = config.key_source.load_key().map_err(|e| {
+                audit::key_load_failed(&config.name, config.keyid, &e.to_string());
+                ProxyError::Config(format!(
+                    "failed to load key for ao_policy '{}': {e}",
+                    config.name
+                ))
+            })?;
+            audit::key_loaded(&config.name, config.keyid, key.fingerprint());
+            policies.push(CompiledPolicy {
+                config: config.clone(),
+                alg_name,
+                maclen,
+                key: Some(Arc::new(key)),
+                key_error: None,
+            });
+        }
+
+        Ok(Self { policies })
+    }
+
+    /// Runs [`PolicySet::compile`] on the blocking pool, since key sources
+    /// may run commands or talk to Vault.
+    pub async fn compile_in_background(configs: &[AoPolicyConfig]) -> Result<Self> {
+        let configs = configs.to_vec();
+        tokio::task::spawn_blocking(move || Self::compile(&configs))
+            .await
+            .map_err(|e| ProxyError::Io(io::Error::other(e)))?
+    }
+
+    /// Recompiles against freshly loaded keys. A key that cannot be read keeps
+    /// its previous material unless the source fails closed, in which case
+    /// the policy is left without a key.
+    pub fn reload(&self, configs: &[AoPolicyConfig]) -> Result<Self> {
+        let mut policies = Vec::with_capacity(configs.len());
+        for config in configs {
+            let (alg_name, maclen) = compile_alg(config)?;
+            let (key, key_error) = match config.key_source.load_key() {
+                Ok(key) => {
+                    let previous = self.get(&config.name).and_then(|p| p.key.as_ref());
+                    let key = match previous {
+                        Some(prev) if **prev == key => Arc::clone(prev),
+                        _ => {
+                            audit::key_loaded(&config.name, config.keyid, key.fingerprint());
+                            Arc::new(key)
+                        }
+                    };
+                    (Some(key), None)
+                }
+                Err(err) if config.key_source.fails_closed() => {
+                    audit::key_load_failed(&config.name, config.keyid, &err.to_string());
+                    warn!(policy = %config.name, error = %err, "key unavailable; policy disabled");
+                    (None, Some(err.to_string()))
+                }
+                Err(err) => {
+                    audit::key_load_failed(&config.name, config.keyid, &err.to_string());
+                    warn!(policy = %config.name, error = %err, "key unavailable; keeping previous key");
+                    let previous = self.get(&config.name).and_then(|p| p.key.clone());
+                    (previous, Some(err.to_string()))
+                }
+            };
+            policies.push(CompiledPolicy {
+                config: config.clone(),
+                alg_name,
+                maclen,
+                key,
+                key_error,
+            });
+        }
+
+        Ok(Self { policies })
+    }
+
+    /// Runs [`PolicySet::reload`] on the blocking pool, since key sources may
+    /// run commands or talk to Vault.
+    pub async fn reload_in_background(self: Arc<Self>, configs: &[AoPolicyConfig]) -> Result<Self> {
+        let configs = configs.to_vec();
+        tokio::task::spawn_blocking(move || self.reload(&configs))
+            .await
+            .map_err(|e| ProxyError::Io(io::Error::other(e)))?
+    }
+
+    pub fn select(&self, peer_ip: IpAddr, peer_port: Option<u16>) -> Option<&CompiledPolicy> {
+        select_policy(&self.policies, peer_ip, peer_port)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CompiledPolicy> {
+        self.policies.iter().find(|p| p.config.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &CompiledPolicy> {
+        self.policies.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.policies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.policies.is_empty()
+    }
+}
+
+fn compile_alg(config: &AoPolicyConfig) -> Result<(String, u8)> {
+    let (alg_name, maclen) = normalize_mac_alg(&config.mac_alg)
+        .map_err(|e| ProxyError::Config(format!("ao_policy '{}': {e}", config.name)))?;
+    if alg_name.len() >= 64 {
+        return Err(ProxyError::Config(format!(
+            "ao_policy '{}': mac_alg string is too long for kernel tcp_ao_add",
+            config.name
+        )));
+    }
+    Ok((alg_name, maclen))
+}
+
+/// Shared handle to the current `PolicySet`; readers take a cheap `Arc` clone.
+#[derive(Debug)]
+pub struct PolicyStore {
+    current: RwLock<Arc<PolicySet>>,
+}
+
+impl PolicyStore {
+    pub fn new(set: PolicySet) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(set)),
+        }
+    }
+
+    pub fn current(&self) -> Arc<PolicySet> {
+        Arc::clone(&self.current.read().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Replaces the current set and returns the previous one.
+    pub fn replace(&self, set: PolicySet) -> Arc<PolicySet> {
+        let mut current = self.current.write().unwrap_or_else(|e| e.into_inner());
+        std::mem::replace(&mut *current, Arc::new(set))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::str::FromStr;
 
-    use crate::config::{AoPolicyConfig, KeySource};
+    use crate::config::{AoPolicyConfig, Enforcement, KeySource};
 
     use super::*;
 
+    fn env_policy(name: &str, key_env: &str) -> AoPolicyConfig {
+        AoPolicyConfig {
+            name: name.to_string(),
+            peer_ip: IpAddr::from_str("10.0.0.2").expect("valid ip"),
+            peer_port: None,
+            keyid: 1,
+            rnextkeyid: None,
+            mac_alg: "hmac-sha256".to_string(),
+            key_source: KeySource(format!("env:{key_env}")),
+            enforcement: Enforcement::Required,
+        }
+    }
+
+    #[test]
+    fn normalize_mac_alg_maps_known_values() {
+        let (name, maclen) = normalize_mac_alg("hmac-sha1").expect("valid alg");
+        assert_eq!(name, "hmac(sha1)");
+        assert_eq!(maclen, 12);
+
+        let (name, maclen) = normalize_mac_alg("hmac-sha256").expect("valid alg");
+        assert_eq!(name, "hmac(sha256)");
+        assert_eq!(maclen, 16);
+    }
+
+    #[test]
+    fn policy_set_compiles_keys_once() {
+        std::env::set_var("TCPAO_POLICY_SET_KEY", "compiled-key");
+        let set = PolicySet::compile(&[env_policy("peer-a", "TCPAO_POLICY_SET_KEY")])
+            .expect("compiled set");
+
+        let compiled = set
+            .select(IpAddr::from_str("10.0.0.2").expect("valid ip"), Some(1790))
+            .expect("matching policy");
+        assert_eq!(compiled.name(), "peer-a");
+        assert_eq!(compiled.alg_name, "hmac(sha256)");
+        assert_eq!(compiled.maclen, 16);
+        assert_eq!(compiled.key().expect("key").as_bytes(), b"compiled-key");
+    }
+
+    #[test]
+    fn policy_set_compile_fails_on_missing_key() {
+        let err = PolicySet::compile(&[env_policy("peer-a", "TCPAO_POLICY_SET_MISSING")])
+            .expect_err("missing key must fail");
+        assert!(err.to_string().contains("peer-a"));
+    }
+
+    #[test]
+    fn policy_set_reload_keeps_previous_key_when_source_is_unreadable() {
+        std::env::set_var("TCPAO_POLICY_SET_RELOAD", "first-key");
+        let configs = [env_policy("peer-a", "TCPAO_POLICY_SET_RELOAD")];
+        let first = PolicySet::compile(&configs).expect("compiled set");
+
+        std::env::remove_var("TCPAO_POLICY_SET_RELOAD");
+        let second = first.reload(&configs).expect("reloaded set");
+        let policy = second.get("peer-a").expect("policy");
+        assert_eq!(policy.key().expect("key").as_bytes(), b"first-key");
+        assert!(policy.key_error.is_some());
+        assert!(first.get("peer-a").expect("policy").key_error.is_none());
+    }
+
+    #[test]
+    fn policy_store_swaps_sets() {
+        let store = PolicyStore::new(PolicySet::default());
+        assert!(store.current().is_empty());
+
+        std::env::set_var("TCPAO_POLICY_STORE_KEY", "store-key");
+        let set = PolicySet::compile(&[env_policy("peer-a", "TCPAO_POLICY_STORE_KEY")])
+            .expect("compiled set");
+        let previous = store.replace(set);
+        assert!(previous.is_empty());
+        assert_eq!(store.current().len(), 1);
+    }
+
     #[test]
     fn policy_match_with_port_preference() {
         let policies = vec![
@@ -59,6 +367,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
             AoPolicyConfig {
                 name: "with-port".to_string(),
@@ -68,6 +377,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
         ];
 
@@ -91,6 +401,7 @@ mod tests {
             rnextkeyid: None,
             mac_alg: "hmac-sha256".to_string(),
             key_source: KeySource("env:KEY".to_string()),
+            enforcement: Enforcement::Required,
         }];
 
         let matched = select_policy(
@@ -113,6 +424,7 @@ mod tests {
             rnextkeyid: None,
             mac_alg: "hmac-sha256".to_string(),
             key_source: KeySource("env:KEY".to_string()),
+            enforcement: Enforcement::Required,
         }];
 
         let matched = select_policy(
@@ -136,6 +448,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
             AoPolicyConfig {
                 name: "ip-only".to_string(),
@@ -145,6 +458,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
         ];
 
@@ -169,6 +483,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
             AoPolicyConfig {
                 name: "with-port-b".to_string(),
@@ -178,6 +493,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
         ];
 
@@ -201,6 +517,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
             AoPolicyConfig {
                 name: "ip-only".to_string(),
@@ -210,6 +527,7 @@ mod tests {
                 rnextkeyid: None,
                 mac_alg: "hmac-sha256".to_string(),
                 key_source: KeySource("env:KEY".to_string()),
+                enforcement: Enforcement::Required,
             },
         ];
         let reversed = vec![forward[1].clone(), forward[0].clone()];
@@ -0,0 +1,490 @@
+//! Per-connection tracing spans, and OTLP export of spans and [`Metrics`]
+//! counters.
+//!
+//! Export is only built with the `otel` cargo feature; without it a
+//! `[telemetry]` section is accepted but ignored, so one config works for
+//! both builds. Spans go through `tracing-opentelemetry`, so every `tracing`
+//! span, including the per-connection ones, becomes an OTLP span. Counters
+//! are read from the shared [`Metrics`] when the periodic reader collects.
+
+use std::fmt::Display;
+use std::sync::Arc;
+
+use tracing::field::Empty;
+use tracing::{info_span, Span};
+
+use crate::metrics::Metrics;
+use crate::tcpao::policy::CompiledPolicy;
+
+#[cfg(feature = "otel")]
+pub use otel::{init, Telemetry};
+
+/// Stand-in so callers need no `cfg` of their own; never constructed
+/// without the `otel` feature.
+#[cfg(not(feature = "otel"))]
+pub struct Telemetry(());
+
+#[cfg(not(feature = "otel"))]
+impl Telemetry {
+    pub async fn shutdown(self) {}
+}
+
+/// Exports `metrics` for `mode` through the meter provider installed by
+/// [`init`]. A no-op when telemetry is off or not built in.
+pub fn observe(mode: &'static str, metrics: &Arc<Metrics>) {
+    #[cfg(feature = "otel")]
+    otel::observe(mode, metrics);
+    #[cfg(not(feature = "otel"))]
+    let _ = (mode, metrics);
+}
+
+/// The span a connection task runs in. Every event of the task, including
+/// those from `tcpao::linux`, carries its fields. `service` is the configured
+/// service name, `listen` the listener that accepted the connection, `peer`
+/// the accepted side and `ao_peer` the AO side; the policy fields are filled
+/// in by [`record_policy`].
+pub fn connection_span(
+    mode: &'static str,
+    service: &str,
+    listen: &dyn Display,
+    conn_id: u64,
+    peer: &dyn Display,
+    ao_peer: &dyn Display,
+) -> Span {
+    info_span!(
+        "connection",
+        mode,
+        service,
+        listen = %listen,
+        conn_id,
+        peer = %peer,
+        ao_peer = %ao_peer,
+        client = Empty,
+        policy = Empty,
+        keyid = Empty,
+        rnextkeyid = Empty,
+        session_keyid = Empty,
+    )
+}
+
+/// Records the selected policy on the current connection span.
+pub fn record_policy(policy: &CompiledPolicy) {
+    let span = Span::current();
+    span.record("policy", policy.name());
+    span.record("keyid", policy.config.keyid);
+    if let Some(rnextkeyid) = policy.config.rnextkeyid {
+        span.record("rnextkeyid", rnextkeyid);
+    }
+}
+
+#[cfg(feature = "otel")]
+mod otel {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::{global, KeyValue};
+    use opentelemetry_otlp::{MetricExporter, Protocol, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+    use opentelemetry_sdk::Resource;
+    use tracing::warn;
+    use tracing_opentelemetry::OpenTelemetryLayer;
+    use tracing_subscriber::registry::LookupSpan;
+
+    use crate::config::{OtlpProtocol, TelemetryConfig};
+    use crate::error::{ProxyError, Result};
+    use crate::metrics::Metrics;
+
+    const SCOPE: &str = "tcpao-proxy";
+
+    /// Keeps the exporters alive; [`Telemetry::shutdown`] flushes them.
+    pub struct Telemetry {
+        tracer: SdkTracerProvider,
+        meter: SdkMeterProvider,
+    }
+
+    impl Telemetry {
+        /// Flushes pending spans and a last metrics collection. Blocks, so it
+        /// runs on a blocking thread while the exporters use the runtime.
+        pub async fn shutdown(self) {
+            let result = tokio::task::spawn_blocking(move || {
+                (self.tracer.shutdown(), self.meter.shutdown())
+            })
+            .await;
+            if let Ok((tracer, meter)) = result {
+                if let Err(err) = tracer.and(meter) {
+                    warn!(error = %err, "failed to flush telemetry");
+                }
+            }
+        }
+    }
+
+    /// Builds the exporters and returns the tracing layer to add to the
+    /// subscriber. Must run inside the tokio runtime, which the gRPC
+    /// exporter spawns its connection onto.
+    pub fn init<S>(
+        cfg: &TelemetryConfig,
+        mode: &'static str,
+    ) -> Result<(Telemetry, OpenTelemetryLayer<S, SdkTracer>)>
+    where
+        S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let resource = Resource::builder()
+            .with_service_name(cfg.service_name.clone())
+            .with_attribute(KeyValue::new("tcpao.mode", mode))
+            .build();
+
+        let spans = match cfg.protocol {
+            OtlpProtocol::Grpc => SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(&cfg.endpoint)
+                .build(),
+            OtlpProtocol::Http => SpanExporter::builder()
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(http_endpoint(&cfg.endpoint, "traces"))
+                .build(),
+        }
+        .map_err(export_error)?;
+        let counters = match cfg.protocol {
+            OtlpProtocol::Grpc => MetricExporter::builder()
+                .with_tonic()
+                .with_endpoint(&cfg.endpoint)
+                .build(),
+            OtlpProtocol::Http => MetricExporter::builder()
+                .with_http()
+                .with_protocol(Protocol::HttpBinary)
+                .with_endpoint(http_endpoint(&cfg.endpoint, "metrics"))
+                .build(),
+        }
+        .map_err(export_error)?;
+
+        let tracer = SdkTracerProvider::builder()
+            .with_batch_exporter(spans)
+            .with_resource(resource.clone())
+            .build();
+        let reader = PeriodicReader::builder(counters)
+            .with_interval(Duration::from_secs(cfg.metrics_interval_secs))
+            .build();
+        let meter = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+        global::set_meter_provider(meter.clone());
+
+        let layer = tracing_opentelemetry::layer().with_tracer(tracer.tracer(SCOPE));
+        Ok((Telemetry { tracer, meter }, layer))
+    }
+
+    /// One [`Metrics`] value exported as an observable instrument.
+    struct Reading<T> {
+        name: &'static str,
+        description: &'static str,
+        unit: &'static str,
+        read: fn(&Metrics) -> T,
+    }
+
+    const COUNTERS: [Reading<u64>; 4] = [
+        Reading {
+            name: "tcpao.connections.closed",
+            description: "Connections closed",
+            unit: "{connection}",
+            read: Metrics::closed_connections,
+        },
+        Reading {
+            name: "tcpao.connections.rejected",
+            description: "Connections refused by admission control",
+            unit: "{connection}",
+            read: Metrics::rejected_connections,
+        },
+        Reading {
+            name: "tcpao.connections.evicted",
+            description: "Sessions closed to admit a newer one",
+            unit: "{connection}",
+            read: Metrics::evicted_connections,
+        },
+        Reading {
+            name: "tcpao.sessions.unprotected",
+            description: "Sessions admitted without tcp-ao",
+            unit: "{session}",
+            read: Metrics::unprotected_sessions,
+        },
+    ];
+
+    const GAUGES: [Reading<u64>; 3] = [
+        Reading {
+            name: "tcpao.connections.open",
+            description: "Connections currently open",
+            unit: "{connection}",
+            read: Metrics::open_connections,
+        },
+        Reading {
+            name: "tcpao.buffer.high_water.up",
+            description: "Most bytes buffered at once towards the destination",
+            unit: "By",
+            read: Metrics::high_water_up,
+        },
+        Reading {
+            name: "tcpao.buffer.high_water.down",
+            description: "Most bytes buffered at once towards the source",
+            unit: "By",
+            read: Metrics::high_water_down,
+        },
+    ];
+
+    const WRITE_BLOCKED: [Reading<Duration>; 2] = [
+        Reading {
+            name: "tcpao.write_blocked.up",
+            description: "Time spent waiting for the destination to take data",
+            unit: "s",
+            read: Metrics::write_blocked_up,
+        },
+        Reading {
+            name: "tcpao.write_blocked.down",
+            description: "Time spent waiting for the source to take data",
+            unit: "s",
+            read: Metrics::write_blocked_down,
+        },
+    ];
+
+    pub(super) fn observe(mode: &'static str, metrics: &Arc<Metrics>) {
+        let meter = global::meter_provider().meter(SCOPE);
+        let attrs = [KeyValue::new("mode", mode)];
+
+        for Reading {
+            name,
+            description,
+            unit,
+            read,
+        } in COUNTERS
+        {
+            let (metrics, attrs) = (Arc::clone(metrics), attrs.clone());
+            meter
+                .u64_observable_counter(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| observer.observe(read(&metrics), &attrs))
+                .build();
+        }
+
+        for Reading {
+            name,
+            description,
+            unit,
+            read,
+        } in GAUGES
+        {
+            let (metrics, attrs) = (Arc::clone(metrics), attrs.clone());
+            meter
+                .u64_observable_gauge(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| observer.observe(read(&metrics), &attrs))
+                .build();
+        }
+
+        for Reading {
+            name,
+            description,
+            unit,
+            read,
+        } in WRITE_BLOCKED
+        {
+            let (metrics, attrs) = (Arc::clone(metrics), attrs.clone());
+            meter
+                .f64_observable_counter(name)
+                .with_description(description)
+                .with_unit(unit)
+                .with_callback(move |observer| {
+                    observer.observe(read(&metrics).as_secs_f64(), &attrs)
+                })
+                .build();
+        }
+    }
+
+    fn http_endpoint(base: &str, signal: &str) -> String {
+        format!("{}/v1/{signal}", base.trim_end_matches('/'))
+    }
+
+    fn export_error(err: impl std::fmt::Display) -> ProxyError {
+        ProxyError::Config(format!("failed to set up otlp export: {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+    use crate::config::AoPolicyConfig;
+
+    /// Collects formatted log lines.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().expect("lock").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[test]
+    fn nested_events_carry_the_connection_context() {
+        let policy = CompiledPolicy {
+            config: AoPolicyConfig {
+                keyid: 7,
+                rnextkeyid: Some(8),
+                ..AoPolicyConfig::for_test("peer-a", "192.0.2.10", "env:UNUSED")
+            },
+            alg_name: "hmac(sha256)".to_string(),
+            maclen: 12,
+            key: None,
+            key_error: None,
+        };
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(captured.clone())
+            .with_max_level(tracing::Level::DEBUG)
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = connection_span(
+                "initiator",
+                "tcpao-proxy",
+                &"127.0.0.1:11019",
+                42,
+                &"127.0.0.1:50000",
+                &"192.0.2.10:1790",
+            );
+            let _entered = span.enter();
+            record_policy(&policy);
+            info_span!("ao_connect").in_scope(|| tracing::debug!("applied"));
+        });
+
+        let out = String::from_utf8(captured.0.lock().expect("lock").clone()).expect("utf8");
+        for field in [
+            "mode=\"initiator\"",
+            "service=\"tcpao-proxy\"",
+            "listen=127.0.0.1:11019",
+            "conn_id=42",
+            "peer=127.0.0.1:50000",
+            "ao_peer=192.0.2.10:1790",
+            "policy=\"peer-a\"",
+            "keyid=7",
+            "rnextkeyid=8",
+        ] {
+            assert!(out.contains(field), "missing {field} in {out}");
+        }
+        assert!(out.contains("ao_connect: "), "{out}");
+    }
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod otel_tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::config::{OtlpProtocol, TelemetryConfig};
+
+    /// Accepts OTLP/HTTP posts and records `path` and body size.
+    async fn receiver() -> (String, Arc<Mutex<Vec<(String, usize)>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let endpoint = format!("http://{}", listener.local_addr().expect("addr"));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&seen);
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let record = Arc::clone(&record);
+                tokio::spawn(async move {
+                    let mut raw = Vec::new();
+                    let mut buf = [0_u8; 4096];
+                    loop {
+                        let Ok(n) = stream.read(&mut buf).await else {
+                            return;
+                        };
+                        if n == 0 {
+                            return;
+                        }
+                        raw.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&raw).into_owned();
+                        let Some((head, _)) = text.split_once("\r\n\r\n") else {
+                            continue;
+                        };
+                        let len: usize = head
+                            .lines()
+                            .find_map(|l| {
+                                l.to_ascii_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse().unwrap_or(0))
+                            })
+                            .unwrap_or(0);
+                        if raw.len() < head.len() + 4 + len {
+                            continue;
+                        }
+                        let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+                        record.lock().expect("lock").push((path, len));
+                        raw.drain(..head.len() + 4 + len);
+                        let _ = stream
+                            .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-protobuf\r\nContent-Length: 0\r\n\r\n")
+                            .await;
+                    }
+                });
+            }
+        });
+        (endpoint, seen)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn exports_spans_and_counters_over_http() {
+        let (endpoint, seen) = receiver().await;
+        let cfg = TelemetryConfig {
+            endpoint,
+            protocol: OtlpProtocol::Http,
+            service_name: "tcpao-proxy-test".to_string(),
+            metrics_interval_secs: 60,
+        };
+
+        let (telemetry, layer) = init(&cfg, "terminator").expect("telemetry");
+        let subscriber = tracing_subscriber::registry().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("connection", conn_id = 1_u64);
+            span.in_scope(|| tracing::info!("inside"));
+        });
+        let metrics = Arc::new(Metrics::default());
+        metrics.conn_opened();
+        observe("terminator", &metrics);
+        telemetry.shutdown().await;
+
+        let seen = seen.lock().expect("lock").clone();
+        for path in ["/v1/traces", "/v1/metrics"] {
+            assert!(
+                seen.iter().any(|(p, len)| p == path && *len > 0),
+                "no export to {path}: {seen:?}"
+            );
+        }
+    }
+}
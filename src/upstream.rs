@@ -0,0 +1,206 @@
+//! Active health checking of the terminator's `forward_plain`.
+//!
+//! A background task connects to the target every interval and closes the
+//! connection straight away. After `health_check_failures` failed checks in
+//! a row the target is down; the next successful check brings it back up.
+//! The terminator stops accepting AO sessions while the target is down.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::config::HealthCheck;
+use crate::plain::PlainEndpoint;
+
+/// Whether `forward_plain` is up, kept current by the checker task. The
+/// task stops when the monitor is dropped.
+#[derive(Debug)]
+pub struct UpstreamMonitor {
+    endpoint: PlainEndpoint,
+    state: watch::Receiver<bool>,
+    task: Option<JoinHandle<()>>,
+    // Keeps `state` open when no checker owns the sender.
+    _idle: Option<watch::Sender<bool>>,
+}
+
+impl Drop for UpstreamMonitor {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+impl UpstreamMonitor {
+    /// Starts checking `endpoint`, or returns a monitor that always reports
+    /// up when checking is off.
+    pub fn spawn(endpoint: PlainEndpoint, cfg: &HealthCheck) -> Self {
+        let (tx, state) = watch::channel(true);
+        let Some(interval) = cfg.interval() else {
+            return Self {
+                endpoint,
+                state,
+                task: None,
+                _idle: Some(tx),
+            };
+        };
+
+        info!(
+            target = %endpoint,
+            interval_secs = interval.as_secs(),
+            failures = cfg.health_check_failures,
+            "checking forward_plain health"
+        );
+        let task = tokio::spawn(check_loop(
+            endpoint.clone(),
+            interval,
+            cfg.timeout(),
+            cfg.health_check_failures,
+            tx,
+        ));
+        Self {
+            endpoint,
+            state,
+            task: Some(task),
+            _idle: None,
+        }
+    }
+
+    pub fn is_up(&self) -> bool {
+        *self.state.borrow()
+    }
+
+    /// The checker's latest verdict for readiness reporting, or `None` when
+    /// checking is off.
+    pub fn status(&self) -> Option<UpstreamStatus> {
+        self.task.as_ref().map(|_| UpstreamStatus {
+            endpoint: self.endpoint.clone(),
+            state: self.state.clone(),
+        })
+    }
+
+    /// Resolves when the target goes down or comes back up.
+    pub async fn changed(&mut self) -> bool {
+        match self.state.changed().await {
+            Ok(()) => *self.state.borrow_and_update(),
+            // The checker only stops when the monitor is dropped.
+            Err(_) => std::future::pending().await,
+        }
+    }
+}
+
+/// A read-only view of an [`UpstreamMonitor`]'s state.
+#[derive(Debug, Clone)]
+pub struct UpstreamStatus {
+    endpoint: PlainEndpoint,
+    state: watch::Receiver<bool>,
+}
+
+impl UpstreamStatus {
+    #[cfg(test)]
+    pub(crate) fn new(endpoint: PlainEndpoint, state: watch::Receiver<bool>) -> Self {
+        Self { endpoint, state }
+    }
+
+    pub fn endpoint(&self) -> &PlainEndpoint {
+        &self.endpoint
+    }
+
+    pub fn is_up(&self) -> bool {
+        *self.state.borrow()
+    }
+}
+
+async fn check_loop(
+    endpoint: PlainEndpoint,
+    interval: Duration,
+    timeout: Duration,
+    failures: u32,
+    state: watch::Sender<bool>,
+) {
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut failed = 0_u32;
+
+    loop {
+        tick.tick().await;
+        let error = match tokio::time::timeout(timeout, endpoint.connect()).await {
+            Ok(Ok(_)) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some("connect timed out".to_string()),
+        };
+
+        match error {
+            None => {
+                failed = 0;
+                if !*state.borrow() {
+                    info!(target = %endpoint, "forward_plain is back up; accepting ao sessions again");
+                    state.send_replace(true);
+                }
+            }
+            Some(error) => {
+                failed = failed.saturating_add(1);
+                debug!(target = %endpoint, failed, error = %error, "forward_plain check failed");
+                if failed >= failures && *state.borrow() {
+                    warn!(
+                        target = %endpoint,
+                        failed,
+                        error = %error,
+                        "forward_plain is down; pausing ao accepts"
+                    );
+                    state.send_replace(false);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn goes_down_after_repeated_failures_and_back_up_on_success() {
+        let target = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = target.local_addr().expect("addr");
+        drop(target);
+
+        let cfg = HealthCheck {
+            health_check_interval_secs: Some(1),
+            health_check_timeout_secs: 1,
+            health_check_failures: 2,
+        };
+        let mut monitor = UpstreamMonitor::spawn(PlainEndpoint::Tcp(addr), &cfg);
+        assert!(monitor.is_up());
+
+        let down = tokio::time::timeout(Duration::from_secs(5), monitor.changed())
+            .await
+            .expect("went down");
+        assert!(!down);
+
+        let _target = TcpListener::bind(addr).await.expect("rebind");
+        let up = tokio::time::timeout(Duration::from_secs(5), monitor.changed())
+            .await
+            .expect("came back");
+        assert!(up);
+        assert!(monitor.is_up());
+    }
+
+    #[tokio::test]
+    async fn without_an_interval_the_target_is_always_up() {
+        let mut monitor = UpstreamMonitor::spawn(
+            PlainEndpoint::Tcp("127.0.0.1:9".parse().expect("addr")),
+            &HealthCheck::default(),
+        );
+        assert!(monitor.is_up());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(100), monitor.changed())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,350 @@
+//! HashiCorp Vault-compatible KV key source.
+//!
+//! Keys are read over HTTP(S) with a static token or AppRole login and cached
+//! in memory for `cache_ttl_secs`, or for the secret's `lease_duration` when
+//! that is shorter. The key watcher re-reads each secret as its cached copy
+//! expires, so rotated secrets reach `install_key` without a restart. Reads
+//! block; async callers run them on the blocking pool.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+use serde_json::Value;
+use tracing::{debug, warn};
+use zeroize::Zeroizing;
+
+use crate::config::{KeyFailureMode, VaultConfig};
+use crate::error::{ProxyError, Result};
+use crate::http::{self, Url};
+use crate::secret::KeyMaterial;
+
+static CLIENT: RwLock<Option<Arc<VaultClient>>> = RwLock::new(None);
+
+/// Installs the process-wide client used by `vault:` key sources. Passing
+/// `None` removes any previously installed client.
+pub fn install(cfg: Option<&VaultConfig>) -> Result<()> {
+    let client = cfg.map(VaultClient::new).transpose()?.map(Arc::new);
+    *CLIENT.write().unwrap_or_else(|e| e.into_inner()) = client;
+    Ok(())
+}
+
+pub fn read_key(path: &str, field: &str) -> Result<KeyMaterial> {
+    let client = CLIENT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+        .ok_or_else(|| {
+            ProxyError::Config("vault key source used without a [vault] section".to_string())
+        })?;
+    client.read_key(path, field)
+}
+
+/// How long until the cached copy of `path#field` expires, or the cache TTL
+/// when nothing is cached. `None` without a `[vault]` section.
+pub fn refresh_in(path: &str, field: &str) -> Option<Duration> {
+    let client = CLIENT.read().unwrap_or_else(|e| e.into_inner()).clone()?;
+    let cache = client.cache.lock().unwrap_or_else(|e| e.into_inner());
+    let ttl = Duration::from_secs(client.cfg.cache_ttl_secs);
+    Some(
+        cache
+            .get(&(path.to_string(), field.to_string()))
+            .map_or(ttl, |cached| {
+                cached.ttl.saturating_sub(cached.fetched.elapsed())
+            }),
+    )
+}
+
+/// Whether an unreadable `vault:` key must be removed rather than kept.
+pub fn fails_closed() -> bool {
+    CLIENT
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .as_ref()
+        .map(|client| client.cfg.on_failure == KeyFailureMode::FailClosed)
+        .unwrap_or(true)
+}
+
+struct CachedKey {
+    value: KeyMaterial,
+    fetched: Instant,
+    ttl: Duration,
+}
+
+pub struct VaultClient {
+    cfg: VaultConfig,
+    base: Url,
+    trust: http::Trust,
+    token: Mutex<Option<Zeroizing<String>>>,
+    cache: Mutex<HashMap<(String, String), CachedKey>>,
+}
+
+impl VaultClient {
+    pub fn new(cfg: &VaultConfig) -> Result<Self> {
+        let base = Url::parse(&cfg.address)
+            .map_err(|e| ProxyError::Config(format!("invalid vault address: {e}")))?;
+        let trust = http::Trust::new(cfg.ca_cert.as_deref())
+            .map_err(|e| ProxyError::Config(format!("invalid vault ca_cert: {e}")))?;
+        Ok(Self {
+            cfg: cfg.clone(),
+            base,
+            trust,
+            token: Mutex::new(None),
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn read_key(&self, path: &str, field: &str) -> Result<KeyMaterial> {
+        let cache_key = (path.to_string(), field.to_string());
+        let ttl = Duration::from_secs(self.cfg.cache_ttl_secs);
+
+        {
+            let cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(cached) = cache.get(&cache_key) {
+                if cached.fetched.elapsed() < cached.ttl {
+                    return Ok(cached.value.clone());
+                }
+            }
+        }
+
+        match self.fetch(path, field) {
+            Ok((value, lease)) => {
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                cache.insert(
+                    cache_key,
+                    CachedKey {
+                        value: value.clone(),
+                        fetched: Instant::now(),
+                        ttl: lease.map_or(ttl, |lease| lease.min(ttl)),
+                    },
+                );
+                Ok(value)
+            }
+            Err(err) => {
+                let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+                match self.cfg.on_failure {
+                    KeyFailureMode::LastKnownGood => {
+                        if let Some(cached) = cache.get(&cache_key) {
+                            warn!(
+                                path,
+                                error = %err,
+                                age_secs = cached.fetched.elapsed().as_secs(),
+                                "vault refresh failed; serving last known good key"
+                            );
+                            return Ok(cached.value.clone());
+                        }
+                    }
+                    KeyFailureMode::FailClosed => {
+                        cache.remove(&cache_key);
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The key and the secret's lease, when the server sets one.
+    fn fetch(&self, path: &str, field: &str) -> Result<(KeyMaterial, Option<Duration>)> {
+        let url = self
+            .base
+            .join(&format!("v1/{}", path.trim_start_matches('/')));
+
+        let mut retried = false;
+        loop {
+            let token = self.token()?;
+            let resp = self.send("GET", &url, Some(token.as_str()), None)?;
+
+            // A 403 with AppRole usually means the client token expired; log in
+            // again once before giving up.
+            if resp.status == 403 && !retried && self.cfg.role_id.is_some() {
+                *self.token.lock().unwrap_or_else(|e| e.into_inner()) = None;
+                retried = true;
+                continue;
+            }
+
+            if resp.status != 200 {
+                return Err(ProxyError::Config(format!(
+                    "vault read of '{path}' failed with http {}",
+                    resp.status
+                )));
+            }
+
+            debug!(path, "fetched key from vault");
+            return extract_field(&resp.body, field)
+                .map(|key| (key, lease_duration(&resp.body)))
+                .map_err(|e| ProxyError::Config(format!("vault secret '{path}': {e}")));
+        }
+    }
+
+    fn token(&self) -> Result<Zeroizing<String>> {
+        if let Some(source) = &self.cfg.token_source {
+            return secret_string(&source.load_key()?, "vault token");
+        }
+
+        let mut cached = self.token.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(token) = cached.as_ref() {
+            return Ok(token.clone());
+        }
+
+        let token = self.login_approle()?;
+        *cached = Some(token.clone());
+        Ok(token)
+    }
+
+    fn login_approle(&self) -> Result<Zeroizing<String>> {
+        let role_id = self.cfg.role_id.as_deref().ok_or_else(|| {
+            ProxyError::Config("vault requires token_source or role_id".to_string())
+        })?;
+        let secret_id = match &self.cfg.secret_id_source {
+            Some(source) => secret_string(&source.load_key()?, "vault secret_id")?,
+            None => Zeroizing::default(),
+        };
+
+        let body = Zeroizing::new(
+            serde_json::to_string(&Login {
+                role_id,
+                secret_id: &secret_id,
+            })
+            .map_err(|e| ProxyError::Config(format!("vault login request: {e}")))?,
+        );
+        let url = self
+            .base
+            .join(&format!("v1/auth/{}/login", self.cfg.approle_mount));
+        let resp = self.send("POST", &url, None, Some(body.as_bytes()))?;
+        if resp.status != 200 {
+            return Err(ProxyError::Config(format!(
+                "vault approle login failed with http {}",
+                resp.status
+            )));
+        }
+
+        client_token(&resp.body)
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        url: &Url,
+        token: Option<&str>,
+        body: Option<&[u8]>,
+    ) -> Result<http::Response> {
+        let mut headers = Vec::new();
+        if let Some(token) = token {
+            headers.push(("X-Vault-Token", token));
+        }
+        if let Some(namespace) = &self.cfg.namespace {
+            headers.push(("X-Vault-Namespace", namespace.as_str()));
+        }
+        if body.is_some() {
+            headers.push(("Content-Type", "application/json"));
+        }
+
+        http::request(
+            method,
+            url,
+            &headers,
+            body,
+            Duration::from_secs(self.cfg.timeout_secs),
+            &self.trust,
+        )
+        .map_err(|e| ProxyError::Config(format!("vault request failed: {e}")))
+    }
+}
+
+#[derive(Serialize)]
+struct Login<'a> {
+    role_id: &'a str,
+    secret_id: &'a str,
+}
+
+/// A token or secret_id loaded from a key source, trimmed.
+fn secret_string(raw: &KeyMaterial, what: &str) -> Result<Zeroizing<String>> {
+    std::str::from_utf8(raw.as_bytes())
+        .map(|s| Zeroizing::new(s.trim().to_string()))
+        .map_err(|_| ProxyError::Config(format!("{what} is not valid utf-8")))
+}
+
+/// `auth.client_token` of a login response, moved out of the parsed body so
+/// the only copy is wiped on drop.
+fn client_token(body: &[u8]) -> Result<Zeroizing<String>> {
+    let mut parsed: Value = serde_json::from_slice(body)
+        .map_err(|e| ProxyError::Config(format!("vault login response: {e}")))?;
+    match parsed.pointer_mut("/auth/client_token").map(Value::take) {
+        Some(Value::String(token)) => Ok(Zeroizing::new(token)),
+        _ => Err(ProxyError::Config(
+            "vault login response has no client_token".to_string(),
+        )),
+    }
+}
+
+/// `lease_duration` of a read response; KV v2 reports 0, meaning none.
+fn lease_duration(body: &[u8]) -> Option<Duration> {
+    let parsed: Value = serde_json::from_slice(body).ok()?;
+    parsed
+        .get("lease_duration")
+        .and_then(Value::as_u64)
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+}
+
+/// Extracts `field` from a KV v2 (`data.data`) or KV v1 (`data`) response.
+fn extract_field(body: &[u8], field: &str) -> std::result::Result<KeyMaterial, String> {
+    let mut parsed: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    let data = if parsed.pointer("/data/data").is_some_and(Value::is_object) {
+        parsed.pointer_mut("/data/data")
+    } else {
+        parsed.get_mut("data")
+    }
+    .ok_or_else(|| "response has no data".to_string())?;
+
+    match data.get_mut(field).map(Value::take) {
+        Some(Value::String(value)) if !value.is_empty() => Ok(KeyMaterial::new(value.into_bytes())),
+        Some(Value::String(_)) => Err(format!("field '{field}' is empty")),
+        _ => Err(format!("field '{field}' missing or not a string")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_field_reads_kv_v2_payload() {
+        let body = br#"{"data":{"data":{"key":"v2-key"},"metadata":{"version":3}}}"#;
+        assert_eq!(
+            extract_field(body, "key").expect("field").as_bytes(),
+            b"v2-key"
+        );
+    }
+
+    #[test]
+    fn extract_field_reads_kv_v1_payload() {
+        let body = br#"{"data":{"key":"v1-key"},"lease_duration":60}"#;
+        assert_eq!(
+            extract_field(body, "key").expect("field").as_bytes(),
+            b"v1-key"
+        );
+        assert_eq!(lease_duration(body), Some(Duration::from_secs(60)));
+        assert_eq!(lease_duration(br#"{"lease_duration":0}"#), None);
+    }
+
+    #[test]
+    fn login_token_is_taken_from_the_auth_block() {
+        let body = br#"{"auth":{"client_token":"s.abc","lease_duration":3600}}"#;
+        assert_eq!(client_token(body).expect("token").as_str(), "s.abc");
+        assert!(client_token(br#"{"auth":{}}"#).is_err());
+        assert_eq!(
+            secret_string(&KeyMaterial::from_slice(b" s.def\n"), "token")
+                .expect("utf-8")
+                .as_str(),
+            "s.def"
+        );
+    }
+
+    #[test]
+    fn extract_field_rejects_missing_field() {
+        let body = br#"{"data":{"data":{"other":"x"}}}"#;
+        assert!(extract_field(body, "key").is_err());
+    }
+}
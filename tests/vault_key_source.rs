@@ -0,0 +1,221 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use tcpao_proxy::config::{KeyFailureMode, KeySource, VaultConfig};
+use tcpao_proxy::vault::VaultClient;
+
+const TEST_TOKEN_ENV: &str = "TCPAO_TEST_VAULT_TOKEN";
+
+#[derive(Default)]
+struct MockState {
+    key: String,
+    fail: bool,
+    reads: usize,
+    logins: usize,
+}
+
+/// Serves just enough of the Vault HTTP API for the key source: AppRole login
+/// and a KV v2 read of `secret/data/bmp`.
+fn spawn_mock_vault(state: Arc<Mutex<MockState>>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock vault");
+    let addr = listener.local_addr().expect("mock vault addr");
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { return };
+            let mut reader = BufReader::new(stream.try_clone().expect("clone stream"));
+
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+
+            let mut content_length = 0;
+            let mut token = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("header line");
+                let line = line.trim_end();
+                if line.is_empty() {
+                    break;
+                }
+                let (name, value) = line.split_once(':').expect("header");
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().expect("length");
+                }
+                if name.eq_ignore_ascii_case("x-vault-token") {
+                    token = Some(value.trim().to_string());
+                }
+            }
+            let mut body = vec![0_u8; content_length];
+            reader.read_exact(&mut body).expect("body");
+
+            let mut state = state.lock().expect("state lock");
+            let (status, payload) = if request_line.starts_with("POST /v1/auth/approle/login") {
+                state.logins += 1;
+                (
+                    200,
+                    r#"{"auth":{"client_token":"approle-token","lease_duration":60}}"#.to_string(),
+                )
+            } else if request_line.starts_with("GET /v1/secret/data/bmp") {
+                state.reads += 1;
+                if state.fail {
+                    (500, r#"{"errors":["sealed"]}"#.to_string())
+                } else if token.is_none() {
+                    (403, r#"{"errors":["permission denied"]}"#.to_string())
+                } else {
+                    (
+                        200,
+                        format!(
+                            r#"{{"data":{{"data":{{"key":"{}"}},"metadata":{{}}}}}}"#,
+                            state.key
+                        ),
+                    )
+                }
+            } else {
+                (404, "{}".to_string())
+            };
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{payload}",
+                payload.len()
+            );
+        }
+    });
+
+    addr
+}
+
+fn vault_config(addr: SocketAddr, on_failure: KeyFailureMode) -> VaultConfig {
+    VaultConfig {
+        address: format!("http://{addr}"),
+        ca_cert: None,
+        token_source: None,
+        role_id: Some("proxy-role".to_string()),
+        secret_id_source: Some(KeySource("exec:/bin/echo proxy-secret".to_string())),
+        approle_mount: "approle".to_string(),
+        namespace: None,
+        cache_ttl_secs: 0,
+        timeout_secs: 2,
+        on_failure,
+    }
+}
+
+#[test]
+fn approle_login_and_kv_read_return_rotated_key() {
+    let state = Arc::new(Mutex::new(MockState {
+        key: "first-key".to_string(),
+        ..MockState::default()
+    }));
+    let addr = spawn_mock_vault(Arc::clone(&state));
+    let client = VaultClient::new(&vault_config(addr, KeyFailureMode::FailClosed)).expect("client");
+
+    assert_eq!(
+        client
+            .read_key("secret/data/bmp", "key")
+            .expect("key")
+            .as_bytes(),
+        b"first-key"
+    );
+
+    state.lock().expect("state lock").key = "second-key".to_string();
+    assert_eq!(
+        client
+            .read_key("secret/data/bmp", "key")
+            .expect("key")
+            .as_bytes(),
+        b"second-key"
+    );
+
+    let state = state.lock().expect("state lock");
+    assert_eq!(state.logins, 1, "client token must be reused");
+    assert_eq!(state.reads, 2);
+}
+
+#[test]
+fn cached_key_is_served_within_ttl() {
+    let state = Arc::new(Mutex::new(MockState {
+        key: "cached-key".to_string(),
+        ..MockState::default()
+    }));
+    let addr = spawn_mock_vault(Arc::clone(&state));
+    let mut cfg = vault_config(addr, KeyFailureMode::FailClosed);
+    cfg.cache_ttl_secs = 300;
+    let client = VaultClient::new(&cfg).expect("client");
+
+    for _ in 0..3 {
+        assert_eq!(
+            client
+                .read_key("secret/data/bmp", "key")
+                .expect("key")
+                .as_bytes(),
+            b"cached-key"
+        );
+    }
+    assert_eq!(state.lock().expect("state lock").reads, 1);
+}
+
+#[test]
+fn last_known_good_survives_vault_outage() {
+    let state = Arc::new(Mutex::new(MockState {
+        key: "good-key".to_string(),
+        ..MockState::default()
+    }));
+    let addr = spawn_mock_vault(Arc::clone(&state));
+    let client =
+        VaultClient::new(&vault_config(addr, KeyFailureMode::LastKnownGood)).expect("client");
+
+    assert_eq!(
+        client
+            .read_key("secret/data/bmp", "key")
+            .expect("key")
+            .as_bytes(),
+        b"good-key"
+    );
+    state.lock().expect("state lock").fail = true;
+    assert_eq!(
+        client
+            .read_key("secret/data/bmp", "key")
+            .expect("stale key")
+            .as_bytes(),
+        b"good-key"
+    );
+}
+
+#[test]
+fn fail_closed_rejects_key_during_vault_outage() {
+    let state = Arc::new(Mutex::new(MockState {
+        key: "good-key".to_string(),
+        ..MockState::default()
+    }));
+    let addr = spawn_mock_vault(Arc::clone(&state));
+    let client = VaultClient::new(&vault_config(addr, KeyFailureMode::FailClosed)).expect("client");
+
+    assert!(client.read_key("secret/data/bmp", "key").is_ok());
+    state.lock().expect("state lock").fail = true;
+    assert!(client.read_key("secret/data/bmp", "key").is_err());
+}
+
+#[test]
+fn static_token_key_source_reads_through_installed_client() {
+    let state = Arc::new(Mutex::new(MockState {
+        key: "token-key".to_string(),
+        ..MockState::default()
+    }));
+    let addr = spawn_mock_vault(Arc::clone(&state));
+    std::env::set_var(TEST_TOKEN_ENV, "static-token");
+
+    let mut cfg = vault_config(addr, KeyFailureMode::FailClosed);
+    cfg.role_id = None;
+    cfg.secret_id_source = None;
+    cfg.token_source = Some(KeySource(format!("env:{TEST_TOKEN_ENV}")));
+    cfg.validate().expect("valid vault config");
+    tcpao_proxy::vault::install(Some(&cfg)).expect("install vault client");
+
+    let source = KeySource("vault:secret/data/bmp#key".to_string());
+    assert_eq!(source.load_key().expect("key").as_bytes(), b"token-key");
+    assert_eq!(state.lock().expect("state lock").logins, 0);
+}